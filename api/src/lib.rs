@@ -4,7 +4,8 @@ ffi_gen_macro::ffi_gen!("api/tlfs.rsh");
 
 use anyhow::Result;
 use futures::{Stream, StreamExt};
-use tlfs::Permission;
+use std::time::Duration;
+use tlfs::{GroupId, Permission};
 use tlfs_crdt::ArchivedSchema;
 
 pub struct Sdk(tlfs::Sdk);
@@ -21,6 +22,102 @@ pub async fn create_memory(package: Vec<u8>) -> Result<Sdk> {
     Ok(Sdk(tlfs::Sdk::memory(&package).await?))
 }
 
+pub struct SdkBuilder {
+    package: Vec<u8>,
+    path: Option<String>,
+    network: tlfs::NetworkConfig,
+}
+
+impl SdkBuilder {
+    pub fn create(package: Vec<u8>) -> Self {
+        Self {
+            package,
+            path: None,
+            network: Default::default(),
+        }
+    }
+
+    pub fn persistent(&mut self, path: String) {
+        self.path = Some(path);
+    }
+
+    pub fn clear_listen_addrs(&mut self) {
+        self.network.listen_on.clear();
+    }
+
+    pub fn add_listen_addr(&mut self, addr: &str) -> Result<()> {
+        self.network.listen_on.push(addr.parse()?);
+        Ok(())
+    }
+
+    pub fn clear_signaling_servers(&mut self) {
+        self.network.signaling_servers.clear();
+    }
+
+    pub fn add_signaling_server(&mut self, addr: &str) -> Result<()> {
+        self.network.signaling_servers.push(addr.parse()?);
+        Ok(())
+    }
+
+    pub fn clear_ice_servers(&mut self) {
+        self.network.ice_servers.clear();
+    }
+
+    pub fn add_ice_server(&mut self, url: &str) {
+        self.network.ice_servers.push(url.into());
+    }
+
+    pub fn clear_relays(&mut self) {
+        self.network.relays.clear();
+    }
+
+    pub fn add_relay(&mut self, addr: &str) -> Result<()> {
+        self.network.relays.push(addr.parse()?);
+        Ok(())
+    }
+
+    pub fn clear_rendezvous_servers(&mut self) {
+        self.network.rendezvous_servers.clear();
+    }
+
+    pub fn add_rendezvous_server(&mut self, addr: &str) -> Result<()> {
+        self.network.rendezvous_servers.push(addr.parse()?);
+        Ok(())
+    }
+
+    pub fn dns_resolver(&mut self, resolver: u8) -> Result<()> {
+        self.network.dns = match resolver {
+            0 => tlfs::DnsResolver::System,
+            1 => tlfs::DnsResolver::Cloudflare,
+            2 => tlfs::DnsResolver::Google,
+            3 => tlfs::DnsResolver::Quad9,
+            _ => anyhow::bail!("invalid dns resolver"),
+        };
+        Ok(())
+    }
+
+    pub fn mdns(&mut self, enabled: bool) {
+        self.network.mdns = enabled;
+    }
+
+    pub fn ping(&mut self, interval: u64, timeout: u64) {
+        self.network.ping_interval = Duration::from_millis(interval);
+        self.network.ping_timeout = Duration::from_millis(timeout);
+    }
+
+    pub async fn build(&self) -> Result<Sdk> {
+        let builder = tlfs::SdkBuilder::new(&self.package).network(self.network.clone());
+        let builder = match &self.path {
+            #[cfg(target_family = "wasm")]
+            Some(path) => builder.browser(path),
+            #[cfg(not(target_family = "wasm"))]
+            Some(path) => builder.filesystem(std::path::Path::new(path)),
+            None => builder,
+        };
+        Ok(Sdk(builder.build().await?))
+    }
+}
+
 impl Sdk {
     pub fn get_peer_id(&self) -> String {
         self.0.peer_id().to_string()
@@ -75,6 +172,14 @@ impl Sdk {
         self.0.subscribe_connected_peers().map(|_| 0)
     }
 
+    pub async fn install_package(&self, package: Vec<u8>) -> Result<()> {
+        self.0.install_package(&package).await
+    }
+
+    pub fn trust_publisher(&self, name: &str, publisher: &str) -> Result<()> {
+        self.0.trust_publisher(name, publisher.parse()?)
+    }
+
     pub fn docs(&self, schema: String) -> Result<Vec<String>> {
         self.0.docs(schema).map(|id| Ok(id?.to_string())).collect()
     }
@@ -91,6 +196,14 @@ impl Sdk {
         Ok(Doc(self.0.doc(doc_id.parse()?)?))
     }
 
+    pub fn open_doc_at(&self, doc_id: &str, version: u32) -> Result<Doc> {
+        Ok(Doc(self.0.doc_at(doc_id.parse()?, version)?))
+    }
+
+    pub fn migrate_doc(&self, doc_id: &str, version: u32) -> Result<()> {
+        self.0.migrate_doc(&doc_id.parse()?, version)
+    }
+
     pub fn add_doc(&self, doc_id: &str, schema: &str) -> Result<Doc> {
         Ok(Doc(self.0.add_doc(doc_id.parse()?, schema)?))
     }
@@ -111,6 +224,39 @@ impl Sdk {
     pub fn subscribe_invites(&self) -> impl Stream<Item = i32> {
         self.0.subscribe_invites().map(|_| 0)
     }
+
+    pub fn redeem_invite(&self, link: &str) -> Result<Doc> {
+        Ok(Doc(self.0.redeem_invite(&link.parse()?)?))
+    }
+
+    pub async fn pairing_link(&self) -> Result<String> {
+        Ok(self.0.pairing_link().await?.to_string())
+    }
+
+    pub fn pair(&self, link: &str) -> Result<()> {
+        self.0.pair(&link.parse()?)
+    }
+
+    pub fn devices(&self) -> Result<Vec<String>> {
+        Ok(self
+            .0
+            .devices()?
+            .into_iter()
+            .map(|device| device.to_string())
+            .collect())
+    }
+
+    pub fn unlink_device(&self, device: &str) -> Result<()> {
+        self.0.unlink_device(&device.parse()?)
+    }
+
+    pub fn rotate_keypair(&self) -> Result<String> {
+        Ok(self.0.rotate_keypair()?.to_string())
+    }
+
+    pub fn quarantine_peer(&self, doc: &str, peer: &str) -> Result<()> {
+        self.0.quarantine_peer(&doc.parse()?, &peer.parse()?, None)
+    }
 }
 
 pub struct Doc(tlfs::Doc);
@@ -131,6 +277,70 @@ impl Doc {
     pub fn invite_peer(&self, peer: String) -> Result<()> {
         self.0.invite(peer.parse()?)
     }
+
+    pub async fn invite_link(&self, token: Box<Token>) -> Result<String> {
+        Ok(self.0.invite_link(token.1).await?.to_string())
+    }
+
+    pub async fn sync_status(&self) -> SyncStatus {
+        SyncStatus(self.0.sync_status().await)
+    }
+
+    pub fn subscribe_sync_status(&self) -> impl Stream<Item = i32> {
+        self.0.subscribe_sync_status().map(|_| 0)
+    }
+}
+
+pub struct SyncStatus(tlfs::SyncStatus);
+
+impl SyncStatus {
+    pub fn pending(&self) -> u64 {
+        self.0.pending
+    }
+
+    pub fn peers(&self) -> Vec<PeerSyncStatus> {
+        self.0
+            .peers
+            .iter()
+            .map(|(peer, status)| PeerSyncStatus(*peer, status.clone()))
+            .collect()
+    }
+}
+
+pub struct PeerSyncStatus(tlfs::PeerId, tlfs::PeerSyncStatus);
+
+impl PeerSyncStatus {
+    pub fn peer_id(&self) -> String {
+        self.0.to_string()
+    }
+
+    pub fn last_sync(&self) -> Option<u64> {
+        self.1.last_sync.map(|time| time.millis())
+    }
+
+    pub fn in_sync(&self) -> bool {
+        self.1.in_sync
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.1.bytes_sent
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.1.bytes_received
+    }
+
+    pub fn outstanding(&self) -> u64 {
+        self.1.outstanding
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.1.errors
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.1.last_error.clone()
+    }
 }
 
 #[derive(Clone)]
@@ -313,12 +523,24 @@ impl<'a> Cursor<'a> {
         self.0.can(&peer_id.parse()?, perm)
     }
 
+    pub fn explain(&self, peer_id: &str, perm: u8) -> Result<String> {
+        let perm = parse_perm(perm)?;
+        Ok(self.0.explain(&peer_id.parse()?, perm)?.to_string())
+    }
+
     pub fn say_can(&self, actor: Option<String>, perm: u8) -> Result<Causal> {
         let actor = actor.map(|s| s.parse()).transpose()?;
         let perm = parse_perm(perm)?;
         Ok(Causal(self.0.say_can(actor, perm)?))
     }
 
+    pub fn say_can_until(&self, actor: Option<String>, perm: u8, expiry: u64) -> Result<Causal> {
+        let actor = actor.map(|s| s.parse()).transpose()?;
+        let perm = parse_perm(perm)?;
+        let expiry = tlfs::Timestamp::new(expiry, 0);
+        Ok(Causal(self.0.say_can_until(actor, perm, expiry)?))
+    }
+
     pub fn cond(&self, actor: Box<Actor>, perm: u8) -> Result<Can> {
         let perm = parse_perm(perm)?;
         Ok(Can(self.0.cond(actor.0, perm)))
@@ -329,7 +551,52 @@ impl<'a> Cursor<'a> {
         Ok(Causal(self.0.say_can_if(actor.0, perm, cond.0)?))
     }
 
-    // TODO: revoke
+    pub fn revoke(&self, dot: &str) -> Result<Causal> {
+        Ok(Causal(self.0.revoke(dot.parse()?)?))
+    }
+
+    pub fn encrypt(&self) -> Result<Causal> {
+        Ok(Causal(self.0.encrypt()?))
+    }
+
+    pub fn policies(&self) -> Vec<Statement> {
+        self.0.policies().into_iter().map(Statement).collect()
+    }
+
+    pub fn say_creator_can(&self, perm: u8) -> Result<Causal> {
+        let perm = parse_perm(perm)?;
+        Ok(Causal(self.0.say_creator_can(perm)?))
+    }
+
+    pub fn say_can_group(&self, group: &str, perm: u8) -> Result<Causal> {
+        let group = GroupId::from_name(group);
+        let perm = parse_perm(perm)?;
+        Ok(Causal(self.0.say_can_group(group, perm)?))
+    }
+
+    pub fn add_member(&self, group: &str, peer_id: &str) -> Result<Causal> {
+        let group = GroupId::from_name(group);
+        Ok(Causal(self.0.add_member(group, peer_id.parse()?)?))
+    }
+
+    pub fn remove_member(&self, group: &str, peer_id: &str) -> Result<Causal> {
+        let group = GroupId::from_name(group);
+        Ok(Causal(self.0.remove_member(group, peer_id.parse()?)?))
+    }
+
+    pub fn members(&self, group: &str) -> Vec<String> {
+        self.0
+            .members(GroupId::from_name(group))
+            .into_iter()
+            .map(|peer| peer.to_string())
+            .collect()
+    }
+
+    pub fn say_token(&self, perm: u8, uses: u32) -> Result<Token> {
+        let perm = parse_perm(perm)?;
+        let (causal, capability) = self.0.say_token(perm, uses)?;
+        Ok(Token(causal, capability))
+    }
 
     pub fn subscribe(&self) -> impl Stream<Item = i32> {
         self.0.subscribe().map(|_batch| 0)
@@ -346,16 +613,98 @@ impl Causal {
 
 pub struct Can(tlfs::Can);
 
+pub struct Token(tlfs::Causal, tlfs::Capability);
+
+impl Token {
+    pub fn causal(&self) -> Causal {
+        Causal(self.0.clone())
+    }
+}
+
 fn parse_perm(perm: u8) -> Result<Permission> {
     Ok(match perm {
         0 => Permission::Read,
         1 => Permission::Write,
         2 => Permission::Control,
         3 => Permission::Own,
+        4 => Permission::Append,
         _ => anyhow::bail!("invalid permission"),
     })
 }
 
+fn perm_to_u8(perm: Permission) -> u8 {
+    match perm {
+        Permission::Read => 0,
+        Permission::Write => 1,
+        Permission::Control => 2,
+        Permission::Own => 3,
+        Permission::Append => 4,
+    }
+}
+
+fn actor_to_string(actor: tlfs::Actor) -> String {
+    match actor {
+        tlfs::Actor::Peer(peer) => peer.to_string(),
+        tlfs::Actor::Anonymous => "anonymous".into(),
+        tlfs::Actor::Unbound => "unbound".into(),
+        tlfs::Actor::Group(group) => format!("group:{}", group),
+        tlfs::Actor::Creator => "creator".into(),
+    }
+}
+
+pub struct Statement(tlfs::Statement);
+
+impl Statement {
+    pub fn id(&self) -> String {
+        self.0.id().to_string()
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self.0.kind() {
+            tlfs::StatementKind::Grant => 0,
+            tlfs::StatementKind::Token(_) => 1,
+            tlfs::StatementKind::Member(_) => 2,
+            tlfs::StatementKind::Device => 3,
+        }
+    }
+
+    pub fn issuer(&self) -> String {
+        self.0.issuer().to_string()
+    }
+
+    pub fn actor(&self) -> String {
+        actor_to_string(self.0.actor())
+    }
+
+    pub fn perm(&self) -> u8 {
+        perm_to_u8(self.0.perm())
+    }
+
+    pub fn path(&self) -> String {
+        self.0.path().to_string()
+    }
+
+    pub fn cond(&self) -> Option<String> {
+        self.0.cond().map(|cond| {
+            format!(
+                "{} can {} {}",
+                actor_to_string(cond.actor()),
+                perm_to_u8(cond.perm()),
+                cond.path()
+            )
+        })
+    }
+
+    pub fn status(&self) -> u8 {
+        match self.0.status() {
+            tlfs::PolicyStatus::Unauthorized => 0,
+            tlfs::PolicyStatus::Authorized => 1,
+            tlfs::PolicyStatus::Revoked => 2,
+            tlfs::PolicyStatus::Expired => 3,
+        }
+    }
+}
+
 pub struct Actor(tlfs::Actor);
 
 impl Actor {
@@ -370,4 +719,12 @@ impl Actor {
     pub fn unbound() -> Self {
         Self(tlfs::Actor::Unbound)
     }
+
+    pub fn creator() -> Self {
+        Self(tlfs::Actor::Creator)
+    }
+
+    pub fn group(name: &str) -> Self {
+        Self(tlfs::Actor::Group(GroupId::from_name(name)))
+    }
 }
@@ -29,6 +29,7 @@ use tracing::*;
 use tracing_subscriber::fmt;
 
 mod acme;
+mod replica;
 
 #[derive(Parser)]
 struct Opts {
@@ -50,6 +51,23 @@ struct Opts {
     tls_email: Option<String>,
     #[clap(long)]
     wss: bool,
+    #[clap(long)]
+    /// Path to the schema package of the replicated documents. Enables the replica mode, which
+    /// stores the documents and syncs them with the peers allowed to read them.
+    replica_package: Option<PathBuf>,
+    #[clap(long, default_value = "replica")]
+    /// Path to the database of the replica.
+    replica_db: PathBuf,
+    #[clap(long, default_value = "4003")]
+    /// TCP port the replica listens on.
+    replica_port: u16,
+    #[clap(long)]
+    /// Document to replicate, formatted as `<doc id>:<schema>`. Invited documents are
+    /// replicated as well.
+    replica_doc: Vec<String>,
+    #[clap(long, default_value = "100")]
+    /// Maximum number of documents of a schema the replica accepts invites to.
+    replica_max_docs: usize,
 }
 
 #[tokio::main]
@@ -69,7 +87,8 @@ async fn main() -> anyhow::Result<()> {
         p
     };
     let kp = identity::Keypair::Ed25519(private_key.into());
-    info!("Peer ID: {}", PeerId::from(kp.public()));
+    let peer_id = PeerId::from(kp.public());
+    info!("Peer ID: {}", peer_id);
 
     let tls = if opts.wss {
         if let (Some(email), Some(domain)) = (opts.tls_email, opts.tls_domain) {
@@ -115,8 +134,27 @@ async fn main() -> anyhow::Result<()> {
         swarm.listen_on(tcp)?;
         swarm.listen_on(ws)?;
     }
-    while let Some(event) = swarm.next().await {
-        debug!("Swarm {:?}", event);
+    let relay = async move {
+        while let Some(event) = swarm.next().await {
+            debug!("Swarm {:?}", event);
+        }
+    };
+    if let Some(package) = opts.replica_package {
+        let relay = format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer_id);
+        let replica = replica::run(
+            &opts.replica_db,
+            &package,
+            opts.replica_port,
+            &relay,
+            opts.replica_max_docs,
+            &opts.replica_doc,
+        );
+        tokio::select! {
+            _ = relay => {}
+            res = replica => res?,
+        }
+    } else {
+        relay.await;
     }
     Ok(())
 }
@@ -0,0 +1,169 @@
+use anyhow::Context;
+use libp2p::futures::StreamExt;
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+use tlfs::{DnsResolver, DocId, Invite, Multiaddr, Permission, Sdk, SdkBuilder};
+use tracing::*;
+
+/// Time after which an invited document the replica can't read is removed.
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Runs an always-on replica of documents. The replica is a regular `tlfs` peer persisting
+/// its state in the file system, so edits propagate between devices that are never online at
+/// the same time. Changes are only served to peers that are allowed to read them.
+///
+/// The replica syncs the configured `docs`, formatted as `<doc id>:<schema>`, and any
+/// document it is invited to and granted read permission in, up to `max_docs` documents per
+/// schema. It is discovered through the `relay` it runs next to and doesn't use any public
+/// infrastructure.
+pub(crate) async fn run(
+    db: &Path,
+    package: &Path,
+    port: u16,
+    relay: &str,
+    max_docs: usize,
+    docs: &[String],
+) -> anyhow::Result<()> {
+    let package = std::fs::read(package).context("Reading schema package")?;
+    let listen_on: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", port).parse()?;
+    let relay: Multiaddr = relay.parse()?;
+    let sdk = SdkBuilder::new(&package)
+        .filesystem(db)
+        .listen_on(vec![listen_on])
+        .signaling_servers(vec![])
+        .ice_servers(vec![])
+        .relays(vec![relay.clone()])
+        .rendezvous_servers(vec![relay])
+        .dns(DnsResolver::System)
+        .mdns(false)
+        .build()
+        .await
+        .context("Creating replica")?;
+    info!("Replica peer ID: {}", sdk.peer_id());
+
+    for doc in docs {
+        let (id, schema) = doc
+            .split_once(':')
+            .context("Documents must be formatted as `<doc id>:<schema>`")?;
+        let id: DocId = id.parse()?;
+        if sdk.doc(id).is_err() {
+            sdk.add_doc(id, schema)?;
+        }
+        info!("Replicating {}", id);
+    }
+
+    let mut invites = sdk.subscribe_invites();
+    let mut check = tokio::time::interval(Duration::from_secs(10));
+    // invited documents waiting for read permission
+    let mut unverified: Vec<(DocId, Instant)> = vec![];
+    loop {
+        tokio::select! {
+            Some(()) = invites.next() => {
+                for invite in sdk.invites().await {
+                    accept(&sdk, &invite, max_docs, &mut unverified);
+                }
+            }
+            _ = check.tick() => check_read(&sdk, &mut unverified, READ_TIMEOUT),
+        }
+    }
+}
+
+/// Adds an invited document unless `max_docs` documents of its schema are replicated.
+fn accept(sdk: &Sdk, invite: &Invite, max_docs: usize, unverified: &mut Vec<(DocId, Instant)>) {
+    if sdk.doc(invite.doc).is_ok() {
+        return;
+    }
+    let n = sdk.docs(invite.schema.clone()).count();
+    if n >= max_docs {
+        warn!(
+            "Ignoring invite to {}, {} documents of {} are replicated",
+            invite.doc, n, invite.schema
+        );
+        return;
+    }
+    match sdk.add_doc(invite.doc, &invite.schema) {
+        Ok(_) => unverified.push((invite.doc, Instant::now())),
+        Err(err) => error!("Adding {} failed: {}", invite.doc, err),
+    }
+}
+
+/// Removes the invited documents the replica wasn't granted read permission in within
+/// `timeout`, or whose permissions can't be checked.
+fn check_read(sdk: &Sdk, unverified: &mut Vec<(DocId, Instant)>, timeout: Duration) {
+    unverified.retain(|(id, invited)| {
+        let can_read = sdk
+            .doc(*id)
+            .and_then(|doc| doc.cursor().can(sdk.peer_id(), Permission::Read));
+        match can_read {
+            Ok(true) => {
+                info!("Replicating {}", id);
+                return false;
+            }
+            Ok(false) if invited.elapsed() < timeout => return true,
+            Ok(false) => info!("Removing {}, no read permission", id),
+            Err(err) => error!("Removing {}: {}", id, err),
+        }
+        if let Err(err) = sdk.remove_doc(id) {
+            error!("Removing {} failed: {}", id, err);
+        }
+        false
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tlfs::{Kind, Lens, Lenses, Package, PrimitiveKind, Ref};
+
+    async fn sdk() -> anyhow::Result<Sdk> {
+        let lenses = vec![
+            Lens::Make(Kind::Struct),
+            Lens::AddProperty("title".into()),
+            Lens::Make(Kind::Reg(PrimitiveKind::Str)).lens_in("title"),
+        ];
+        let packages = vec![Package::new("notes".into(), 3, &Lenses::new(lenses))];
+        let package = Ref::archive(&packages);
+        SdkBuilder::new(package.as_bytes()).offline().build().await
+    }
+
+    #[tokio::test]
+    async fn test_invites() -> anyhow::Result<()> {
+        let owner = sdk().await?;
+        let replica = sdk().await?;
+        let mut unverified = vec![];
+
+        let readable = owner.create_doc("notes").await?;
+        let op = readable
+            .cursor()
+            .say_can(Some(*replica.peer_id()), Permission::Read)?;
+        readable.apply(op)?;
+        let unreadable = owner.create_doc("notes").await?;
+        let ignored = owner.create_doc("notes").await?;
+        for doc in [&readable, &unreadable, &ignored] {
+            let invite = Invite {
+                doc: *doc.id(),
+                schema: "notes".into(),
+            };
+            accept(&replica, &invite, 2, &mut unverified);
+        }
+        // invites beyond the maximum number of documents are ignored
+        assert_eq!(unverified.len(), 2);
+        assert!(replica.doc(*ignored.id()).is_err());
+
+        let request = replica.sync_request(readable.id())?;
+        let response = owner.answer_sync(replica.peer_id(), &request).await?;
+        replica
+            .apply_sync(owner.peer_id(), readable.id(), &response)
+            .await?;
+
+        check_read(&replica, &mut unverified, READ_TIMEOUT);
+        assert_eq!(unverified, vec![(*unreadable.id(), unverified[0].1)]);
+        check_read(&replica, &mut unverified, Duration::from_secs(0));
+        assert!(unverified.is_empty());
+        assert!(replica.doc(*readable.id()).is_ok());
+        assert!(replica.doc(*unreadable.id()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::executor::block_on;
+use std::pin::Pin;
+use std::sync::Arc;
+use tlfs_crdt::{Backend, Doc, Keypair, MemStorage, PeerId, Permission, Ref};
+
+const DOCS: usize = 10;
+const STATEMENTS: u64 = 1000;
+
+const SCHEMA: &str = r#"todoapp {
+    0.1.0 {
+        .: Struct
+        .todos: Table<u64>
+        .todos.{}: Struct
+        .todos.{}.title: MVReg<String>
+    }
+}"#;
+
+fn peer(i: u64) -> PeerId {
+    let mut id = [0; 32];
+    id[..8].copy_from_slice(&i.to_be_bytes());
+    id[31] = 1;
+    PeerId::new(id)
+}
+
+fn grant(doc: &Doc, i: u64) -> anyhow::Result<()> {
+    let op = doc
+        .cursor()
+        .field("todos")?
+        .key_u64(i)?
+        .say_can(Some(peer(i)), Permission::Write)?;
+    doc.apply(&op)
+}
+
+fn setup(storage: Arc<MemStorage>, package: &[u8]) -> anyhow::Result<(Backend, Vec<Doc>)> {
+    let mut backend = Backend::new(storage, package)?;
+    let owner = backend.frontend().generate_keypair()?;
+    let mut docs = Vec::with_capacity(DOCS);
+    for _ in 0..DOCS {
+        let fut = backend
+            .frontend()
+            .create_doc(owner, "todoapp", Keypair::generate())?;
+        block_on(Pin::new(&mut backend))?;
+        let doc = block_on(fut);
+        for i in 0..STATEMENTS {
+            grant(&doc, i)?;
+            block_on(Pin::new(&mut backend))?;
+        }
+        docs.push(doc);
+    }
+    Ok((backend, docs))
+}
+
+fn bench_acl(c: &mut Criterion) {
+    let packages = tlfsc::compile_lenses(SCHEMA).unwrap();
+    let package = Ref::archive(&packages);
+    let storage = Arc::new(MemStorage::default());
+    let (mut backend, docs) = setup(storage, package.as_bytes()).unwrap();
+
+    let mut group = c.benchmark_group("acl");
+    group.sample_size(10);
+    let mut i = STATEMENTS;
+    group.bench_function("incremental", |b| {
+        b.iter(|| {
+            grant(&docs[0], i).unwrap();
+            block_on(Pin::new(&mut backend)).unwrap();
+            i += 1;
+        })
+    });
+    // evaluates the policies of all documents, as every change did before
+    group.bench_function("full", |b| {
+        b.iter(|| {
+            grant(&docs[0], i).unwrap();
+            block_on(Pin::new(&mut backend)).unwrap();
+            backend.reload_acl().unwrap();
+            i += 1;
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_acl);
+criterion_main!(benches);
@@ -1,48 +1,70 @@
+use crate::clock::{Clock, Timestamp};
+use crate::crdt::{author, DotStore};
+use crate::crypto::{Keypair, SealedKey};
+use crate::cursor::array_util::{ARRAY_META, ARRAY_VALUES};
 use crate::dotset::Dot;
-use crate::id::{DocId, PeerId};
+use crate::id::{DocId, GroupId, PeerId};
 use crate::path::{Path, PathBuf};
-use crate::radixdb::{BlobMap, Diff, Storage};
+use crate::radixdb::{BlobMap, BlobSet, Diff, Storage};
 use crate::util::Ref;
 use anyhow::Result;
 use bytecheck::CheckBytes;
 use crepe::crepe;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
 use futures::stream::BoxStream;
+use parking_lot::RwLock;
 use rkyv::{Archive, Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Permission type.
-#[derive(
-    Clone,
-    Copy,
-    Debug,
-    Eq,
-    Hash,
-    PartialEq,
-    Ord,
-    PartialOrd,
-    Archive,
-    CheckBytes,
-    Deserialize,
-    Serialize,
-)]
+///
+/// The discriminants are part of the archived format and of the ffi, so new permissions are
+/// appended. Permissions are ordered by what they imply instead:
+/// `Read < Append < Write < Control < Own`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Archive, CheckBytes, Deserialize, Serialize)]
 #[archive(as = "Permission")]
 #[repr(u8)]
 pub enum Permission {
     /// Permission to read plaintext, implies sync
-    Read,
-    /// Permission to write, implies read
-    Write,
-    /// Permission to delegate sync/read/write, implies write
-    Control,
-    /// Permission to delegate sync/read/write/control/own, implies control
-    Own,
+    Read = 0,
+    /// Permission to write, implies append
+    Write = 1,
+    /// Permission to delegate sync/read/append/write, implies write
+    Control = 2,
+    /// Permission to delegate sync/read/append/write/control/own, implies control
+    Own = 3,
+    /// Permission to insert and to remove own entries, implies read
+    Append = 4,
 }
 
 impl Permission {
     /// Permission can be delegated with `Control` permissions.
     pub fn controllable(self) -> bool {
-        matches!(self, Self::Read | Self::Write)
+        matches!(self, Self::Read | Self::Append | Self::Write)
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            Self::Read => 0,
+            Self::Append => 1,
+            Self::Write => 2,
+            Self::Control => 3,
+            Self::Own => 4,
+        }
+    }
+}
+
+impl Ord for Permission {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+impl PartialOrd for Permission {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -58,6 +80,10 @@ pub enum Actor {
     /// Unbound public key which will be bound by the condition
     /// in a conditional statement.
     Unbound,
+    /// The members of a group.
+    Group(GroupId),
+    /// The peer that created an entry of a table or array.
+    Creator,
 }
 
 impl Actor {
@@ -85,6 +111,8 @@ impl std::fmt::Debug for Actor {
             Self::Peer(p) => write!(f, "{:?}", p),
             Self::Anonymous => write!(f, "Anonymous"),
             Self::Unbound => write!(f, "Unbound"),
+            Self::Group(g) => write!(f, "{:?}", g),
+            Self::Creator => write!(f, "Creator"),
         }
     }
 }
@@ -106,6 +134,21 @@ impl Can {
         Self { actor, perm, path }
     }
 
+    /// Returns the [`Actor`].
+    pub fn actor(&self) -> Actor {
+        self.actor
+    }
+
+    /// Returns the [`Permission`].
+    pub fn perm(&self) -> Permission {
+        self.perm
+    }
+
+    /// Returns the [`Path`].
+    pub fn path(&self) -> Path {
+        self.path.as_path()
+    }
+
     fn as_ref(&self) -> CanRef<'_> {
         CanRef {
             actor: self.actor,
@@ -125,6 +168,47 @@ pub enum Policy {
     CanIf(Actor, Permission, Can),
     /// Revocation statement.
     Revokes(Dot),
+    /// Membership statement; A peer is a member of a group.
+    Member(GroupId, PeerId),
+    /// Capability statement; Up to `uses` peers proving possession of the token key have
+    /// permission.
+    Token(PeerId, Permission, u32),
+    /// Redemption statement; The issuer proves possession of the token key of a capability
+    /// statement.
+    Redeems(Dot, [u8; 64]),
+    /// Certification statement; A device key acts as the issuing root key.
+    Device(PeerId),
+    /// Unlink statement; A device key no longer acts as the issuing root key.
+    Unlinks(PeerId),
+    /// Rotation statement; A new key takes over the permissions the issuing key granted itself
+    /// or could delegate, the issuing key loses all permissions.
+    Rotates(PeerId),
+    /// Quarantine statement; The paths signed by a peer are expired, except for the paths
+    /// kept by the keep statements of the issuer with the causal context hash.
+    Quarantines(PeerId, Option<[u8; 32]>),
+    /// Key statement; The values below the path are encrypted with a key sealed to each
+    /// reader. The key with the highest epoch supersedes the others.
+    Encrypts(u64, Vec<SealedKey>),
+    /// Acceptance statement; The issuer of a capability statement accepts a redemption as
+    /// the use with the index.
+    Accepts(Dot, u32),
+    /// Unconditional statement; An actor has permission until the timestamp.
+    CanUntil(Actor, Permission, Timestamp),
+    /// Conditional statement; An actor has permission if the condition is met until the
+    /// timestamp.
+    CanIfUntil(Actor, Permission, Can, Timestamp),
+    /// Keep statement; A path of a quarantined peer is kept by the quarantine statement with
+    /// the causal context hash.
+    Keeps([u8; 32], Dot),
+}
+
+/// Returns the hash of the dots kept by a quarantine statement.
+pub(crate) fn keep_hash(keep: &BTreeSet<Dot>) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    for dot in keep {
+        hasher.update(dot.as_ref());
+    }
+    hasher.finalize().into()
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -148,7 +232,7 @@ impl<'a> CanRef<'a> {
     }
 
     fn root(self) -> DocId {
-        self.path.first().unwrap().doc().unwrap()
+        root(self.path)
     }
 
     fn implies(self, other: CanRef<'a>) -> bool {
@@ -162,12 +246,71 @@ impl<'a> CanRef<'a> {
     }
 
     fn bind(self, rule: CanRef<'a>) -> Self {
+        self.with_actor(rule.actor)
+    }
+
+    fn with_actor(self, actor: Actor) -> Self {
         Self {
-            actor: rule.actor,
+            actor,
             perm: self.perm,
             path: self.path,
         }
     }
+
+    fn with_path(self, path: Path<'a>) -> Self {
+        Self {
+            actor: self.actor,
+            perm: self.perm,
+            path,
+        }
+    }
+}
+
+fn root(path: Path) -> DocId {
+    path.first().unwrap().doc().unwrap()
+}
+
+fn is_policy(path: Path) -> bool {
+    path.parent()
+        .and_then(|path| path.parent())
+        .and_then(|path| path.last())
+        .and_then(|segment| segment.policy())
+        .is_some()
+}
+
+/// Returns the entry of the table or array at `table` containing `path`.
+fn entry_of(table: Path, path: Path) -> Option<PathBuf> {
+    let mut rest = path.strip_prefix(table).ok()?;
+    // <path_to_array>.VALUES.<pos>.<uid>
+    let depth = match rest.first()?.prim_str() {
+        Some(ARRAY_VALUES) => 3,
+        Some(ARRAY_META) => return None,
+        _ => 1,
+    };
+    let mut entry = table.to_owned();
+    for _ in 0..depth {
+        let (segment, tail) = rest.split_first()?;
+        entry.push_segment(segment);
+        rest = tail;
+    }
+    Some(entry)
+}
+
+/// Adds the creators of the entries of the table or array at `table`. The creator of an
+/// entry is the author of it's first path.
+fn add_creators<'a>(
+    creators: &mut BTreeMap<PathBuf, PeerId>,
+    table: Path,
+    paths: impl Iterator<Item = Path<'a>>,
+) {
+    for path in paths {
+        if is_policy(path) {
+            continue;
+        }
+        if let (Some(entry), Some(peer)) = (entry_of(table, path), author(path)) {
+            creators.entry(entry).or_insert(peer);
+        }
+    }
 }
 
 impl std::fmt::Display for Can {
@@ -186,19 +329,149 @@ impl std::fmt::Display for Can {
 #[archive_attr(derive(Debug, Eq, PartialEq, CheckBytes))]
 #[repr(C)]
 enum Says {
-    Can(Dot, PeerId, Can),
-    CanIf(Dot, PeerId, Can, Can),
+    Can(Dot, PeerId, Can, Option<Timestamp>),
+    CanIf(Dot, PeerId, Can, Can, Option<Timestamp>),
     Revokes(PeerId, Dot),
+    Member(Dot, PeerId, GroupId, PeerId, PathBuf),
+    Token(Dot, PeerId, Can, u32),
+    Redeems(Dot, PeerId, Dot, [u8; 64]),
+    Device(Dot, PeerId, PeerId, PathBuf),
+    Unlinks(Dot, PeerId, PeerId, PathBuf),
+    Rotates(Dot, PeerId, PeerId, PathBuf),
+    Quarantines(Dot, PeerId, PeerId, Option<[u8; 32]>, PathBuf),
+    Accepts(Dot, PeerId, Dot, u32),
+    Keeps(Dot, PeerId, [u8; 32], Dot),
+}
+
+impl Says {
+    fn from_path(path: Path) -> Option<(DocId, Self)> {
+        let doc = path.first()?.doc()?;
+        let dot = path.dot();
+        // schema.doc.(primitive|str)*.policy.peer.sig
+        let (path, _) = path.split_last()?;
+        let (path, peer) = path.split_last()?;
+        let (path, policy) = path.split_last()?;
+        let peer = peer.peer()?;
+        let policy = policy.policy()?;
+        let path = path.to_owned();
+        let says = match policy {
+            Policy::Can(actor, perm) => Self::Can(dot, peer, Can::new(actor, perm, path), None),
+            Policy::CanIf(actor, perm, cond) => {
+                Self::CanIf(dot, peer, Can::new(actor, perm, path), cond, None)
+            }
+            Policy::CanUntil(actor, perm, expiry) => {
+                Self::Can(dot, peer, Can::new(actor, perm, path), Some(expiry))
+            }
+            Policy::CanIfUntil(actor, perm, cond, expiry) => {
+                Self::CanIf(dot, peer, Can::new(actor, perm, path), cond, Some(expiry))
+            }
+            Policy::Revokes(dot) => Self::Revokes(peer, dot),
+            Policy::Member(group, member) => Self::Member(dot, peer, group, member, path),
+            Policy::Token(key, perm, uses) => {
+                Self::Token(dot, peer, Can::new(Actor::Peer(key), perm, path), uses)
+            }
+            Policy::Redeems(token, proof) => Self::Redeems(dot, peer, token, proof),
+            Policy::Device(device) => Self::Device(dot, peer, device, path),
+            Policy::Unlinks(device) => Self::Unlinks(dot, peer, device, path),
+            Policy::Rotates(key) => Self::Rotates(dot, peer, key, path),
+            Policy::Quarantines(peer2, keep) => Self::Quarantines(dot, peer, peer2, keep, path),
+            Policy::Accepts(redemption, index) => Self::Accepts(dot, peer, redemption, index),
+            Policy::Keeps(hash, kept) => Self::Keeps(dot, peer, hash, kept),
+            // keys are resolved by the readers
+            Policy::Encrypts(_, _) => return None,
+        };
+        Some((doc, says))
+    }
+
+    /// Returns the [`Dot`] and expiry of statements that expire.
+    fn expiry(&self) -> Option<(Dot, Timestamp)> {
+        match self {
+            Self::Can(id, _, _, Some(expiry)) | Self::CanIf(id, _, _, _, Some(expiry)) => {
+                Some((*id, *expiry))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the document and the documents referenced by it's conditional statements.
+fn scope(policy: &BTreeMap<DocId, BTreeSet<Says>>, doc: DocId) -> BTreeSet<DocId> {
+    let mut scope = BTreeSet::new();
+    let mut stack = vec![doc];
+    while let Some(doc) = stack.pop() {
+        if !scope.insert(doc) {
+            continue;
+        }
+        for says in policy.get(&doc).into_iter().flatten() {
+            if let Says::CanIf(_, _, _, cond, _) = says {
+                stack.push(cond.as_ref().root());
+            }
+        }
+    }
+    scope
 }
 
 impl std::fmt::Display for Says {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Self::Can(id, peer, can) => write!(f, "{}: {} says {}", id, peer, can),
-            Self::CanIf(id, peer, can, cond) => {
-                write!(f, "{}: {} says {} if {}", id, peer, can, cond)
+            Self::Can(id, peer, can, expiry) => {
+                write!(f, "{}: {} says {}", id, peer, can)?;
+                if let Some(expiry) = expiry {
+                    write!(f, " until {}", expiry)?;
+                }
+                Ok(())
+            }
+            Self::CanIf(id, peer, can, cond, expiry) => {
+                write!(f, "{}: {} says {} if {}", id, peer, can, cond)?;
+                if let Some(expiry) = expiry {
+                    write!(f, " until {}", expiry)?;
+                }
+                Ok(())
             }
             Self::Revokes(peer, id) => write!(f, "{} revokes {}", peer, id),
+            Self::Member(id, peer, group, member, path) => write!(
+                f,
+                "{}: {} says {} is a member of {:?} {}",
+                id, peer, member, group, path
+            ),
+            Self::Token(id, peer, can, uses) => {
+                write!(f, "{}: {} says {} up to {} times", id, peer, can, uses)
+            }
+            Self::Redeems(id, peer, token, _) => write!(f, "{}: {} redeems {}", id, peer, token),
+            Self::Device(id, peer, device, path) => {
+                write!(f, "{}: {} says {} is a device {}", id, peer, device, path)
+            }
+            Self::Unlinks(id, peer, device, path) => {
+                write!(f, "{}: {} unlinks {} {}", id, peer, device, path)
+            }
+            Self::Rotates(id, peer, key, path) => {
+                write!(f, "{}: {} rotates to {} {}", id, peer, key, path)
+            }
+            Self::Quarantines(id, peer, peer2, None, path) => {
+                write!(f, "{}: {} quarantines {} {}", id, peer, peer2, path)
+            }
+            Self::Quarantines(id, peer, peer2, Some(hash), path) => write!(
+                f,
+                "{}: {} quarantines {} {} keeping {}",
+                id,
+                peer,
+                peer2,
+                path,
+                blake3::Hash::from(*hash)
+            ),
+            Self::Accepts(id, peer, redemption, index) => write!(
+                f,
+                "{}: {} accepts {} as use {}",
+                id, peer, redemption, index
+            ),
+            Self::Keeps(id, peer, hash, kept) => write!(
+                f,
+                "{}: {} keeps {} for {}",
+                id,
+                peer,
+                kept,
+                blake3::Hash::from(*hash)
+            ),
         }
     }
 }
@@ -207,6 +480,9 @@ crepe! {
     @input
     struct Input<'a>(&'a Says);
 
+    @input
+    struct Created<'a>(PeerId, Path<'a>);
+
     struct DerivedCan<'a>(Dot, PeerId, CanRef<'a>);
 
     struct DerivedCanIf<'a>(Dot, PeerId, CanRef<'a>, CanRef<'a>);
@@ -215,56 +491,276 @@ crepe! {
 
     struct MaybeRevoked<'a>(Dot, PeerId, CanRef<'a>);
 
+    struct DerivedMember<'a>(Dot, PeerId, GroupId, PeerId, Path<'a>);
+
+    struct Grant<'a>(Dot, CanRef<'a>);
+
+    struct DerivedToken<'a>(Dot, PeerId, CanRef<'a>, u32);
+
+    struct Acceptance(Dot, PeerId, u32);
+
+    struct RootGrant<'a>(Dot, CanRef<'a>);
+
+    struct Held<'a>(Dot, CanRef<'a>);
+
+    struct RotatedKey(Actor, DocId);
+
+    struct Unlinked(Dot, PeerId, Dot);
+
+    struct UnlinkedDevice(Dot);
+
     @output
     struct Authorized<'a>(Dot, PeerId, CanRef<'a>);
 
     @output
     struct Revoked(Dot);
 
+    @output
+    struct Derivation<'a>(Dot, PeerId, CanRef<'a>, Inference, Option<Dot>);
+
+    @output
+    struct ConditionMet<'a>(Dot, PeerId, CanRef<'a>, Dot);
+
+    @output
+    struct Revocation(Dot, PeerId, Dot);
+
+    @output
+    struct AuthorizedMember<'a>(Dot, PeerId, GroupId, PeerId, Path<'a>);
+
+    @output
+    struct GroupGrant<'a>(Dot, CanRef<'a>, Dot);
+
+    @output
+    struct CreatorGrant<'a>(Dot, CanRef<'a>);
+
+    @output
+    struct AuthorizedToken<'a>(Dot, PeerId, CanRef<'a>, u32);
+
+    @output
+    struct Redemption<'a>(Dot, Dot, CanRef<'a>, u32, u32);
+
+    @output
+    struct AuthorizedDevice<'a>(Dot, PeerId, PeerId, Path<'a>);
+
+    @output
+    struct DeviceGrant<'a>(Dot, CanRef<'a>, Dot);
+
+    @output
+    struct Rotation<'a>(Dot, PeerId, PeerId, Path<'a>);
+
+    @output
+    struct RotationGrant<'a>(Dot, CanRef<'a>, Dot);
+
+    @output
+    struct AuthorizedQuarantine(Dot);
+
     DerivedCan(*id, *peer, can.as_ref()) <-
         Input(s),
-        let Says::Can(id, peer, can) = s;
+        let Says::Can(id, peer, can, _) = s;
 
     DerivedCanIf(*id, *peer, can.as_ref(), cond.as_ref()) <-
         Input(s),
-        let Says::CanIf(id, peer, can, cond) = s;
+        let Says::CanIf(id, peer, can, cond, _) = s;
+
+    DerivedMember(*id, *peer, *group, *member, path.as_path()) <-
+        Input(s),
+        let Says::Member(id, peer, group, member, path) = s;
+
+    DerivedToken(*id, *peer, can.as_ref(), *uses) <-
+        Input(s),
+        let Says::Token(id, peer, can, uses) = s;
 
     DerivedRevokes(*peer, *id, peer2, can) <-
         Input(s),
         let Says::Revokes(peer, id) = s,
         Authorized(*id, peer2, can);
 
+    // a held grant is an authorized statement or a statement resolved for a group member,
+    // creator, device or rotated key
+    Held(id, can) <-
+        Authorized(id, _, can);
+
+    Held(id, can) <-
+        DeviceGrant(id, can, _);
+
+    Held(id, can) <-
+        RotationGrant(id, can, _);
+
+    Held(id, can) <-
+        GroupGrant(id, can, _);
+
+    Held(id, can) <-
+        CreatorGrant(id, can);
+
+    // a grant is a held grant of a key that wasn't rotated
+    Grant(id, can) <-
+        Held(id, can),
+        let actor = can.actor(),
+        let doc = can.root(),
+        !RotatedKey(actor, doc);
+
+    // resolve creator
+    CreatorGrant(id, can.with_actor(Actor::Peer(peer)).with_path(entry)) <-
+        Authorized(id, _, can),
+        (can.actor() == Actor::Creator),
+        Created(peer, entry),
+        (can.path().is_ancestor(entry));
+
+    // resolve group
+    GroupGrant(id, can.with_actor(Actor::Peer(member)), member_id) <-
+        Authorized(id, _, can),
+        AuthorizedMember(member_id, _, group, member, path),
+        (can.actor() == Actor::Group(group)),
+        (path.is_ancestor(can.path()));
+
+    // devices are certified by their root key
+    AuthorizedDevice(*id, *peer, *device, path.as_path()) <-
+        Input(s),
+        let Says::Device(id, peer, device, path) = s;
+
+    // root keys can unlink their devices
+    Unlinked(id, *peer, *unlink_id) <-
+        Input(s),
+        let Says::Unlinks(unlink_id, peer, device, path) = s,
+        AuthorizedDevice(id, root_key, device_key, device_path),
+        (*peer == root_key && *device == device_key),
+        (root(path.as_path()) == root(device_path));
+
+    UnlinkedDevice(id) <-
+        Unlinked(id, _, _);
+
+    // grants a root key holds itself, a device can't pass on the grants of it's root key
+    RootGrant(id, can) <-
+        Authorized(id, _, can);
+
+    RootGrant(id, can) <-
+        RotationGrant(id, can, _);
+
+    RootGrant(id, can) <-
+        GroupGrant(id, can, _);
+
+    RootGrant(id, can) <-
+        CreatorGrant(id, can);
+
+    // resolve device
+    DeviceGrant(id, can.with_actor(Actor::Peer(device)), device_id) <-
+        RootGrant(id, can),
+        AuthorizedDevice(device_id, peer, device, path),
+        (can.actor() == Actor::Peer(peer)),
+        (root(path) == can.root()),
+        !UnlinkedDevice(device_id),
+        let actor = can.actor(),
+        let doc = can.root(),
+        !RotatedKey(actor, doc);
+
+    // keys are rotated by the old key, which loses it's grants
+    Rotation(*id, *peer, *key, path.as_path()) <-
+        Input(s),
+        let Says::Rotates(id, peer, key, path) = s;
+
+    RotatedKey(Actor::Peer(*peer), root(path.as_path())) <-
+        Input(s),
+        let Says::Rotates(_, peer, _, path) = s;
+
+    // resolve rotation, the new key takes over the grants the old key issued itself
+    RotationGrant(id, can.with_actor(Actor::Peer(key)), rotation_id) <-
+        Authorized(id, peer, can),
+        Rotation(rotation_id, peer, key, path),
+        (can.actor() == Actor::Peer(peer)),
+        (root(path) == can.root());
+
+    // and the grants the old key could delegate
+    RotationGrant(id, can.with_actor(Actor::Peer(key)), rotation_id) <-
+        Held(id, can),
+        Rotation(rotation_id, peer, key, path),
+        (can.actor() == Actor::Peer(peer)),
+        (root(path) == can.root()),
+        Held(_, auth),
+        (auth.actor() == Actor::Peer(peer)),
+        (
+            auth.perm() == Permission::Own ||
+            auth.perm() == Permission::Control && can.perm().controllable()
+        ),
+        (auth.path().is_ancestor(can.path()));
+
+    // members are added by the local authority or an owner
+    AuthorizedMember(id, peer, group, member, path) <-
+        DerivedMember(id, peer, group, member, path),
+        (Actor::Peer(peer).is_local_authority(root(path)));
+
+    AuthorizedMember(id, peer, group, member, path) <-
+        DerivedMember(id, peer, group, member, path),
+        Grant(_, auth),
+        (Actor::Peer(peer) == auth.actor()),
+        (Permission::Own == auth.perm()),
+        (auth.path().is_ancestor(path));
+
+    // tokens are minted by the local authority or a controller
+    AuthorizedToken(id, peer, can, uses) <-
+        DerivedToken(id, peer, can, uses),
+        (can.perm().controllable()),
+        (Actor::Peer(peer).is_local_authority(can.root()));
+
+    AuthorizedToken(id, peer, can, uses) <-
+        DerivedToken(id, peer, can, uses),
+        Grant(_, auth),
+        (Actor::Peer(peer) == auth.actor()),
+        (auth.perm() >= Permission::Control && can.perm().controllable()),
+        (auth.path().is_ancestor(can.path()));
+
+    Acceptance(*redemption, *peer, *index) <-
+        Input(s),
+        let Says::Accepts(_, peer, redemption, index) = s;
+
+    // resolve token, the proof is signed by the token key and the issuer of the token
+    // accepted the redemption
+    Redemption(*id, *token, can.with_actor(Actor::Peer(*peer)), uses, index) <-
+        Input(s),
+        let Says::Redeems(id, peer, token, proof) = s,
+        AuthorizedToken(*token, issuer, can, uses),
+        (verify_proof(can.actor(), *token, *peer, proof)),
+        Acceptance(*id, issuer, index);
+
     // resolve conditional
-    DerivedCan(id, peer, can.bind(auth)) <-
+    ConditionMet(id, peer, can.bind(auth), auth_id) <-
         DerivedCanIf(id, peer, can, cond),
-        Authorized(_, _, auth),
+        Grant(auth_id, auth),
         (auth.implies(cond));
 
-    // local authority
+    DerivedCan(id, peer, can) <-
+        ConditionMet(id, peer, can, _);
+
     Authorized(id, peer, can) <-
+        Derivation(id, peer, can, _, _);
+
+    // local authority
+    Derivation(id, peer, can, Inference::LocalAuthority, None) <-
         DerivedCan(id, peer, can),
         (Actor::Peer(peer).is_local_authority(can.root()));
 
     // ownership
-    Authorized(id, peer, can) <-
+    Derivation(id, peer, can, Inference::Ownership, Some(auth_id)) <-
         DerivedCan(id, peer, can),
-        Authorized(_, _, auth),
+        Grant(auth_id, auth),
         (Actor::Peer(peer) == auth.actor()),
         (Permission::Own == auth.perm()),
         (auth.path().is_ancestor(can.path()));
 
     // control
-    Authorized(id, peer, can) <-
+    Derivation(id, peer, can, Inference::Control, Some(auth_id)) <-
         DerivedCan(id, peer, can),
-        Authorized(_, _, auth),
+        Grant(auth_id, auth),
         (Actor::Peer(peer) == auth.actor()),
         (auth.perm() == Permission::Control && can.perm().controllable()),
         (auth.path().is_ancestor(can.path()));
 
-    // higher privileges can revoke
     Revoked(id) <-
+        Revocation(id, _, _);
+
+    // higher privileges can revoke
+    Revocation(id, peer, auth_id) <-
         DerivedRevokes(peer, id, peer2, can),
-        Authorized(_, _, auth),
+        Grant(auth_id, auth),
         (
             Actor::Peer(peer) == auth.actor() && auth.perm() >= Permission::Control ||
             Actor::Peer(peer).is_local_authority(can.root())
@@ -277,112 +773,1127 @@ crepe! {
                 Actor::Peer(peer).is_local_authority(can.root())
             )
         );
+
+    // owners can remove members
+    Revocation(*id, *peer, auth_id) <-
+        Input(s),
+        let Says::Revokes(peer, id) = s,
+        AuthorizedMember(*id, _, _, _, path),
+        Grant(auth_id, auth),
+        (
+            Actor::Peer(*peer) == auth.actor() && auth.perm() == Permission::Own ||
+            Actor::Peer(*peer).is_local_authority(root(path))
+        ),
+        (auth.path().is_ancestor(path));
+
+    Revocation(id, peer, unlink_id) <-
+        Unlinked(id, peer, unlink_id);
+
+    // owners can revoke rotations
+    Revocation(*id, *peer, auth_id) <-
+        Input(s),
+        let Says::Revokes(peer, id) = s,
+        Rotation(*id, _, _, path),
+        Grant(auth_id, auth),
+        (
+            Actor::Peer(*peer) == auth.actor() && auth.perm() == Permission::Own ||
+            Actor::Peer(*peer).is_local_authority(root(path))
+        ),
+        (auth.path().is_ancestor(path));
+
+    // peers are quarantined by the local authority or an owner
+    AuthorizedQuarantine(*id) <-
+        Input(s),
+        let Says::Quarantines(id, peer, _, _, path) = s,
+        (Actor::Peer(*peer).is_local_authority(root(path.as_path())));
+
+    AuthorizedQuarantine(*id) <-
+        Input(s),
+        let Says::Quarantines(id, peer, _, _, path) = s,
+        Grant(_, auth),
+        (Actor::Peer(*peer) == auth.actor()),
+        (Permission::Own == auth.perm()),
+        (auth.path().is_ancestor(path.as_path()));
+
+    // controllers can revoke tokens
+    Revocation(*id, *peer, auth_id) <-
+        Input(s),
+        let Says::Revokes(peer, id) = s,
+        AuthorizedToken(*id, _, can, _),
+        Grant(auth_id, auth),
+        (
+            Actor::Peer(*peer) == auth.actor() && auth.perm() >= Permission::Control ||
+            Actor::Peer(*peer).is_local_authority(can.root())
+        ),
+        (auth.path().is_ancestor(can.path()));
 }
 
-#[derive(Debug, Archive, Serialize)]
-#[archive(as = "Rule")]
-#[repr(C)]
-pub(crate) struct Rule {
-    pub id: Dot,
-    pub perm: Permission,
+/// Output relations of the policy of a document.
+struct Relations<'a> {
+    authorized: Vec<Authorized<'a>>,
+    revoked: BTreeSet<Dot>,
+    derivations: Vec<Derivation<'a>>,
+    conditions: Vec<ConditionMet<'a>>,
+    revocations: Vec<Revocation>,
+    members: Vec<AuthorizedMember<'a>>,
+    groups: Vec<GroupGrant<'a>>,
+    created: Vec<CreatorGrant<'a>>,
+    tokens: Vec<AuthorizedToken<'a>>,
+    redemptions: Vec<Redemption<'a>>,
+    devices: Vec<AuthorizedDevice<'a>>,
+    device_grants: Vec<DeviceGrant<'a>>,
+    rotations: Vec<Rotation<'a>>,
+    rotation_grants: Vec<RotationGrant<'a>>,
+    quarantines: BTreeSet<Dot>,
 }
 
-impl Rule {
-    fn new(id: Dot, perm: Permission) -> Self {
-        Self { id, perm }
+impl<'a> Relations<'a> {
+    /// Evaluates the statements and created paths of a document.
+    fn evaluate(
+        says: impl IntoIterator<Item = &'a Says>,
+        created: impl IntoIterator<Item = (PeerId, Path<'a>)>,
+    ) -> Self {
+        let mut runtime = Crepe::new();
+        runtime.extend(says.into_iter().map(Input));
+        runtime.extend(created.into_iter().map(|(peer, path)| Created(peer, path)));
+        let (
+            authorized,
+            revoked,
+            derivations,
+            conditions,
+            revocations,
+            members,
+            groups,
+            created,
+            tokens,
+            redemptions,
+            devices,
+            device_grants,
+            rotations,
+            rotation_grants,
+            quarantines,
+        ) = runtime.run();
+        Self {
+            authorized: authorized.into_iter().collect(),
+            revoked: revoked.into_iter().map(|Revoked(id)| id).collect(),
+            derivations: derivations.into_iter().collect(),
+            conditions: conditions.into_iter().collect(),
+            revocations: revocations.into_iter().collect(),
+            members: members.into_iter().collect(),
+            groups: groups.into_iter().collect(),
+            created: created.into_iter().collect(),
+            tokens: tokens.into_iter().collect(),
+            redemptions: redemptions.into_iter().collect(),
+            devices: devices.into_iter().collect(),
+            device_grants: device_grants.into_iter().collect(),
+            rotations: rotations.into_iter().collect(),
+            rotation_grants: rotation_grants.into_iter().collect(),
+            quarantines: quarantines
+                .into_iter()
+                .map(|AuthorizedQuarantine(id)| id)
+                .collect(),
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct Acl(BlobMap);
+/// Message signed by the token key to prove that `redeemer` holds the token.
+fn redemption_message(token: Dot, redeemer: PeerId) -> [u8; 64] {
+    let mut msg = [0; 64];
+    msg[..32].copy_from_slice(token.as_ref());
+    msg[32..].copy_from_slice(redeemer.as_ref());
+    msg
+}
 
-impl Acl {
-    pub fn new(tree: BlobMap) -> Self {
-        Self(tree)
+fn verify_proof(key: Actor, token: Dot, redeemer: PeerId, proof: &[u8; 64]) -> bool {
+    let key = if let Actor::Peer(key) = key {
+        key
+    } else {
+        return false;
+    };
+    let (pubkey, sig) = match (
+        PublicKey::from_bytes(key.as_ref()),
+        Signature::from_bytes(proof),
+    ) {
+        (Ok(pubkey), Ok(sig)) => (pubkey, sig),
+        _ => return false,
+    };
+    pubkey
+        .verify(&redemption_message(token, redeemer), &sig)
+        .is_ok()
+}
+
+/// Selects the redemptions within the uses of their tokens. Each redeemer uses a token
+/// once. Redemptions are selected in the order the issuer of the token accepted them, so
+/// that all replicas select the same redemptions and the first accepted ones win.
+fn redeemed<'a>(
+    mut redemptions: Vec<Redemption<'a>>,
+    revoked: &BTreeSet<Dot>,
+) -> impl Iterator<Item = (Dot, CanRef<'a>)> + '_ {
+    redemptions.sort_by_key(|Redemption(id, token, _, _, index)| (*token, *index, *id));
+    let mut redeemers: BTreeMap<Dot, BTreeSet<Actor>> = BTreeMap::new();
+    redemptions
+        .into_iter()
+        .filter(move |Redemption(_, token, can, uses, index)| {
+            let redeemers = redeemers.entry(*token).or_default();
+            index < uses && redeemers.len() < *uses as usize && redeemers.insert(can.actor)
+        })
+        .filter(move |Redemption(_, token, _, _, _)| !revoked.contains(token))
+        .map(|Redemption(id, _, can, _, _)| (id, can))
+}
+
+/// Status of a policy statement.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PolicyStatus {
+    /// The issuer lacks the permission to make the statement or the condition isn't met.
+    Unauthorized,
+    /// The statement is in effect.
+    Authorized,
+    /// The statement was revoked.
+    Revoked,
+    /// The statement expired.
+    Expired,
+}
+
+/// Kind of a policy statement.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum StatementKind {
+    /// The actor is granted the permission.
+    Grant,
+    /// Up to `uses` peers redeeming the token are granted the permission, the actor is the
+    /// token key.
+    Token(u32),
+    /// The actor is a member of the group.
+    Member(GroupId),
+    /// The actor is a device of the issuer.
+    Device,
+}
+
+/// Policy statement with it's issuer and status.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Statement {
+    id: Dot,
+    kind: StatementKind,
+    issuer: PeerId,
+    can: Can,
+    cond: Option<Can>,
+    expiry: Option<Timestamp>,
+    status: PolicyStatus,
+}
+
+impl Statement {
+    /// Returns the [`Dot`] identifying the statement.
+    pub fn id(&self) -> Dot {
+        self.id
     }
 
-    pub fn load(storage: Arc<dyn Storage>, name: &str) -> Result<Self> {
-        Ok(Self(BlobMap::load(storage, name)?))
+    /// Returns the [`StatementKind`].
+    pub fn kind(&self) -> StatementKind {
+        self.kind
     }
 
-    pub fn active_peer(&self, peer: &PeerId) -> bool {
-        for (key, _) in self.0.iter() {
-            let peer2 = Path::new(&key)
-                .child()
-                .unwrap()
-                .first()
-                .unwrap()
-                .peer()
-                .unwrap();
-            if *peer == peer2 {
-                return true;
-            }
-        }
-        false
+    /// Returns the [`PeerId`] that signed the statement.
+    pub fn issuer(&self) -> PeerId {
+        self.issuer
     }
 
-    pub fn subscribe(&self, doc: &DocId) -> BoxStream<'static, Diff<u8, Arc<[u8]>>> {
-        let mut path = PathBuf::new();
-        path.doc(doc);
-        self.0.watch_prefix(path)
+    /// Returns the [`Actor`] granted the permission.
+    pub fn actor(&self) -> Actor {
+        self.can.actor()
     }
 
-    fn add_rule(&self, id: Dot, actor: Actor, perm: Permission, path: Path) -> Result<()> {
-        let peer = match actor {
-            Actor::Peer(peer) => peer,
-            _ => PeerId::new([0; 32]),
-        };
-        let mut prefix = PathBuf::new();
-        prefix.doc(&path.first().unwrap().doc().unwrap());
-        prefix.peer(&peer);
-        prefix.extend(path.child().unwrap());
-        self.0
-            .insert_archived(prefix.as_path(), &Rule::new(id, perm))?;
-        Ok(())
+    /// Returns the granted [`Permission`]. Members and devices are granted the permissions
+    /// of the group or issuer instead, their statements return [`Permission::Read`].
+    pub fn perm(&self) -> Permission {
+        self.can.perm()
     }
 
-    fn revoke_rules(&self, revoked: BTreeSet<Dot>) -> Result<()> {
-        for (k, v) in self.0.iter() {
-            if revoked.contains(&Ref::<Rule>::new(v.clone()).as_ref().id) {
-                self.0.remove(k)?;
-            }
-        }
-        Ok(())
+    /// Returns the [`Path`] the permission is granted on.
+    pub fn path(&self) -> Path {
+        self.can.path()
     }
 
-    fn implies(&self, peer: &PeerId, doc: &DocId, perm: Permission, path: Path) -> Result<bool> {
-        let mut prefix = PathBuf::new();
-        prefix.doc(doc);
-        prefix.peer(peer);
-        for (k, v) in self.0.scan_prefix(prefix) {
-            let p = Path::new(&k);
-            let rule = Ref::<Rule>::new(v.clone());
-            if p.child().unwrap().child().unwrap().is_ancestor(path) && rule.as_ref().perm >= perm {
-                return Ok(true);
-            }
-        }
-        Ok(false)
+    /// Returns the condition of a conditional statement.
+    pub fn cond(&self) -> Option<&Can> {
+        self.cond.as_ref()
     }
 
-    pub fn can(&self, peer: PeerId, perm: Permission, path: Path) -> Result<bool> {
-        let (doc, path) = path.split_first().unwrap();
-        let doc = doc.doc().unwrap();
-        if peer == doc.into() {
-            return Ok(true);
-        }
-        if self.implies(&peer, &doc, perm, path)? {
-            return Ok(true);
-        }
-        if self.implies(&PeerId::new([0; 32]), &doc, perm, path)? {
-            return Ok(true);
-        }
-        Ok(false)
+    /// Returns the [`Timestamp`] the statement expires at. A statement derived from an
+    /// expiring statement expires with it.
+    pub fn expiry(&self) -> Option<Timestamp> {
+        self.expiry
     }
-}
 
-struct AclDebug<'a>(&'a BlobMap);
+    /// Returns the [`PolicyStatus`].
+    pub fn status(&self) -> PolicyStatus {
+        self.status
+    }
 
-impl<'a> std::fmt::Debug for AclDebug<'a> {
+    fn new(says: &Says, evaluation: &Evaluation, now: Timestamp) -> Option<Self> {
+        use StatementKind::*;
+        let (id, kind, issuer, can, cond, expiry) = match says {
+            Says::Can(id, issuer, can, expiry) => (*id, Grant, *issuer, can.clone(), None, *expiry),
+            Says::CanIf(id, issuer, can, cond, expiry) => (
+                *id,
+                Grant,
+                *issuer,
+                can.clone(),
+                Some(cond.clone()),
+                *expiry,
+            ),
+            Says::Token(id, issuer, can, uses) => {
+                (*id, Token(*uses), *issuer, can.clone(), None, None)
+            }
+            Says::Member(id, issuer, group, member, path) => {
+                let can = Can::new(Actor::Peer(*member), Permission::Read, path.clone());
+                (*id, Member(*group), *issuer, can, None, None)
+            }
+            Says::Device(id, issuer, device, path) => {
+                let can = Can::new(Actor::Peer(*device), Permission::Read, path.clone());
+                (*id, Device, *issuer, can, None, None)
+            }
+            Says::Revokes(_, _)
+            | Says::Redeems(_, _, _, _)
+            | Says::Unlinks(_, _, _, _)
+            | Says::Rotates(_, _, _, _)
+            | Says::Quarantines(_, _, _, _, _)
+            | Says::Accepts(_, _, _, _)
+            | Says::Keeps(_, _, _, _) => return None,
+        };
+        let Evaluation {
+            authorized,
+            revoked,
+            expiries,
+        } = evaluation;
+        let expiry = expiries.get(&id).copied().or(expiry);
+        let status = if revoked.contains(&id) {
+            PolicyStatus::Revoked
+        } else if expiry.map(|expiry| expiry <= now).unwrap_or_default() {
+            PolicyStatus::Expired
+        } else if authorized.contains(&id) {
+            PolicyStatus::Authorized
+        } else {
+            PolicyStatus::Unauthorized
+        };
+        Some(Self {
+            id,
+            kind,
+            issuer,
+            can,
+            cond,
+            expiry,
+            status,
+        })
+    }
+}
+
+/// Group membership statement with it's issuer and status.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Membership {
+    id: Dot,
+    issuer: PeerId,
+    group: GroupId,
+    member: PeerId,
+    path: PathBuf,
+    status: PolicyStatus,
+}
+
+impl Membership {
+    /// Returns the [`Dot`] identifying the statement.
+    pub fn id(&self) -> Dot {
+        self.id
+    }
+
+    /// Returns the [`PeerId`] that signed the statement.
+    pub fn issuer(&self) -> PeerId {
+        self.issuer
+    }
+
+    /// Returns the [`GroupId`].
+    pub fn group(&self) -> GroupId {
+        self.group
+    }
+
+    /// Returns the [`PeerId`] of the member.
+    pub fn member(&self) -> PeerId {
+        self.member
+    }
+
+    /// Returns the [`Path`] the membership applies to.
+    pub fn path(&self) -> Path {
+        self.path.as_path()
+    }
+
+    /// Returns the [`PolicyStatus`].
+    pub fn status(&self) -> PolicyStatus {
+        self.status
+    }
+
+    fn new(says: &Says, evaluation: &Evaluation) -> Option<Self> {
+        if let Says::Member(id, issuer, group, member, path) = says {
+            let status = if evaluation.revoked.contains(id) {
+                PolicyStatus::Revoked
+            } else if evaluation.authorized.contains(id) {
+                PolicyStatus::Authorized
+            } else {
+                PolicyStatus::Unauthorized
+            };
+            Some(Self {
+                id: *id,
+                issuer: *issuer,
+                group: *group,
+                member: *member,
+                path: path.clone(),
+                status,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Authorized and revoked statements and the expiries of the statements of the last
+/// evaluation of a document.
+#[derive(Clone, Debug, Default)]
+struct Evaluation {
+    authorized: BTreeSet<Dot>,
+    revoked: BTreeSet<Dot>,
+    expiries: BTreeMap<Dot, Timestamp>,
+}
+
+/// Capability minted by [`Cursor::say_token`](crate::Cursor::say_token). Holding the
+/// capability proves possession of the token key, the first redemptions accepted by the
+/// issuer of the token gain the permission of the token.
+#[derive(Clone, Debug, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(CheckBytes))]
+#[repr(C)]
+pub struct Capability {
+    id: Dot,
+    path: PathBuf,
+    secret: Keypair,
+}
+
+impl Capability {
+    pub(crate) fn new(id: Dot, path: PathBuf, secret: Keypair) -> Self {
+        Self { id, path, secret }
+    }
+
+    /// Returns the [`Dot`] of the capability statement.
+    pub fn id(&self) -> Dot {
+        self.id
+    }
+
+    /// Returns the [`DocId`] of the document.
+    pub fn doc(&self) -> DocId {
+        root(self.path.as_path())
+    }
+
+    /// Returns the [`Path`] the permission is granted on.
+    pub fn path(&self) -> Path {
+        self.path.as_path()
+    }
+
+    /// Signs the proof that `redeemer` holds the token.
+    pub(crate) fn prove(&self, redeemer: PeerId) -> [u8; 64] {
+        self.secret
+            .sign(&redemption_message(self.id, redeemer))
+            .to_bytes()
+    }
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let bytes = Ref::archive(self);
+        write!(
+            f,
+            "{}",
+            base64::encode_config(bytes.as_bytes(), base64::URL_SAFE)
+        )
+    }
+}
+
+impl std::str::FromStr for Capability {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = base64::decode_config(s, base64::URL_SAFE)?;
+        Ref::<Self>::checked(&bytes)?.to_owned()
+    }
+}
+
+/// Inference rule of the acl program.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Inference {
+    /// The statement is signed by the document key.
+    LocalAuthority,
+    /// The issuer owns the path or one of it's ancestors.
+    Ownership,
+    /// The issuer controls the path or one of it's ancestors.
+    Control,
+    /// The statement is revoked by a peer with higher privileges.
+    Revocation,
+    /// The statement expired.
+    Expiry,
+}
+
+/// Step in the derivation of an access decision.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Step {
+    statement: Statement,
+    inference: Option<Inference>,
+    premise: Option<Dot>,
+    condition: Option<Dot>,
+}
+
+impl Step {
+    /// Returns the policy [`Statement`].
+    pub fn statement(&self) -> &Statement {
+        &self.statement
+    }
+
+    /// Returns the [`Inference`] rule that derived the status of the statement. Returns
+    /// `None` if the statement isn't authorized, which is the missing link.
+    pub fn inference(&self) -> Option<Inference> {
+        self.inference
+    }
+
+    /// Returns the [`Dot`] of the authorized statement the [`Inference`] relies on.
+    pub fn premise(&self) -> Option<Dot> {
+        self.premise
+    }
+
+    /// Returns the [`Dot`] of the authorized statement satisfying the condition of a
+    /// conditional statement.
+    pub fn condition(&self) -> Option<Dot> {
+        self.condition
+    }
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let st = &self.statement;
+        write!(
+            f,
+            "{}: {} says {:?} can {:?} {}",
+            st.id, st.issuer, st.can.actor, st.can.perm, st.can.path
+        )?;
+        if let Some(cond) = &st.cond {
+            write!(f, " if {}", cond)?;
+            if let Some(id) = self.condition {
+                write!(f, " (met by {})", id)?;
+            }
+        }
+        match (self.inference, self.premise) {
+            (Some(Inference::LocalAuthority), _) => write!(f, " by local authority"),
+            (Some(Inference::Revocation), Some(id)) => write!(f, " revoked by {}", id),
+            (Some(Inference::Expiry), _) => write!(f, " expired"),
+            (Some(inference), Some(id)) => write!(f, " by {:?} of {}", inference, id),
+            (Some(inference), None) => write!(f, " by {:?}", inference),
+            (None, _) => write!(f, " is not authorized"),
+        }
+    }
+}
+
+/// Explanation of an access decision.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Explanation {
+    granted: bool,
+    steps: Vec<Step>,
+}
+
+impl Explanation {
+    /// Returns if access is granted.
+    pub fn granted(&self) -> bool {
+        self.granted
+    }
+
+    /// If access is granted returns the derivation chain from the statement granting access
+    /// to a statement signed by the document key. If access is denied returns the statements
+    /// that would grant access and why they don't. The steps are empty if the peer is the
+    /// local authority or if no statement grants access.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+}
+
+impl std::fmt::Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", if self.granted { "granted" } else { "denied" })?;
+        for step in &self.steps {
+            write!(f, "\n  {}", step)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Archive, Serialize)]
+#[archive(as = "Rule")]
+#[repr(C)]
+pub(crate) struct Rule {
+    pub id: Dot,
+    pub perm: Permission,
+}
+
+impl Rule {
+    fn new(id: Dot, perm: Permission) -> Self {
+        Self { id, perm }
+    }
+}
+
+/// Grants of a rule that expire. Changes are checked against the grants in effect at their
+/// timestamp, so that all replicas join the same changes. The stored rules only contain the
+/// grants in effect at the local time.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Expiring {
+    /// Strongest grant that doesn't expire.
+    permanent: Option<Permission>,
+    /// Grants that expire with their expiry.
+    until: Vec<(Permission, Timestamp)>,
+}
+
+impl Expiring {
+    /// Returns if the grants imply the permission at `now`.
+    fn implies(&self, perm: Permission, now: Timestamp) -> bool {
+        self.permanent.map(|p| p >= perm).unwrap_or_default()
+            || self
+                .until
+                .iter()
+                .any(|(p, expiry)| *p >= perm && *expiry > now)
+    }
+
+    /// Returns the strongest grant in effect at `now`.
+    fn strongest(&self, now: Timestamp) -> Option<Permission> {
+        self.until
+            .iter()
+            .filter(|(_, expiry)| *expiry > now)
+            .map(|(perm, _)| *perm)
+            .chain(self.permanent)
+            .max()
+    }
+}
+
+/// Returns the earlier of two expiries, `None` never expires.
+fn earliest(a: Option<Timestamp>, b: Option<Timestamp>) -> Option<Timestamp> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Returns the expiries of the statements. A statement derived from an expiring statement
+/// expires with it, a statement derived in several ways expires with the last of them.
+fn expiries(policy: &[&Says], derivations: &[Derivation]) -> BTreeMap<Dot, Timestamp> {
+    let own: BTreeMap<Dot, Timestamp> = policy.iter().filter_map(|says| says.expiry()).collect();
+    let mut effective: BTreeMap<Dot, Option<Timestamp>> = BTreeMap::new();
+    loop {
+        let mut changed = false;
+        for Derivation(id, _, _, _, premise) in derivations {
+            let cap = match premise {
+                Some(premise) => match effective.get(premise) {
+                    Some(cap) => *cap,
+                    None => continue,
+                },
+                None => None,
+            };
+            let expiry = earliest(own.get(id).copied(), cap);
+            let later = match (effective.get(id), expiry) {
+                (None, _) => true,
+                (Some(Some(_)), None) => true,
+                (Some(Some(prev)), Some(expiry)) => expiry > *prev,
+                (Some(None), _) => false,
+            };
+            if later {
+                effective.insert(*id, expiry);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    own.into_iter()
+        .filter(|(id, _)| !effective.contains_key(id))
+        .chain(
+            effective
+                .into_iter()
+                .filter_map(|(id, expiry)| Some((id, expiry?))),
+        )
+        .collect()
+}
+
+/// Authorized quarantine statement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Quarantine {
+    peer: PeerId,
+    path: PathBuf,
+    keep: BTreeSet<Dot>,
+}
+
+impl Quarantine {
+    /// Returns if the path is expired by the quarantine. Policy statements are never
+    /// expired, they are revoked instead.
+    fn contains(&self, path: Path) -> bool {
+        author(path) == Some(self.peer)
+            && !is_policy(path)
+            && self.path.as_path().is_ancestor(path)
+            && !self.keep.contains(&path.dot())
+    }
+}
+
+#[derive(Clone)]
+pub struct Acl {
+    rules: BlobMap,
+    policy: Arc<RwLock<BTreeMap<DocId, BTreeSet<Says>>>>,
+    creators: Arc<RwLock<BTreeMap<DocId, BTreeMap<PathBuf, PeerId>>>>,
+    quarantines: Arc<RwLock<BTreeMap<DocId, Vec<Quarantine>>>>,
+    evaluations: Arc<RwLock<BTreeMap<DocId, Evaluation>>>,
+    expiring: Arc<RwLock<BTreeMap<DocId, BTreeMap<PathBuf, Expiring>>>>,
+    clock: Clock,
+}
+
+impl Acl {
+    pub fn new(tree: BlobMap) -> Self {
+        Self {
+            rules: tree,
+            policy: Default::default(),
+            creators: Default::default(),
+            quarantines: Default::default(),
+            evaluations: Default::default(),
+            expiring: Default::default(),
+            clock: Default::default(),
+        }
+    }
+
+    pub fn load(storage: Arc<dyn Storage>, name: &str) -> Result<Self> {
+        Ok(Self::new(BlobMap::load(storage, name)?))
+    }
+
+    /// Returns the hybrid logical clock of the replica.
+    pub(crate) fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Checks if a peer has permissions in a document.
+    pub fn has_rules(&self, peer: &PeerId, doc: &DocId) -> bool {
+        let mut prefix = PathBuf::new();
+        prefix.doc(doc);
+        prefix.peer(peer);
+        self.rules.scan_prefix(prefix).next().is_some()
+    }
+
+    /// Returns the peers that can read the path or a path below it with the statements
+    /// granting them the permission. Anonymous readers are not returned, see
+    /// [`Acl::has_anonymous_reader`].
+    pub fn readers(&self, path: Path) -> Vec<(PeerId, Dot)> {
+        let (doc, path) = path.split_first().unwrap();
+        let mut prefix = PathBuf::new();
+        prefix.doc(&doc.doc().unwrap());
+        let mut readers = vec![];
+        for (k, v) in self.rules.scan_prefix(prefix) {
+            let p = Path::new(&k).child().unwrap();
+            let peer = p.first().unwrap().peer().unwrap();
+            let p = p.child().unwrap();
+            if peer != PeerId::new([0; 32]) && (p.is_ancestor(path) || path.is_ancestor(p)) {
+                let rule = Ref::<Rule>::new(v.clone());
+                readers.push((peer, rule.as_ref().id));
+            }
+        }
+        readers
+    }
+
+    /// Checks if anonymous peers can read the path or a path below it.
+    pub fn has_anonymous_reader(&self, path: Path) -> bool {
+        let (doc, path) = path.split_first().unwrap();
+        let mut prefix = PathBuf::new();
+        prefix.doc(&doc.doc().unwrap());
+        prefix.peer(&PeerId::new([0; 32]));
+        self.rules.scan_prefix(prefix).any(|(k, _)| {
+            let p = Path::new(&k).child().unwrap().child().unwrap();
+            p.is_ancestor(path) || path.is_ancestor(p)
+        })
+    }
+
+    /// Checks if anonymous peers can read a document.
+    pub fn has_anonymous_readers(&self, doc: &DocId) -> bool {
+        let mut prefix = PathBuf::new();
+        prefix.doc(doc);
+        prefix.peer(&PeerId::new([0; 32]));
+        self.rules.scan_prefix(prefix).next().is_some()
+    }
+
+    /// Returns the policy statements of a document with their status of the last evaluation.
+    pub fn statements(&self, doc: &DocId) -> Vec<Statement> {
+        let evaluation = self
+            .evaluations
+            .read()
+            .get(doc)
+            .cloned()
+            .unwrap_or_default();
+        self.policy
+            .read()
+            .get(doc)
+            .into_iter()
+            .flatten()
+            .filter_map(|says| Statement::new(says, &evaluation, self.clock.now()))
+            .collect()
+    }
+
+    /// Returns the index of the next use of the token redeemed by `redemption`, if the token
+    /// was issued by `issuer`, has uses left and the redemption wasn't accepted yet.
+    pub fn next_use(&self, doc: &DocId, redemption: Dot, issuer: &PeerId) -> Option<u32> {
+        let policy = self.policy.read();
+        let says = policy.get(doc)?;
+        let token = says.iter().find_map(|says| match says {
+            Says::Redeems(id, _, token, _) if *id == redemption => Some(*token),
+            _ => None,
+        })?;
+        let uses = says.iter().find_map(|says| match says {
+            Says::Token(id, peer, _, uses) if *id == token && peer == issuer => Some(*uses),
+            _ => None,
+        })?;
+        let redemptions: BTreeSet<Dot> = says
+            .iter()
+            .filter_map(|says| match says {
+                Says::Redeems(id, _, t, _) if *t == token => Some(*id),
+                _ => None,
+            })
+            .collect();
+        let mut accepted = 0;
+        for says in says {
+            if let Says::Accepts(_, peer, id, _) = says {
+                if peer != issuer || !redemptions.contains(id) {
+                    continue;
+                }
+                if *id == redemption {
+                    return None;
+                }
+                accepted += 1;
+            }
+        }
+        if accepted < uses {
+            Some(accepted)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the group membership statements of a document with their status of the last
+    /// evaluation.
+    pub fn memberships(&self, doc: &DocId) -> Vec<Membership> {
+        let evaluation = self
+            .evaluations
+            .read()
+            .get(doc)
+            .cloned()
+            .unwrap_or_default();
+        self.policy
+            .read()
+            .get(doc)
+            .into_iter()
+            .flatten()
+            .filter_map(|says| Membership::new(says, &evaluation))
+            .collect()
+    }
+
+    /// Checks if a path was signed by a quarantined peer.
+    pub fn quarantined(&self, path: Path) -> bool {
+        self.quarantines
+            .read()
+            .get(&root(path))
+            .into_iter()
+            .flatten()
+            .any(|quarantine| quarantine.contains(path))
+    }
+
+    pub fn active_peer(&self, peer: &PeerId) -> bool {
+        for (key, _) in self.rules.iter() {
+            let peer2 = Path::new(&key)
+                .child()
+                .unwrap()
+                .first()
+                .unwrap()
+                .peer()
+                .unwrap();
+            if *peer == peer2 {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn subscribe(&self, doc: &DocId) -> BoxStream<'static, Diff<u8, Arc<[u8]>>> {
+        let mut path = PathBuf::new();
+        path.doc(doc);
+        self.rules.watch_prefix(path)
+    }
+
+    fn rule_key(actor: Actor, path: Path) -> PathBuf {
+        let peer = match actor {
+            Actor::Peer(peer) => peer,
+            _ => PeerId::new([0; 32]),
+        };
+        let mut prefix = PathBuf::new();
+        prefix.doc(&path.first().unwrap().doc().unwrap());
+        prefix.peer(&peer);
+        prefix.extend(path.child().unwrap());
+        prefix
+    }
+
+    /// Returns the stored rules of a document by their key.
+    fn doc_rules(&self, doc: &DocId) -> BTreeMap<PathBuf, DerivedRule> {
+        let mut prefix = PathBuf::new();
+        prefix.doc(doc);
+        self.rules
+            .scan_prefix(prefix)
+            .map(|(k, v)| {
+                let rule = Ref::<Rule>::new(v.clone());
+                let rule = rule.as_ref();
+                (Path::new(&k).to_owned(), (rule.id, rule.perm))
+            })
+            .collect()
+    }
+
+    fn implies(
+        &self,
+        peer: &PeerId,
+        doc: &DocId,
+        perm: Permission,
+        path: Path,
+        now: Timestamp,
+    ) -> Result<bool> {
+        let mut prefix = PathBuf::new();
+        prefix.doc(doc);
+        prefix.peer(peer);
+        let expiring = self.expiring.read();
+        let expiring = expiring.get(doc);
+        // rules with expiring grants are checked against their grants, as the stored rule
+        // is the strongest grant at the local time
+        for (k, v) in self.rules.scan_prefix(&prefix) {
+            let p = Path::new(&k);
+            let rule = Ref::<Rule>::new(v.clone());
+            let key: &[u8] = p.as_ref();
+            if p.child().unwrap().child().unwrap().is_ancestor(path)
+                && rule.as_ref().perm >= perm
+                && !expiring.map(|e| e.contains_key(key)).unwrap_or_default()
+            {
+                return Ok(true);
+            }
+        }
+        for (key, lifetime) in expiring.into_iter().flatten() {
+            let p = key.as_path();
+            if p.as_ref().starts_with(prefix.as_ref())
+                && p.child().unwrap().child().unwrap().is_ancestor(path)
+                && lifetime.implies(perm, now)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn check(&self, peer: PeerId, perm: Permission, path: Path, now: Timestamp) -> Result<bool> {
+        let (doc, path) = path.split_first().unwrap();
+        let doc = doc.doc().unwrap();
+        if peer == doc.into() {
+            return Ok(true);
+        }
+        if self.implies(&peer, &doc, perm, path, now)? {
+            return Ok(true);
+        }
+        if self.implies(&PeerId::new([0; 32]), &doc, perm, path, now)? {
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Checks if a peer has a permission at a [`Timestamp`]. Grants that expired at the
+    /// timestamp are inactive. Changes are checked at their timestamp, so that all replicas
+    /// join the same changes.
+    pub fn can(&self, peer: PeerId, perm: Permission, path: Path, now: Timestamp) -> Result<bool> {
+        self.check(peer, perm, path, now)
+    }
+
+    /// Checks if a peer has a permission at the local time.
+    pub fn can_now(&self, peer: PeerId, perm: Permission, path: Path) -> Result<bool> {
+        self.check(peer, perm, path, self.clock.now())
+    }
+
+    /// Explains the access decision of [`Acl::can_now`] by tracing the derivation of the
+    /// statements granting access.
+    pub fn explain(&self, peer: PeerId, perm: Permission, path: Path) -> Result<Explanation> {
+        let granted = self.can_now(peer, perm, path)?;
+        let doc = path.first().unwrap().doc().unwrap();
+        if peer == doc.into() {
+            return Ok(Explanation {
+                granted,
+                steps: vec![],
+            });
+        }
+        let query = CanRef {
+            actor: Actor::Peer(peer),
+            perm,
+            path,
+        };
+        let guard = self.policy.read();
+        let creators = self.creators.read();
+        let docs = scope(&guard, doc);
+        let policy: Vec<&Says> = docs
+            .iter()
+            .filter_map(|doc| guard.get(doc))
+            .flatten()
+            .collect();
+        let relations = Relations::evaluate(
+            policy.iter().copied(),
+            docs.iter()
+                .filter_map(|doc| creators.get(doc))
+                .flatten()
+                .map(|(entry, peer)| (*peer, entry.as_path())),
+        );
+        let Relations {
+            authorized,
+            revoked,
+            derivations,
+            conditions,
+            revocations,
+            groups,
+            created,
+            device_grants,
+            rotation_grants,
+            ..
+        } = relations;
+        let expiries = expiries(&policy, &derivations);
+        let now = self.clock.now();
+        let inactive: BTreeSet<Dot> = expiries
+            .iter()
+            .filter(|(_, expiry)| **expiry <= now)
+            .map(|(id, _)| *id)
+            .chain(revoked.iter().copied())
+            .collect();
+        let conditions: BTreeMap<Dot, Dot> = conditions
+            .into_iter()
+            .map(|ConditionMet(id, _, _, auth)| (id, auth))
+            .collect();
+        let revocations: BTreeMap<Dot, Dot> = revocations
+            .into_iter()
+            .map(|Revocation(id, _, auth)| (id, auth))
+            .collect();
+
+        // shortest derivation of each authorized statement
+        let mut shortest: BTreeMap<Dot, (usize, Inference, Option<Dot>)> = BTreeMap::new();
+        loop {
+            let mut changed = false;
+            for Derivation(id, _, _, inference, premise) in &derivations {
+                let depth = match premise {
+                    Some(premise) => match shortest.get(premise) {
+                        Some((depth, _, _)) => depth + 1,
+                        None => continue,
+                    },
+                    None => 0,
+                };
+                if shortest.get(id).map(|(d, _, _)| depth < *d).unwrap_or(true) {
+                    shortest.insert(*id, (depth, *inference, *premise));
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let statements: BTreeMap<Dot, Statement> = {
+            let evaluation = Evaluation {
+                authorized: authorized.iter().map(|a| a.0).collect(),
+                revoked: revoked.clone(),
+                expiries,
+            };
+            policy
+                .iter()
+                .filter_map(|says| Statement::new(says, &evaluation, now))
+                .filter(|st| st.kind == StatementKind::Grant)
+                .map(|st| (st.id, st))
+                .collect()
+        };
+        let step = |id: Dot, inference, premise| Step {
+            statement: statements[&id].clone(),
+            inference,
+            premise,
+            condition: conditions.get(&id).copied(),
+        };
+
+        let grant = authorized
+            .iter()
+            .filter(|Authorized(id, _, can)| !inactive.contains(id) && can.implies(query))
+            .map(|Authorized(id, _, _)| *id)
+            .chain(
+                groups
+                    .iter()
+                    .filter(|GroupGrant(id, can, member)| {
+                        !inactive.contains(id) && !inactive.contains(member) && can.implies(query)
+                    })
+                    .map(|GroupGrant(id, _, _)| *id),
+            )
+            .chain(
+                created
+                    .iter()
+                    .filter(|CreatorGrant(id, can)| !inactive.contains(id) && can.implies(query))
+                    .map(|CreatorGrant(id, _)| *id),
+            )
+            .chain(
+                device_grants
+                    .iter()
+                    .filter(|DeviceGrant(id, can, device)| {
+                        !inactive.contains(id) && !inactive.contains(device) && can.implies(query)
+                    })
+                    .map(|DeviceGrant(id, _, _)| *id),
+            )
+            .chain(
+                rotation_grants
+                    .iter()
+                    .filter(|RotationGrant(id, can, rotation)| {
+                        !inactive.contains(id) && !inactive.contains(rotation) && can.implies(query)
+                    })
+                    .map(|RotationGrant(id, _, _)| *id),
+            )
+            .filter_map(|id| Some((shortest.get(&id)?.0, id)))
+            .min();
+        let mut steps = vec![];
+        if let Some((_, id)) = grant {
+            let mut next = Some(id);
+            while let Some(id) = next {
+                let (_, inference, premise) = shortest[&id];
+                steps.push(step(id, Some(inference), premise));
+                next = premise;
+            }
+        } else {
+            for st in statements.values() {
+                let can = st.can.as_ref();
+                let can = if can.actor == Actor::Unbound {
+                    can.bind(query)
+                } else {
+                    can
+                };
+                if !can.implies(query) {
+                    continue;
+                }
+                match st.status {
+                    PolicyStatus::Revoked => steps.push(step(
+                        st.id,
+                        Some(Inference::Revocation),
+                        revocations.get(&st.id).copied(),
+                    )),
+                    PolicyStatus::Expired => steps.push(step(st.id, Some(Inference::Expiry), None)),
+                    PolicyStatus::Unauthorized => steps.push(step(st.id, None, None)),
+                    PolicyStatus::Authorized => {}
+                }
+            }
+        }
+        Ok(Explanation { granted, steps })
+    }
+}
+
+struct AclDebug<'a>(&'a BlobMap);
+
+impl<'a> std::fmt::Debug for AclDebug<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut m = f.debug_map();
         for (k, v) in self.0.iter() {
@@ -396,155 +1907,890 @@ impl<'a> std::fmt::Debug for AclDebug<'a> {
 
 impl std::fmt::Debug for Acl {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        AclDebug(&self.0).fmt(f)
+        AclDebug(&self.rules).fmt(f)
     }
 }
 
+/// Rule with the statement it was derived from.
+type DerivedRule = (Dot, Permission);
+
 pub struct Engine {
-    policy: BTreeSet<Says>,
     acl: Acl,
+    store: BlobSet,
+    /// Documents with new policy statements or entries since the last evaluation.
+    dirty: BTreeSet<DocId>,
+    /// Tables and arrays with statements granting permissions to the creators of entries.
+    tables: BTreeMap<DocId, BTreeSet<PathBuf>>,
+    /// Documents with conditional statements referencing a document.
+    dependents: BTreeMap<DocId, BTreeSet<DocId>>,
+    /// Rules derived by the last evaluation of a document by their key.
+    derived: BTreeMap<DocId, BTreeMap<PathBuf, DerivedRule>>,
+    /// Store paths of quarantined peers to tombstone.
+    purge: BTreeMap<DocId, DotStore>,
 }
 
 impl Engine {
-    pub fn new(acl: Acl) -> Result<Self> {
+    pub fn new(acl: Acl, store: BlobSet) -> Result<Self> {
         Ok(Self {
-            policy: Default::default(),
             acl,
+            store,
+            dirty: Default::default(),
+            tables: Default::default(),
+            dependents: Default::default(),
+            derived: Default::default(),
+            purge: Default::default(),
         })
     }
 
-    pub fn active_peer(&self, peer: &PeerId) -> bool {
-        self.acl.active_peer(peer)
-    }
+    pub fn active_peer(&self, peer: &PeerId) -> bool {
+        self.acl.active_peer(peer)
+    }
+
+    /// Sets the time used to judge the expiry of statements.
+    #[cfg(test)]
+    pub(crate) fn set_time(&self, now: Timestamp) {
+        self.acl.clock.set(now);
+    }
+
+    /// Returns the stored rules.
+    #[cfg(test)]
+    pub(crate) fn rules(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.acl
+            .rules
+            .iter()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect()
+    }
+
+    pub fn add_policy(&mut self, path: Path) {
+        let (doc, says) = if let Some(says) = Says::from_path(path) {
+            says
+        } else {
+            self.add_entry(path);
+            return;
+        };
+        match &says {
+            Says::CanIf(_, _, _, cond, _) => {
+                let root = cond.as_ref().root();
+                if root != doc {
+                    self.dependents.entry(root).or_default().insert(doc);
+                }
+            }
+            Says::Can(_, _, can, _) if can.actor == Actor::Creator => {
+                self.tables.entry(doc).or_default().insert(can.path.clone());
+            }
+            _ => {}
+        }
+        if self.acl.policy.write().entry(doc).or_default().insert(says) {
+            self.dirty.insert(doc);
+        }
+    }
+
+    /// Removes a path that is no longer in the store.
+    pub fn remove_policy(&mut self, path: Path) {
+        let (doc, says) = if let Some(says) = Says::from_path(path) {
+            says
+        } else {
+            self.remove_entry(path);
+            return;
+        };
+        let removed = self
+            .acl
+            .policy
+            .write()
+            .get_mut(&doc)
+            .map(|policy| policy.remove(&says))
+            .unwrap_or_default();
+        if removed {
+            self.index(doc);
+            self.dirty.insert(doc);
+        }
+    }
+
+    /// Reindexes the creator statements and conditional statements of a document.
+    fn index(&mut self, doc: DocId) {
+        self.tables.remove(&doc);
+        for dependents in self.dependents.values_mut() {
+            dependents.remove(&doc);
+        }
+        let policy = self.acl.policy.read();
+        for says in policy.get(&doc).into_iter().flatten() {
+            match says {
+                Says::CanIf(_, _, _, cond, _) => {
+                    let root = cond.as_ref().root();
+                    if root != doc {
+                        self.dependents.entry(root).or_default().insert(doc);
+                    }
+                }
+                Says::Can(_, _, can, _) if can.actor == Actor::Creator => {
+                    self.tables.entry(doc).or_default().insert(can.path.clone());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Marks the document dirty if the path belonged to an entry with a known creator.
+    fn remove_entry(&mut self, path: Path) {
+        let doc = if let Some(doc) = path.first().and_then(|segment| segment.doc()) {
+            doc
+        } else {
+            return;
+        };
+        let creators = self.acl.creators.read();
+        for table in self.tables.get(&doc).into_iter().flatten() {
+            if let Some(entry) = entry_of(table.as_path(), path) {
+                if creators
+                    .get(&doc)
+                    .map(|creators| creators.contains_key(&entry))
+                    .unwrap_or_default()
+                {
+                    self.dirty.insert(doc);
+                }
+            }
+        }
+    }
+
+    /// Forgets the policy of all documents, which need to be added again.
+    pub fn reset(&mut self) {
+        self.acl.policy.write().clear();
+        self.acl.creators.write().clear();
+        self.tables.clear();
+        self.dependents.clear();
+        self.dirty.clear();
+        self.purge.clear();
+    }
+
+    /// Marks the document dirty if the path creates a new entry of a table or array with
+    /// creator statements.
+    fn add_entry(&mut self, path: Path) {
+        let doc = if let Some(doc) = path.first().and_then(|segment| segment.doc()) {
+            doc
+        } else {
+            return;
+        };
+        let creators = self.acl.creators.read();
+        for table in self.tables.get(&doc).into_iter().flatten() {
+            if let Some(entry) = entry_of(table.as_path(), path) {
+                if !creators
+                    .get(&doc)
+                    .map(|creators| creators.contains_key(&entry))
+                    .unwrap_or_default()
+                {
+                    self.dirty.insert(doc);
+                }
+            }
+        }
+    }
+
+    /// Evaluates the documents with new policy statements or entries and the documents
+    /// depending on them.
+    pub fn update_acl(&mut self) -> Result<()> {
+        let mut docs = BTreeSet::new();
+        let mut stack: Vec<DocId> = std::mem::take(&mut self.dirty).into_iter().collect();
+        while let Some(doc) = stack.pop() {
+            if docs.insert(doc) {
+                if let Some(dependents) = self.dependents.get(&doc) {
+                    stack.extend(dependents.iter().copied());
+                }
+            }
+        }
+        for doc in docs {
+            self.update_doc(doc)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the store paths of quarantined peers to tombstone since the last call.
+    pub fn take_purge(&mut self) -> BTreeMap<DocId, DotStore> {
+        std::mem::take(&mut self.purge)
+    }
+
+    /// Returns the time until the next grant expires.
+    pub fn next_expiry(&self) -> Option<Duration> {
+        let now = self.acl.clock.now();
+        self.acl
+            .expiring
+            .read()
+            .values()
+            .flat_map(|rules| rules.values())
+            .flat_map(|lifetime| lifetime.until.iter().map(|(_, expiry)| *expiry))
+            .filter(|expiry| *expiry > now)
+            .min()
+            .map(|expiry| now.until(expiry))
+    }
+
+    /// Evaluates the documents with stored rules of grants that expired.
+    pub fn expire(&mut self) -> Result<()> {
+        let now = self.acl.clock.now();
+        for (doc, rules) in self.acl.expiring.read().iter() {
+            let derived = self.derived.get(doc);
+            let expired = rules.iter().any(|(key, lifetime)| {
+                let stored = derived.and_then(|derived| derived.get(key));
+                lifetime.strongest(now) != stored.map(|(_, perm)| *perm)
+            });
+            if expired {
+                self.dirty.insert(*doc);
+            }
+        }
+        self.update_acl()
+    }
+
+    fn update_doc(&mut self, doc: DocId) -> Result<()> {
+        let mut creators = BTreeMap::new();
+        for table in self.tables.get(&doc).into_iter().flatten() {
+            let keys: Vec<_> = self.store.scan_prefix(table).collect();
+            add_creators(
+                &mut creators,
+                table.as_path(),
+                keys.iter().map(|k| Path::new(k)),
+            );
+        }
+        self.acl.creators.write().insert(doc, creators);
+
+        let policy = self.acl.policy.read();
+        let creators = self.acl.creators.read();
+        let mut says = vec![];
+        let mut paths = vec![];
+        for doc in scope(&policy, doc) {
+            says.extend(policy.get(&doc).into_iter().flatten());
+            paths.extend(
+                creators
+                    .get(&doc)
+                    .into_iter()
+                    .flatten()
+                    .map(|(entry, peer)| (*peer, entry.as_path())),
+            );
+        }
+        let Relations {
+            authorized,
+            revoked,
+            derivations,
+            members,
+            groups,
+            created,
+            tokens,
+            redemptions,
+            devices,
+            device_grants,
+            rotations,
+            rotation_grants,
+            quarantines,
+            ..
+        } = Relations::evaluate(says.iter().copied(), paths);
+        let expiries = expiries(&says, &derivations);
+        let evaluation = Evaluation {
+            authorized: authorized
+                .iter()
+                .map(|a| a.0)
+                .chain(members.iter().map(|m| m.0))
+                .chain(tokens.iter().map(|t| t.0))
+                .chain(devices.iter().map(|d| d.0))
+                .collect(),
+            revoked: revoked.clone(),
+            expiries: expiries.clone(),
+        };
+        self.acl.evaluations.write().insert(doc, evaluation);
+        let authorized = authorized
+            .into_iter()
+            .filter(|Authorized(id, _, can)| {
+                !matches!(can.actor, Actor::Group(_) | Actor::Creator) && !revoked.contains(id)
+            })
+            .map(|Authorized(id, _, can)| (id, can));
+        let groups = groups
+            .into_iter()
+            .filter(|GroupGrant(id, _, member)| !revoked.contains(id) && !revoked.contains(member))
+            .map(|GroupGrant(id, can, _)| (id, can));
+        let created = created
+            .into_iter()
+            .filter(|CreatorGrant(id, _)| !revoked.contains(id))
+            .map(|CreatorGrant(id, can)| (id, can));
+        let rotation_grants = rotation_grants
+            .into_iter()
+            .filter(|RotationGrant(id, _, rotation)| {
+                !revoked.contains(id) && !revoked.contains(rotation)
+            })
+            .map(|RotationGrant(id, can, _)| (id, can));
+        // rotated keys lose their permissions, even if the rotation is revoked
+        let rotated: BTreeSet<Actor> = rotations
+            .into_iter()
+            .filter(|Rotation(_, _, _, path)| root(*path) == doc)
+            .map(|Rotation(_, peer, _, _)| Actor::Peer(peer))
+            .collect();
+        // certified devices inherit the grants of their root key
+        let device_grants = device_grants
+            .into_iter()
+            .filter(|DeviceGrant(id, _, _)| !revoked.contains(id))
+            .map(|DeviceGrant(id, can, _)| (id, can));
+        let rules: BTreeSet<_> = authorized
+            .chain(groups)
+            .chain(created)
+            .chain(redeemed(redemptions, &revoked))
+            .chain(rotation_grants)
+            .chain(device_grants)
+            .filter(|(_, can)| can.root() == doc && !rotated.contains(&can.actor))
+            .map(|(id, can)| {
+                let expiry = expiries.get(&id).copied();
+                (id, can.actor, can.perm, can.path.to_owned(), expiry)
+            })
+            .collect();
+        // the strongest rule of an actor on a path in effect at the local time is stored, the
+        // expiring grants are kept to check changes at their timestamp
+        let now = self.acl.clock.now();
+        let mut derived: BTreeMap<PathBuf, DerivedRule> = BTreeMap::new();
+        let mut lifetimes: BTreeMap<PathBuf, Expiring> = BTreeMap::new();
+        for (id, actor, perm, path, expiry) in rules {
+            let key = Acl::rule_key(actor, path.as_path());
+            let lifetime = lifetimes.entry(key.clone()).or_default();
+            match expiry {
+                Some(expiry) => lifetime.until.push((perm, expiry)),
+                None => lifetime.permanent = lifetime.permanent.max(Some(perm)),
+            }
+            if expiry.map(|expiry| expiry <= now).unwrap_or_default() {
+                continue;
+            }
+            match derived.get(&key) {
+                Some((id2, perm2)) if (*perm2, *id2) >= (perm, id) => {}
+                _ => {
+                    derived.insert(key, (id, perm));
+                }
+            }
+        }
+        lifetimes.retain(|_, lifetime| !lifetime.until.is_empty());
+        self.acl.expiring.write().insert(doc, lifetimes);
+        let prev = match self.derived.remove(&doc) {
+            Some(prev) => prev,
+            None => self.acl.doc_rules(&doc),
+        };
+        for key in prev.keys() {
+            if !derived.contains_key(key) {
+                self.acl.rules.remove(key)?;
+            }
+        }
+        for (key, rule) in &derived {
+            if prev.get(key) != Some(rule) {
+                let (id, perm) = *rule;
+                self.acl.rules.insert_archived(key, &Rule::new(id, perm))?;
+            }
+        }
+        self.derived.insert(doc, derived);
+
+        let statements = policy.get(&doc).into_iter().flatten();
+        // a quarantine applies once all of it's keep statements are known
+        let quarantines: Vec<Quarantine> = statements
+            .clone()
+            .filter_map(|says| match says {
+                Says::Quarantines(id, issuer, peer, hash, path) if quarantines.contains(id) => {
+                    let keep: BTreeSet<Dot> = statements
+                        .clone()
+                        .filter_map(|says| match says {
+                            Says::Keeps(_, issuer2, hash2, kept)
+                                if issuer2 == issuer && Some(hash2) == hash.as_ref() =>
+                            {
+                                Some(*kept)
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    if hash.is_some() && hash != &Some(keep_hash(&keep)) {
+                        return None;
+                    }
+                    Some(Quarantine {
+                        peer: *peer,
+                        path: path.clone(),
+                        keep,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        // the paths of quarantined peers are purged with tombstones and rejected by joins
+        // from now on
+        let purge: DotStore = quarantines
+            .iter()
+            .flat_map(|quarantine| {
+                self.store
+                    .scan_prefix(&quarantine.path)
+                    .filter(|key| quarantine.contains(Path::new(&key[..])))
+                    .map(|key| Path::new(&key[..]).to_owned())
+            })
+            .collect();
+        if !purge.is_empty() {
+            self.purge.insert(doc, purge);
+        }
+        self.acl.quarantines.write().insert(doc, quarantines);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Backend, Event, Keypair};
+    use futures::prelude::*;
+    use std::pin::Pin;
+    use Permission::*;
+
+    #[async_std::test]
+    async fn test_la_says_can() -> Result<()> {
+        let mut sdk = Backend::test(
+            r#"acl {
+            0.1.0 {
+                .: Struct
+                .contacts: EWFlag
+            }
+        }"#,
+        )?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+
+        assert!(!doc.cursor().can(&b, Read)?);
+
+        assert!(doc.cursor().can(&a, Write)?);
+        assert!(doc.cursor().can(&a, Read)?);
+        assert!(doc.cursor().can(&a, Own)?);
+
+        assert!(doc.cursor().field("contacts")?.can(&a, Write)?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_says_if() -> Result<()> {
+        let mut sdk = Backend::test("acl {}")?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc1 = fut.await;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc2 = fut.await;
+
+        let cond = doc1.cursor().cond(Actor::Peer(b), Read);
+        let op = doc2.cursor().say_can_if(Actor::Peer(b), Write, cond)?;
+        doc2.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(!doc2.cursor().can(&b, Read)?);
+
+        let op = doc1.cursor().say_can(Some(b), Write)?;
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc2.cursor().can(&b, Read)?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_says_if_unbound() -> Result<()> {
+        let mut sdk = Backend::test("acl {}")?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc1 = fut.await;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc2 = fut.await;
+
+        let cond = doc1.cursor().cond(Actor::Unbound, Read);
+        let op = doc2.cursor().say_can_if(Actor::Unbound, Write, cond)?;
+        doc2.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(!doc2.cursor().can(&b, Read)?);
+
+        let op = doc1.cursor().say_can(Some(b), Write)?;
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc2.cursor().can(&b, Read)?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_own_and_control() -> Result<()> {
+        let mut sdk = Backend::test("acl {}")?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let c = sdk.frontend().generate_keypair()?;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+
+        let op = doc.cursor().say_can(Some(b), Control)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().can(&b, Control)?);
+
+        let bdoc = sdk.frontend().doc_as(*doc.id(), &b)?;
+        assert!(bdoc.cursor().say_can(Some(c), Control).is_err());
+
+        let op = bdoc.cursor().say_can(Some(c), Read)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().can(&c, Read)?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_anonymous_can() -> Result<()> {
+        let mut sdk = Backend::test("acl {}")?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+
+        let op = doc.cursor().say_can(None, Read)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().can(&b, Read)?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_revoke() -> Result<()> {
+        let mut sdk = Backend::test("acl {}")?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+
+        let op = doc.cursor().say_can(Some(b), Write)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().can(&b, Write)?);
+
+        let op = doc
+            .cursor()
+            .revoke(op.store.iter().next().unwrap().as_path().dot())?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(!doc.cursor().can(&b, Write)?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_cant_revoke_inv() -> Result<()> {
+        let mut sdk = Backend::test("acl {}")?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+
+        let op = doc.cursor().say_can(Some(b), Own)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().can(&b, Own)?);
 
-    pub fn add_policy(&mut self, path: Path) {
-        self._add_policy(path);
-    }
+        let bdoc = sdk.frontend().doc_as(*doc.id(), &b)?;
+        let op = bdoc
+            .cursor()
+            .revoke(op.store.iter().next().unwrap().as_path().dot())?;
+        doc.apply(&op)?;
 
-    fn _add_policy(&mut self, path: Path) -> Option<()> {
-        let dot = path.dot();
-        // schema.doc.(primitive|str)*.policy.peer.sig
-        let (path, _) = path.split_last()?;
-        let (path, peer) = path.split_last()?;
-        let (path, policy) = path.split_last()?;
-        let peer = peer.peer()?;
-        let policy = policy.policy()?;
-        let path = path.to_owned();
-        let says = match policy {
-            Policy::Can(actor, perm) => Says::Can(dot, peer, Can::new(actor, perm, path)),
-            Policy::CanIf(actor, perm, cond) => {
-                Says::CanIf(dot, peer, Can::new(actor, perm, path), cond)
-            }
-            Policy::Revokes(dot) => Says::Revokes(peer, dot),
-        };
-        self.policy.insert(says);
-        None
-    }
+        assert!(doc.cursor().can(&a, Own)?);
 
-    pub fn update_acl(&self) -> Result<()> {
-        let mut runtime = Crepe::new();
-        runtime.extend(self.policy.iter().map(Input));
-        let (authorized, revoked) = runtime.run();
-        let revoked: BTreeSet<Dot> = revoked.into_iter().map(|r| r.0).collect();
-        for Authorized(id, _, CanRef { actor, perm, path }) in authorized.into_iter() {
-            if !revoked.contains(&id) {
-                self.acl.add_rule(id, actor, perm, path)?;
-            }
-        }
-        self.acl.revoke_rules(revoked)?;
         Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{Backend, Keypair};
-    use std::pin::Pin;
-    use Permission::*;
 
     #[async_std::test]
-    async fn test_la_says_can() -> Result<()> {
+    async fn test_policies() -> Result<()> {
         let mut sdk = Backend::test(
             r#"acl {
             0.1.0 {
                 .: Struct
                 .contacts: EWFlag
+                .notes: EWFlag
             }
         }"#,
         )?;
         let a = sdk.frontend().generate_keypair()?;
         let b = sdk.frontend().generate_keypair()?;
-
         let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
         Pin::new(&mut sdk).await?;
         let doc = fut.await;
 
-        assert!(!doc.cursor().can(&b, Read)?);
+        let policies = doc.cursor().policies();
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].issuer(), PeerId::from(*doc.id()));
+        assert_eq!(policies[0].actor(), Actor::Peer(a));
+        assert_eq!(policies[0].perm(), Own);
+        assert_eq!(policies[0].status(), PolicyStatus::Authorized);
 
-        assert!(doc.cursor().can(&a, Write)?);
-        assert!(doc.cursor().can(&a, Read)?);
-        assert!(doc.cursor().can(&a, Own)?);
+        let op = doc.cursor().field("contacts")?.say_can(Some(b), Write)?;
+        doc.apply(&op)?;
+        let cond = doc.cursor().field("contacts")?.cond(Actor::Peer(b), Own);
+        let op = doc
+            .cursor()
+            .field("notes")?
+            .say_can_if(Actor::Peer(b), Read, cond)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+
+        let policies = doc.cursor().field("contacts")?.policies();
+        assert_eq!(policies.len(), 2);
+        let grant = policies
+            .iter()
+            .find(|st| st.actor() == Actor::Peer(b))
+            .unwrap();
+        assert_eq!(grant.issuer(), a);
+        assert_eq!(grant.perm(), Write);
+        assert_eq!(grant.status(), PolicyStatus::Authorized);
+        let id = grant.id();
+
+        let policies = doc.cursor().field("notes")?.policies();
+        let grant = policies.iter().find(|st| st.cond().is_some()).unwrap();
+        assert_eq!(grant.status(), PolicyStatus::Unauthorized);
+
+        let op = doc.cursor().field("contacts")?.revoke(id)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        let policies = doc.cursor().field("contacts")?.policies();
+        let grant = policies
+            .iter()
+            .find(|st| st.actor() == Actor::Peer(b))
+            .unwrap();
+        assert_eq!(grant.status(), PolicyStatus::Revoked);
+        assert!(!doc.cursor().field("contacts")?.can(&b, Write)?);
 
-        assert!(doc.cursor().field("contacts")?.can(&a, Write)?);
         Ok(())
     }
 
     #[async_std::test]
-    async fn test_says_if() -> Result<()> {
+    async fn test_explain() -> Result<()> {
         let mut sdk = Backend::test("acl {}")?;
         let a = sdk.frontend().generate_keypair()?;
         let b = sdk.frontend().generate_keypair()?;
+        let c = sdk.frontend().generate_keypair()?;
         let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
         Pin::new(&mut sdk).await?;
-        let doc1 = fut.await;
+        let doc = fut.await;
+
+        let op = doc.cursor().say_can(Some(b), Control)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        let bdoc = sdk.frontend().doc_as(*doc.id(), &b)?;
+        let op = bdoc.cursor().say_can(Some(c), Read)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        let grant = op.store.iter().next().unwrap().as_path().dot();
+
+        let explanation = doc.cursor().explain(&c, Read)?;
+        assert!(explanation.granted());
+        let steps = explanation.steps();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0].statement().id(), grant);
+        assert_eq!(steps[0].inference(), Some(Inference::Control));
+        assert_eq!(steps[0].premise(), Some(steps[1].statement().id()));
+        assert_eq!(steps[1].statement().issuer(), a);
+        assert_eq!(steps[1].inference(), Some(Inference::Ownership));
+        assert_eq!(steps[1].premise(), Some(steps[2].statement().id()));
+        assert_eq!(steps[2].inference(), Some(Inference::LocalAuthority));
+        assert_eq!(steps[2].premise(), None);
+
+        let explanation = doc.cursor().explain(&c, Write)?;
+        assert!(!explanation.granted());
+        assert!(explanation.steps().is_empty());
+
+        let op = doc.cursor().revoke(grant)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        let explanation = doc.cursor().explain(&c, Read)?;
+        assert!(!explanation.granted());
+        let steps = explanation.steps();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].statement().id(), grant);
+        assert_eq!(steps[0].statement().status(), PolicyStatus::Revoked);
+        assert_eq!(steps[0].inference(), Some(Inference::Revocation));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_groups() -> Result<()> {
+        let mut sdk = Backend::test(
+            r#"acl {
+            0.1.0 {
+                .: Struct
+                .contacts: EWFlag
+                .notes: EWFlag
+            }
+        }"#,
+        )?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let c = sdk.frontend().generate_keypair()?;
         let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
         Pin::new(&mut sdk).await?;
-        let doc2 = fut.await;
+        let doc = fut.await;
+        let team = GroupId::from_name("team");
 
-        let cond = doc1.cursor().cond(Actor::Peer(b), Read);
-        let op = doc2.cursor().say_can_if(Actor::Peer(b), Write, cond)?;
-        doc2.apply(&op)?;
+        let op = doc.cursor().field("contacts")?.say_can_group(team, Write)?;
+        doc.apply(&op)?;
+        let op = doc.cursor().add_member(team, b)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
         Pin::new(&mut sdk).await?;
-        assert!(!doc2.cursor().can(&b, Read)?);
 
-        let op = doc1.cursor().say_can(Some(b), Write)?;
-        doc1.apply(&op)?;
+        assert_eq!(doc.cursor().field("contacts")?.members(team), vec![b]);
+        assert!(doc.cursor().field("contacts")?.can(&b, Write)?);
+        assert!(!doc.cursor().field("notes")?.can(&b, Write)?);
+        assert!(!doc.cursor().field("contacts")?.can(&c, Write)?);
+
+        let bdoc = sdk.frontend().doc_as(*doc.id(), &b)?;
+        assert!(bdoc.cursor().add_member(team, c).is_err());
+
+        let op = doc.cursor().remove_member(team, b)?;
+        doc.apply(&op)?;
         Pin::new(&mut sdk).await?;
-        assert!(doc2.cursor().can(&b, Read)?);
+
+        assert!(doc.cursor().members(team).is_empty());
+        assert!(!doc.cursor().field("contacts")?.can(&b, Write)?);
 
         Ok(())
     }
 
+    #[test]
+    fn test_permission_order() {
+        assert!(Read < Append && Append < Write && Write < Control && Control < Own);
+        assert_eq!(Own as u8, 3);
+        assert_eq!(Append as u8, 4);
+    }
+
     #[async_std::test]
-    async fn test_says_if_unbound() -> Result<()> {
-        let mut sdk = Backend::test("acl {}")?;
+    async fn test_append() -> Result<()> {
+        let mut sdk = Backend::test(
+            r#"acl {
+            0.1.0 {
+                .: Struct
+                .entries: Table<u64>
+                .entries.{}: EWFlag
+            }
+        }"#,
+        )?;
+        let hash = sdk.registry().lookup("acl").unwrap().1;
         let a = sdk.frontend().generate_keypair()?;
         let b = sdk.frontend().generate_keypair()?;
         let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
         Pin::new(&mut sdk).await?;
-        let doc1 = fut.await;
-        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        let doc = fut.await;
+
+        let op = doc.cursor().say_can(Some(b), Append)?;
+        doc.apply(&op)?;
         Pin::new(&mut sdk).await?;
-        let doc2 = fut.await;
+        assert!(doc.cursor().can(&b, Append)?);
+        assert!(doc.cursor().can(&b, Read)?);
+        assert!(!doc.cursor().can(&b, Write)?);
 
-        let cond = doc1.cursor().cond(Actor::Unbound, Read);
-        let op = doc2.cursor().say_can_if(Actor::Unbound, Write, cond)?;
-        doc2.apply(&op)?;
+        let op = doc.cursor().field("entries")?.key_u64(0)?.enable()?;
+        doc.apply(&op)?;
+        let bdoc = sdk.frontend().doc_as(*doc.id(), &b)?;
+        let op = bdoc.cursor().field("entries")?.key_u64(1)?.enable()?;
+        sdk.join(&b, doc.id(), &hash, op)?;
+        assert!(doc.cursor().field("entries")?.key_u64(1)?.enabled()?);
+
+        // others' entries can't be removed
+        let op = bdoc.cursor().field("entries")?.key_u64(0)?.disable()?;
+        assert!(op.is_empty());
+        let op = doc.cursor().field("entries")?.key_u64(0)?.disable()?;
+        sdk.join(&b, doc.id(), &hash, op)?;
+        assert!(doc.cursor().field("entries")?.key_u64(0)?.enabled()?);
+
+        // own entries can be removed
+        let op = bdoc.cursor().field("entries")?.key_u64(1)?.disable()?;
+        sdk.join(&b, doc.id(), &hash, op)?;
+        assert!(!doc.cursor().field("entries")?.key_u64(1)?.enabled()?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_creator_owns() -> Result<()> {
+        let mut sdk = Backend::test(
+            r#"acl {
+            0.1.0 {
+                .: Struct
+                .todos: Table<u64>
+                .todos.{}: Struct
+                .todos.{}.title: MVReg<String>
+            }
+        }"#,
+        )?;
+        let hash = sdk.registry().lookup("acl").unwrap().1;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let c = sdk.frontend().generate_keypair()?;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
         Pin::new(&mut sdk).await?;
-        assert!(!doc2.cursor().can(&b, Read)?);
+        let doc = fut.await;
 
-        let op = doc1.cursor().say_can(Some(b), Write)?;
-        doc1.apply(&op)?;
+        let op = doc.cursor().field("todos")?.say_can(None, Append)?;
+        doc.apply(&op)?;
         Pin::new(&mut sdk).await?;
-        assert!(doc2.cursor().can(&b, Read)?);
+        let op = doc.cursor().field("todos")?.say_creator_can(Own)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+
+        let bdoc = sdk.frontend().doc_as(*doc.id(), &b)?;
+        let op = bdoc
+            .cursor()
+            .field("todos")?
+            .key_u64(0)?
+            .field("title")?
+            .assign_str("b's todo")?;
+        sdk.join(&b, doc.id(), &hash, op)?;
+        let cdoc = sdk.frontend().doc_as(*doc.id(), &c)?;
+        let op = cdoc
+            .cursor()
+            .field("todos")?
+            .key_u64(1)?
+            .field("title")?
+            .assign_str("c's todo")?;
+        sdk.join(&c, doc.id(), &hash, op)?;
+
+        let mut todo = doc.cursor();
+        todo.field("todos")?.key_u64(0)?;
+        assert!(todo.can(&b, Own)?);
+        assert!(todo.can(&a, Own)?);
+        assert!(!todo.can(&c, Write)?);
+        let mut todo = doc.cursor();
+        todo.field("todos")?.key_u64(1)?;
+        assert!(todo.can(&c, Own)?);
+        assert!(!todo.can(&b, Write)?);
+
+        // the creator can delegate
+        let op = bdoc
+            .cursor()
+            .field("todos")?
+            .key_u64(0)?
+            .say_can(Some(c), Write)?;
+        sdk.join(&b, doc.id(), &hash, op)?;
+        let mut todo = doc.cursor();
+        todo.field("todos")?.key_u64(0)?;
+        assert!(todo.can(&c, Write)?);
 
         Ok(())
     }
 
     #[async_std::test]
-    async fn test_own_and_control() -> Result<()> {
-        let mut sdk = Backend::test("acl {}")?;
+    async fn test_token() -> Result<()> {
+        let mut sdk = Backend::test(
+            r#"acl {
+            0.1.0 {
+                .: Struct
+                .contacts: EWFlag
+            }
+        }"#,
+        )?;
+        let hash = sdk.registry().lookup("acl").unwrap().1;
         let a = sdk.frontend().generate_keypair()?;
         let b = sdk.frontend().generate_keypair()?;
         let c = sdk.frontend().generate_keypair()?;
@@ -552,84 +2798,268 @@ mod tests {
         Pin::new(&mut sdk).await?;
         let doc = fut.await;
 
-        let op = doc.cursor().say_can(Some(b), Control)?;
+        assert!(doc.cursor().field("contacts")?.say_token(Own, 1).is_err());
+        let (op, capability) = doc.cursor().field("contacts")?.say_token(Write, 1)?;
         doc.apply(&op)?;
         Pin::new(&mut sdk).await?;
-        assert!(doc.cursor().can(&b, Control)?);
+        let capability: Capability = capability.to_string().parse()?;
+        assert_eq!(capability.doc(), *doc.id());
 
         let bdoc = sdk.frontend().doc_as(*doc.id(), &b)?;
-        assert!(bdoc.cursor().say_can(Some(c), Control).is_err());
+        let op = bdoc.cursor().redeem(&capability)?;
+        let redemption = op.redemption().unwrap();
+        sdk.join(&b, doc.id(), &hash, op)?;
+        assert!(!doc.cursor().field("contacts")?.can(&b, Write)?);
+
+        // the issuer accepts the redemption
+        assert!(sdk
+            .frontend()
+            .doc_as(*doc.id(), &c)?
+            .cursor()
+            .accept(redemption)
+            .is_err());
+        sdk.frontend().accept_redemption(doc.id(), redemption)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().field("contacts")?.can(&b, Write)?);
+        assert!(!doc.cursor().can(&b, Write)?);
+        assert!(doc.cursor().accept(redemption).is_err());
+
+        // the capability is used up
+        let cdoc = sdk.frontend().doc_as(*doc.id(), &c)?;
+        let op = cdoc.cursor().redeem(&capability)?;
+        let redemption = op.redemption().unwrap();
+        sdk.join(&c, doc.id(), &hash, op)?;
+        assert!(doc.cursor().accept(redemption).is_err());
+        assert!(!doc.cursor().field("contacts")?.can(&c, Write)?);
+        assert!(doc.cursor().field("contacts")?.can(&b, Write)?);
+
+        let token = |doc: &crate::Doc| -> Result<Statement> {
+            let policies = doc.cursor().field("contacts")?.policies();
+            Ok(policies
+                .into_iter()
+                .find(|st| st.id() == capability.id())
+                .unwrap())
+        };
+        assert_eq!(token(&doc)?.kind(), StatementKind::Token(1));
+        assert_eq!(token(&doc)?.status(), PolicyStatus::Authorized);
 
-        let op = bdoc.cursor().say_can(Some(c), Read)?;
+        let op = doc.cursor().field("contacts")?.revoke(capability.id())?;
         doc.apply(&op)?;
         Pin::new(&mut sdk).await?;
-        assert!(doc.cursor().can(&c, Read)?);
+        assert!(!doc.cursor().field("contacts")?.can(&b, Write)?);
+        assert_eq!(token(&doc)?.status(), PolicyStatus::Revoked);
 
         Ok(())
     }
 
     #[async_std::test]
-    async fn test_anonymous_can() -> Result<()> {
+    async fn test_device() -> Result<()> {
         let mut sdk = Backend::test("acl {}")?;
+        let hash = sdk.registry().lookup("acl").unwrap().1;
         let a = sdk.frontend().generate_keypair()?;
         let b = sdk.frontend().generate_keypair()?;
+        let d = sdk.frontend().generate_keypair()?;
         let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
         Pin::new(&mut sdk).await?;
         let doc = fut.await;
+        assert!(!doc.cursor().can(&d, Read)?);
 
-        let op = doc.cursor().say_can(None, Read)?;
-        doc.apply(&op)?;
+        let statements = sdk.frontend().link_device(&a, &d)?;
+        assert_eq!(statements.len(), 1);
         Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().can(&d, Own)?);
+        let policies = doc.cursor().policies();
+        let device = policies
+            .iter()
+            .find(|st| st.kind() == StatementKind::Device)
+            .unwrap();
+        assert_eq!(device.issuer(), a);
+        assert_eq!(device.actor(), Actor::Peer(d));
+        assert_eq!(device.status(), PolicyStatus::Authorized);
+
+        // the device acts as the root key
+        let op = sdk
+            .frontend()
+            .doc_as(*doc.id(), &d)?
+            .cursor()
+            .say_can(Some(b), Read)?;
+        sdk.join(&d, doc.id(), &hash, op)?;
         assert!(doc.cursor().can(&b, Read)?);
+
+        // devices can't certify devices of their own
+        let e = sdk.frontend().generate_keypair()?;
+        let op = sdk
+            .frontend()
+            .doc_as(*doc.id(), &d)?
+            .cursor()
+            .say_device(e)?;
+        sdk.join(&d, doc.id(), &hash, op)?;
+        assert!(!doc.cursor().can(&e, Read)?);
+
+        // documents created later on certify linked devices
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc2 = fut.await;
+        assert!(doc2.cursor().can(&d, Own)?);
+
+        // only the root key can unlink it's devices
+        let op = sdk
+            .frontend()
+            .doc_as(*doc.id(), &b)?
+            .cursor()
+            .unlink_device(d)?;
+        sdk.join(&b, doc.id(), &hash, op)?;
+        assert!(doc.cursor().can(&d, Own)?);
+
+        let statements = sdk.frontend().unlink_device(&a, &d)?;
+        assert_eq!(statements.len(), 2);
+        for _ in statements {
+            Pin::new(&mut sdk).await?;
+        }
+        assert!(!doc.cursor().can(&d, Read)?);
+        assert!(!doc2.cursor().can(&d, Read)?);
+        assert!(doc.cursor().can(&a, Own)?);
+        // statements of an unlinked device lose their authorization
+        assert!(!doc.cursor().can(&b, Read)?);
+
         Ok(())
     }
 
     #[async_std::test]
-    async fn test_revoke() -> Result<()> {
+    async fn test_rotate_keypair() -> Result<()> {
         let mut sdk = Backend::test("acl {}")?;
         let a = sdk.frontend().generate_keypair()?;
         let b = sdk.frontend().generate_keypair()?;
         let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
         Pin::new(&mut sdk).await?;
         let doc = fut.await;
-
         let op = doc.cursor().say_can(Some(b), Write)?;
         doc.apply(&op)?;
         Pin::new(&mut sdk).await?;
-        assert!(doc.cursor().can(&b, Write)?);
 
-        let op = doc
-            .cursor()
-            .revoke(op.store.iter().next().unwrap().as_path().dot())?;
-        doc.apply(&op)?;
+        // a write grant can't be delegated, so it isn't transferred
+        let (c, statements) = sdk.frontend().rotate_keypair(&b)?;
+        assert_eq!(statements.len(), 1);
         Pin::new(&mut sdk).await?;
+        assert!(!doc.cursor().can(&c, Write)?);
         assert!(!doc.cursor().can(&b, Write)?);
 
+        let e = sdk.frontend().generate_keypair()?;
+        let op = doc.cursor().say_can(Some(e), Own)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        let (f, statements) = sdk.frontend().rotate_keypair(&e)?;
+        assert_eq!(statements.len(), 1);
+        Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().can(&f, Own)?);
+        assert!(!doc.cursor().can(&e, Read)?);
+
+        let (d, statements) = sdk.frontend().rotate_keypair(&a)?;
+        assert_eq!(statements.len(), 1);
+        Pin::new(&mut sdk).await?;
+        assert_eq!(sdk.frontend().peer_id(doc.id())?, d);
+        assert!(doc.cursor().can(&d, Own)?);
+        assert!(!doc.cursor().can(&a, Own)?);
+        assert!(doc.cursor().can(&f, Own)?);
+
         Ok(())
     }
 
     #[async_std::test]
-    async fn test_cant_revoke_inv() -> Result<()> {
-        let mut sdk = Backend::test("acl {}")?;
+    async fn test_expiry() -> Result<()> {
+        let mut sdk = Backend::test(
+            r#"acl {
+            0.1.0 {
+                .: Struct
+                .contacts: EWFlag
+                .notes: EWFlag
+            }
+        }"#,
+        )?;
+        let hash = sdk.registry().lookup("acl").unwrap().1;
         let a = sdk.frontend().generate_keypair()?;
         let b = sdk.frontend().generate_keypair()?;
+        let c = sdk.frontend().generate_keypair()?;
         let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
         Pin::new(&mut sdk).await?;
         let doc = fut.await;
+        let now = Timestamp::new(1_000_000, 0);
+        sdk.set_time(now);
 
-        let op = doc.cursor().say_can(Some(b), Own)?;
+        let expiry = now.after(std::time::Duration::from_secs(60));
+        let op = doc.cursor().say_can_until(Some(b), Control, expiry)?;
         doc.apply(&op)?;
         Pin::new(&mut sdk).await?;
-        assert!(doc.cursor().can(&b, Own)?);
+        assert!(doc.cursor().can(&b, Write)?);
 
+        // grants derived from an expiring grant expire with it
         let bdoc = sdk.frontend().doc_as(*doc.id(), &b)?;
-        let op = bdoc
-            .cursor()
-            .revoke(op.store.iter().next().unwrap().as_path().dot())?;
-        doc.apply(&op)?;
+        let op = bdoc.cursor().say_can(Some(c), Read)?;
+        sdk.join(&b, doc.id(), &hash, op)?;
+        assert!(doc.cursor().can(&c, Read)?);
+        let grant = |peer: PeerId| -> Result<Statement> {
+            let policies = doc.cursor().policies();
+            Ok(policies
+                .into_iter()
+                .find(|st| st.actor() == Actor::Peer(peer))
+                .unwrap())
+        };
+        assert_eq!(grant(b)?.expiry(), Some(expiry));
+        assert_eq!(grant(b)?.status(), PolicyStatus::Authorized);
+        assert_eq!(grant(c)?.expiry(), Some(expiry));
+        let op = bdoc.cursor().field("contacts")?.enable()?;
+        let mut late = bdoc.cursor().field("notes")?.enable()?;
+        late.time = expiry;
+
+        // expired grants stop local changes
+        sdk.set_time(expiry);
+        assert!(!doc.cursor().can(&b, Write)?);
+        assert!(!doc.cursor().can(&c, Read)?);
+        assert!(bdoc.cursor().field("contacts")?.enable().is_err());
+        assert_eq!(grant(b)?.status(), PolicyStatus::Expired);
+        assert_eq!(grant(c)?.status(), PolicyStatus::Expired);
 
-        assert!(doc.cursor().can(&a, Own)?);
+        // joins don't depend on the clock of the replica
+        sdk.join(&b, doc.id(), &hash, op)?;
+        assert!(doc.cursor().field("contacts")?.enabled()?);
+
+        // changes made after the expiry are rejected
+        sdk.join(&b, doc.id(), &hash, late)?;
+        assert!(!doc.cursor().field("notes")?.enabled()?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_expiry_timer() -> Result<()> {
+        let mut sdk = Backend::test(
+            r#"acl {
+            0.1.0 {
+                .: Struct
+                .contacts: EWFlag
+            }
+        }"#,
+        )?;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let fut = sdk.frontend().create_doc(a, "acl", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+
+        let expiry = Timestamp::now().after(std::time::Duration::from_millis(50));
+        let op = doc.cursor().say_can_until(Some(b), Read, expiry)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(doc.cursor().can(&b, Read)?);
 
+        // the backend evaluates the rules again once the grant expired
+        let mut sub = doc.cursor().subscribe();
+        Pin::new(&mut sdk).await?;
+        assert!(!doc.cursor().can(&b, Read)?);
+        let batch = sub.next().now_or_never().flatten().unwrap();
+        assert!(batch
+            .into_iter()
+            .any(|ev| matches!(ev, Event::Revoked(_, Some(peer)) if peer == b)));
         Ok(())
     }
 }
@@ -0,0 +1,182 @@
+use bytecheck::CheckBytes;
+use rkyv::{Archive, Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Hybrid logical clock timestamp. The upper 48 bits are the milliseconds since the unix
+/// epoch and the lower 16 bits a logical counter.
+#[derive(
+    Clone,
+    Copy,
+    Default,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Archive,
+    CheckBytes,
+    Deserialize,
+    Serialize,
+)]
+#[archive(as = "Timestamp")]
+#[repr(transparent)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// Creates a new [`Timestamp`] from milliseconds since the unix epoch and a logical
+    /// counter.
+    pub fn new(millis: u64, counter: u16) -> Self {
+        Self(millis << 16 | counter as u64)
+    }
+
+    /// Returns the [`Timestamp`] of the physical clock.
+    pub fn now() -> Self {
+        #[cfg(target_arch = "wasm32")]
+        let millis = js_sys::Date::now() as u64;
+        #[cfg(not(target_arch = "wasm32"))]
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        Self::new(millis, 0)
+    }
+
+    /// Returns the milliseconds since the unix epoch.
+    pub fn millis(self) -> u64 {
+        self.0 >> 16
+    }
+
+    /// Returns the logical counter.
+    pub fn counter(self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Returns the [`Timestamp`] after a [`Duration`].
+    pub fn after(self, duration: Duration) -> Self {
+        Self::new(self.millis() + duration.as_millis() as u64, 0)
+    }
+
+    /// Returns the [`Duration`] until a later [`Timestamp`].
+    pub fn until(self, later: Timestamp) -> Duration {
+        Duration::from_millis(later.millis().saturating_sub(self.millis()))
+    }
+}
+
+impl From<Timestamp> for u64 {
+    fn from(ts: Timestamp) -> Self {
+        ts.0
+    }
+}
+
+impl From<u64> for Timestamp {
+    fn from(ts: u64) -> Self {
+        Self(ts)
+    }
+}
+
+impl std::fmt::Debug for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Timestamp({}.{})", self.millis(), self.counter())
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.millis(), self.counter())
+    }
+}
+
+/// Remote timestamps further ahead of the physical clock are not merged into the [`Clock`].
+const MAX_DRIFT: Duration = Duration::from_secs(60);
+
+/// Hybrid logical clock used to stamp changes and to judge the expiry of statements.
+/// Follows the physical clock unless a test sets the time.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Clock {
+    /// Last [`Timestamp`] issued or received.
+    last: Arc<AtomicU64>,
+    /// Physical time set by a test.
+    fixed: Arc<AtomicU64>,
+}
+
+impl Clock {
+    /// Returns the [`Timestamp`] of the physical clock.
+    fn physical(&self) -> Timestamp {
+        match self.fixed.load(Ordering::Relaxed) {
+            0 => Timestamp::now(),
+            now => Timestamp::from(now),
+        }
+    }
+
+    /// Returns the current [`Timestamp`] without advancing the clock.
+    pub fn now(&self) -> Timestamp {
+        self.physical()
+            .max(Timestamp::from(self.last.load(Ordering::Relaxed)))
+    }
+
+    /// Returns a new [`Timestamp`] for a local change. It is later than all timestamps
+    /// issued or received before.
+    pub fn tick(&self) -> Timestamp {
+        let physical = u64::from(self.physical());
+        let prev = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(physical.max(last + 1))
+            })
+            .unwrap();
+        Timestamp::from(physical.max(prev + 1))
+    }
+
+    /// Merges the [`Timestamp`] of a remote change, so that later local changes are stamped
+    /// after it.
+    pub fn update(&self, remote: Timestamp) {
+        let physical = self.physical();
+        if remote > physical.after(MAX_DRIFT) {
+            tracing::warn!("ignoring timestamp {} ahead of the clock", remote);
+            return;
+        }
+        let physical = u64::from(physical);
+        let remote = u64::from(remote);
+        self.last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(physical.max(last.max(remote) + 1))
+            })
+            .unwrap();
+    }
+
+    /// Sets the current [`Timestamp`].
+    #[cfg(test)]
+    pub fn set(&self, now: Timestamp) {
+        self.fixed.store(now.into(), Ordering::Relaxed);
+        self.last.store(now.into(), Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hlc() {
+        let clock = Clock::default();
+        let now = Timestamp::new(1_000, 0);
+        clock.set(now);
+        assert_eq!(clock.tick(), Timestamp::new(1_000, 1));
+        assert_eq!(clock.tick(), Timestamp::new(1_000, 2));
+
+        // remote timestamps ahead of the local clock are merged
+        clock.update(Timestamp::new(2_000, 5));
+        assert_eq!(clock.now(), Timestamp::new(2_000, 6));
+        assert_eq!(clock.tick(), Timestamp::new(2_000, 7));
+
+        // older remote timestamps only advance the counter
+        clock.update(Timestamp::new(500, 0));
+        assert_eq!(clock.tick(), Timestamp::new(2_000, 9));
+
+        // timestamps too far ahead are ignored
+        clock.update(now.after(MAX_DRIFT * 2));
+        assert_eq!(clock.tick(), Timestamp::new(2_000, 10));
+    }
+}
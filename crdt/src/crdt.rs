@@ -1,5 +1,7 @@
-use crate::acl::{Acl, Permission};
-use crate::dotset::DotSet;
+use crate::acl::{Acl, Explanation, Membership, Permission, Policy, Statement};
+use crate::clock::Timestamp;
+use crate::crypto::{Key, Keypair, SealedKey};
+use crate::dotset::{Dot, DotSet};
 use crate::id::{DocId, PeerId};
 use crate::lens::LensesRef;
 use crate::path::{Path, PathBuf};
@@ -7,8 +9,11 @@ use crate::radixdb::BlobSet;
 use crate::subscriber::Subscriber;
 use anyhow::Result;
 use bytecheck::CheckBytes;
+use parking_lot::RwLock;
 use rkyv::{Archive, Archived, Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::iter::FromIterator;
+use std::sync::Arc;
 use vec_collections::radix_tree::{AbstractRadixTree, AbstractRadixTreeMut, IterKey, RadixTree};
 
 #[derive(Clone, Default, Eq, PartialEq, Archive, Deserialize, Serialize)]
@@ -171,6 +176,8 @@ impl std::fmt::Debug for ArchivedCausalContext {
 pub struct Causal {
     pub(crate) store: DotStore,
     pub(crate) expired: DotStore,
+    /// Hybrid logical clock timestamp the transaction was created at.
+    pub(crate) time: Timestamp,
 }
 
 impl Causal {
@@ -184,11 +191,44 @@ impl Causal {
         &self.expired
     }
 
+    /// Returns the [`Timestamp`] the transaction was created at. Permissions of the peer
+    /// sending the transaction are checked at this time.
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
     /// Returns true if there are no dots.
     pub fn is_empty(&self) -> bool {
         self.store.is_empty() && self.expired.is_empty()
     }
 
+    /// Returns the [`Dot`] of the redemption statement if the transaction consists of a
+    /// single redemption statement.
+    pub fn redemption(&self) -> Option<Dot> {
+        let mut paths = self.store.iter();
+        let path = paths.next()?;
+        if paths.next().is_some() || !self.expired.is_empty() {
+            return None;
+        }
+        let path = path.as_path();
+        // schema.doc.(primitive|str)*.policy.peer.sig
+        let (rest, _) = path.split_last()?;
+        let (rest, _) = rest.split_last()?;
+        let (_, policy) = rest.split_last()?;
+        if let Policy::Redeems(_, _) = policy.policy()? {
+            Some(path.dot())
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the transaction contains a key statement.
+    pub(crate) fn has_key_statements(&self) -> bool {
+        self.store
+            .iter()
+            .any(|path| matches!(policy(path.as_path()), Some(Policy::Encrypts(_, _))))
+    }
+
     /// Computes the [`CausalContext`] of this transaction.
     pub fn ctx(&self) -> CausalContext {
         let mut ctx = CausalContext::new();
@@ -209,6 +249,7 @@ impl Causal {
         self.store.union(&that.store);
         self.expired.union(&that.expired);
         self.store.0.difference_with(&self.expired.0);
+        self.time = self.time.max(that.time);
     }
 
     /// Returns the difference of a transaction and a [`CausalContext`].
@@ -230,7 +271,11 @@ impl Causal {
                 store.insert(buf);
             }
         }
-        Self { expired, store }
+        Self {
+            expired,
+            store,
+            time: self.time,
+        }
     }
 
     /// Transforms a transaction so that it can be applied to a target document.
@@ -259,6 +304,7 @@ impl std::fmt::Debug for Causal {
         f.debug_struct("Causal")
             .field("store", &self.store)
             .field("expired", &self.expired)
+            .field("time", &self.time)
             .finish()
     }
 }
@@ -268,15 +314,58 @@ impl std::fmt::Debug for ArchivedCausal {
         f.debug_struct("Causal")
             .field("store", &self.store)
             .field("expired", &self.expired)
+            .field("time", &self.time)
             .finish()
     }
 }
 
+/// Returns the peer that authored a store path.
+pub(crate) fn author(path: Path) -> Option<PeerId> {
+    path.parent()?.last()?.peer()
+}
+
+/// Returns the dot of the store path of a tombstone.
+fn expired_dot(path: Path) -> Dot {
+    path.parent().unwrap().parent().unwrap().dot()
+}
+
+/// Returns the key of a store or expired path in the dot index. The key is prefixed by the
+/// document and dot, so that the paths of a dot are found without scanning the document.
+fn dot_key(path: Path, dot: &Dot) -> PathBuf {
+    let mut key = PathBuf::new();
+    key.doc(&path.first().unwrap().doc().unwrap());
+    key.dot(dot);
+    key.extend(path);
+    key
+}
+
+/// Returns the policy of a policy statement.
+fn policy(path: Path) -> Option<Policy> {
+    path.parent()?.parent()?.last()?.policy()
+}
+
+/// Key statement of a document.
+#[derive(Clone)]
+pub(crate) struct KeyStatement {
+    pub(crate) id: Dot,
+    pub(crate) issuer: PeerId,
+    pub(crate) path: PathBuf,
+    pub(crate) epoch: u64,
+    pub(crate) keys: Vec<SealedKey>,
+}
+
 #[derive(Clone)]
 pub struct Crdt {
     store: BlobSet,
     expired: BlobSet,
+    compacted: BlobSet,
+    /// Store and expired paths indexed by their document and dot.
+    dots: BlobSet,
     acl: Acl,
+    /// Key statements of the documents, read from the store on first use.
+    keys: Arc<RwLock<BTreeMap<DocId, Arc<Vec<KeyStatement>>>>>,
+    /// Keys of the documents unsealed by a reader.
+    keyrings: Arc<RwLock<BTreeMap<(DocId, PeerId), Arc<BTreeMap<Dot, Key>>>>>,
 }
 
 impl std::fmt::Debug for Crdt {
@@ -284,6 +373,7 @@ impl std::fmt::Debug for Crdt {
         f.debug_struct("Crdt")
             .field("store", &StoreDebug(&self.store))
             .field("expired", &ExpiredDebug(&self.expired))
+            .field("compacted", &self.compacted.keys().count())
             .field("acl", &self.acl)
             .finish()
     }
@@ -316,18 +406,160 @@ impl<'a> std::fmt::Debug for ExpiredDebug<'a> {
 }
 
 impl Crdt {
-    pub fn new(store: BlobSet, expired: BlobSet, acl: Acl) -> Self {
-        Self {
+    pub fn new(
+        store: BlobSet,
+        expired: BlobSet,
+        compacted: BlobSet,
+        dots: BlobSet,
+        acl: Acl,
+    ) -> Result<Self> {
+        let me = Self {
             store,
             expired,
+            compacted,
+            dots,
             acl,
+            keys: Default::default(),
+            keyrings: Default::default(),
+        };
+        // stores created before the index existed are indexed once
+        if me.dots.keys().next().is_none() {
+            for k in me.store.keys() {
+                me.dots.insert(dot_key(Path::new(&k), &Path::new(&k).dot()));
+            }
+            for k in me.expired.keys() {
+                me.dots
+                    .insert(dot_key(Path::new(&k), &expired_dot(Path::new(&k))));
+            }
+            me.dots.flush()?;
+        }
+        Ok(me)
+    }
+
+    fn insert_store(&self, path: Path) {
+        self.store.insert(path);
+        self.dots.insert(dot_key(path, &path.dot()));
+    }
+
+    fn remove_store(&self, path: Path) {
+        self.store.remove(path);
+        self.dots.remove(dot_key(path, &path.dot()));
+    }
+
+    fn insert_expired(&self, path: Path) {
+        self.expired.insert(path);
+        self.dots.insert(dot_key(path, &expired_dot(path)));
+    }
+
+    fn remove_expired(&self, path: Path) {
+        self.expired.remove(path);
+        self.dots.remove(dot_key(path, &expired_dot(path)));
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.dots.flush()?;
+        self.expired.flush()?;
+        self.store.flush()?;
+        Ok(())
+    }
+
+    /// Returns the key statements of a document.
+    pub(crate) fn key_statements(&self, doc: &DocId) -> Arc<Vec<KeyStatement>> {
+        if let Some(statements) = self.keys.read().get(doc) {
+            return statements.clone();
         }
+        let mut path = PathBuf::new();
+        path.doc(doc);
+        let statements: Arc<Vec<KeyStatement>> = Arc::new(
+            self.store
+                .scan_prefix(&path)
+                .filter_map(|k| {
+                    let path = Path::new(&k);
+                    let id = path.dot();
+                    // path.policy.peer.sig
+                    let (path, issuer) = path.parent()?.split_last()?;
+                    let (path, policy) = path.split_last()?;
+                    if let Some(Policy::Encrypts(epoch, keys)) = policy.policy() {
+                        Some(KeyStatement {
+                            id,
+                            issuer: issuer.peer()?,
+                            path: path.to_owned(),
+                            epoch,
+                            keys,
+                        })
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+        );
+        self.keys.write().insert(*doc, statements.clone());
+        statements
+    }
+
+    /// Returns the keys of a document sealed to a reader by the [`Dot`] of their key
+    /// statement. A key sealed again to a new reader keeps the path and epoch of it's key
+    /// statement, the reader gets the key of each statement with the same path and epoch.
+    pub(crate) fn keyring(&self, doc: &DocId, reader: Keypair) -> Arc<BTreeMap<Dot, Key>> {
+        let peer = reader.peer_id();
+        if let Some(keyring) = self.keyrings.read().get(&(*doc, peer)) {
+            return keyring.clone();
+        }
+        let statements = self.key_statements(doc);
+        let mut keyring: BTreeMap<Dot, Key> = statements
+            .iter()
+            .filter_map(|st| {
+                let sealed = st.keys.iter().find(|key| key.reader() == peer)?;
+                Some((st.id, sealed.unseal(reader).ok()?))
+            })
+            .collect();
+        for st in statements.iter() {
+            if keyring.contains_key(&st.id) {
+                continue;
+            }
+            let key = statements
+                .iter()
+                .filter(|st2| st2.path == st.path && st2.epoch == st.epoch)
+                .find_map(|st2| keyring.get(&st2.id).copied());
+            if let Some(key) = key {
+                keyring.insert(st.id, key);
+            }
+        }
+        let keyring = Arc::new(keyring);
+        self.keyrings.write().insert((*doc, peer), keyring.clone());
+        keyring
+    }
+
+    /// Drops the cached keys of a document.
+    fn invalidate_keys(&self, doc: &DocId) {
+        self.keys.write().remove(doc);
+        self.keyrings.write().retain(|(doc2, _), _| doc2 != doc);
+    }
+
+    /// Drops the cached keys of the document of a key statement.
+    fn add_key_statement(&self, path: Path) {
+        if let Some(Policy::Encrypts(_, _)) = policy(path) {
+            self.invalidate_keys(&path.first().unwrap().doc().unwrap());
+        }
+    }
+
+    /// Checks if the tombstone of a store path was compacted.
+    fn is_compacted(&self, path: Path) -> bool {
+        let doc = path.first().unwrap().doc().unwrap();
+        let mut key = PathBuf::new();
+        key.doc(&doc);
+        key.dot(&path.dot());
+        self.compacted.contains(key)
     }
 
     pub fn iter(&self) -> impl Iterator<Item = IterKey<u8>> {
         self.store.keys()
     }
 
+    pub fn contains(&self, path: Path) -> bool {
+        self.store.contains(path)
+    }
+
     pub fn scan_path(&self, path: Path) -> impl Iterator<Item = IterKey<u8>> {
         // ensures that it has a static lifetime.
         #[allow(clippy::unnecessary_to_owned)]
@@ -341,8 +573,59 @@ impl Crdt {
         )
     }
 
-    pub fn can(&self, peer: &PeerId, perm: Permission, path: Path) -> Result<bool> {
-        self.acl.can(*peer, perm, path)
+    pub fn can(&self, peer: &PeerId, perm: Permission, path: Path, now: Timestamp) -> Result<bool> {
+        self.acl.can(*peer, perm, path, now)
+    }
+
+    pub fn can_now(&self, peer: &PeerId, perm: Permission, path: Path) -> Result<bool> {
+        self.acl.can_now(*peer, perm, path)
+    }
+
+    /// Returns the current [`Timestamp`] of the replica.
+    pub fn now(&self) -> Timestamp {
+        self.acl.clock().now()
+    }
+
+    /// Returns a new [`Timestamp`] for a local transaction.
+    pub fn tick(&self) -> Timestamp {
+        self.acl.clock().tick()
+    }
+
+    /// Updates the clock of the replica with the [`Timestamp`] of a received transaction.
+    pub fn update_clock(&self, remote: Timestamp) {
+        self.acl.clock().update(remote);
+    }
+
+    pub fn explain(&self, peer: &PeerId, perm: Permission, path: Path) -> Result<Explanation> {
+        self.acl.explain(*peer, perm, path)
+    }
+
+    pub fn has_rules(&self, peer: &PeerId, doc: &DocId) -> bool {
+        self.acl.has_rules(peer, doc)
+    }
+
+    pub fn readers(&self, path: Path) -> Vec<(PeerId, Dot)> {
+        self.acl.readers(path)
+    }
+
+    pub fn has_anonymous_reader(&self, path: Path) -> bool {
+        self.acl.has_anonymous_reader(path)
+    }
+
+    pub fn has_anonymous_readers(&self, doc: &DocId) -> bool {
+        self.acl.has_anonymous_readers(doc)
+    }
+
+    pub fn policies(&self, doc: &DocId) -> Vec<Statement> {
+        self.acl.statements(doc)
+    }
+
+    pub fn memberships(&self, doc: &DocId) -> Vec<Membership> {
+        self.acl.memberships(doc)
+    }
+
+    pub fn next_use(&self, doc: &DocId, redemption: Dot, issuer: &PeerId) -> Option<u32> {
+        self.acl.next_use(doc, redemption, issuer)
     }
 
     pub fn ctx(&self, doc: &DocId) -> Result<CausalContext> {
@@ -360,6 +643,38 @@ impl Crdt {
         Ok(ctx)
     }
 
+    /// Returns the [`CausalContext`] of the paths of a document a peer may read.
+    pub fn readable_ctx(&self, peer_id: &PeerId, doc: &DocId) -> Result<CausalContext> {
+        let now = self.now();
+        let mut ctx = CausalContext::new();
+        let mut path = PathBuf::new();
+        path.doc(doc);
+        for k in self.store.scan_prefix(&path) {
+            let path = Path::new(&k);
+            if self.can(peer_id, Permission::Read, path, now)? {
+                ctx.store.insert(path.dot());
+            }
+        }
+        for k in self.expired.scan_prefix(&path) {
+            let path = Path::new(&k);
+            if self.can(peer_id, Permission::Read, path, now)? {
+                ctx.expired
+                    .insert(path.parent().unwrap().parent().unwrap().dot());
+            }
+        }
+        Ok(ctx)
+    }
+
+    /// Returns the dots of the compacted tombstones of a document.
+    pub fn compacted(&self, doc: &DocId) -> DotSet {
+        let mut path = PathBuf::new();
+        path.doc(doc);
+        self.compacted
+            .scan_prefix(&path)
+            .filter_map(|k| Path::new(&k).last()?.dot())
+            .collect()
+    }
+
     pub fn join_policy(&self, causal: &Causal) -> Result<()> {
         for buf in causal.store.iter() {
             let path = buf.as_path();
@@ -374,44 +689,70 @@ impl Crdt {
                 .is_some()
             {
                 tracing::info!("join_policy: {}", path);
-                self.store.insert(path);
+                self.insert_store(path);
+                self.add_key_statement(path);
             }
         }
-        self.store.flush()?;
-        Ok(())
+        self.flush()
     }
 
     /// Applies a transaction. Uses the peer that sent the transaction for acl and not
     /// the peer that created the transaction. The reason for this is that the logic
     /// would be a little bit more complicated to ensure convergence in the presence of
-    /// revocations.
+    /// revocations. Peers with append permission can only remove paths they authored.
+    /// Permissions are checked at the [`Timestamp`] of the transaction.
     pub fn join(&self, peer: &PeerId, causal: &Causal) -> Result<()> {
         for buf in causal.store.iter() {
             let path = buf.as_path();
-            let is_expired = self.expired.scan_prefix(path.as_ref()).next().is_some();
+            let is_expired =
+                self.expired.scan_prefix(path.as_ref()).next().is_some() || self.is_compacted(path);
             if !is_expired && !causal.expired.contains_prefix(path) {
-                if !self.can(peer, Permission::Write, path)? {
+                if !self.can(peer, Permission::Append, path, causal.time)? {
                     tracing::info!("join: peer is unauthorized to insert {}", path);
                     continue;
                 }
-                self.store.insert(&path);
+                if self.acl.quarantined(path) {
+                    tracing::info!("join: path is quarantined {}", path);
+                    continue;
+                }
+                self.insert_store(path);
+                self.add_key_statement(path);
             }
         }
         for buf in causal.expired.iter() {
             let path = buf.as_path();
             let store_path = path.parent().unwrap().parent().unwrap();
-            if !self.can(peer, Permission::Write, store_path)? {
+            if self.is_compacted(store_path) {
+                tracing::info!("join: tombstone is compacted {}", store_path);
+                continue;
+            }
+            if !self.can(peer, Permission::Write, store_path, causal.time)?
+                && !(author(store_path) == Some(*peer)
+                    && self.can(peer, Permission::Append, store_path, causal.time)?)
+            {
                 tracing::info!("join: peer is unauthorized to remove {}", store_path);
                 continue;
             }
             if self.store.contains(store_path) {
-                self.store.remove(store_path);
+                self.remove_store(store_path);
             }
-            self.expired.insert(&path);
+            self.insert_expired(path);
         }
-        self.expired.flush()?;
-        self.store.flush()?;
-        Ok(())
+        self.flush()
+    }
+
+    /// Tombstones the paths of a quarantined peer. Unlike joins the tombstones are not
+    /// checked against the acl, as all replicas purge the paths of an authorized quarantine.
+    pub fn purge(&self, expired: &DotStore) -> Result<()> {
+        for buf in expired.iter() {
+            let path = buf.as_path();
+            let store_path = path.parent().unwrap().parent().unwrap();
+            if self.store.contains(store_path) {
+                self.remove_store(store_path);
+            }
+            self.insert_expired(path);
+        }
+        self.flush()
     }
 
     pub fn unjoin(
@@ -420,89 +761,167 @@ impl Crdt {
         doc: &DocId,
         other: &Archived<CausalContext>,
     ) -> Result<Causal> {
-        let mut path = PathBuf::new();
-        path.doc(doc);
-
         let ctx = self.ctx(doc)?;
         let expired_dots = ctx.expired.difference(&other.expired);
         let store_dots = ctx
             .store
             .difference(&other.store)
             .difference(&other.expired);
+        self.unjoin_dots(peer_id, doc, &store_dots, &expired_dots)
+    }
 
-        let mut store = DotStore::new();
+    /// Returns the dots of the policy statements of a document contained in `dots`.
+    pub fn policy_dots(&self, doc: &DocId, dots: &DotSet) -> DotSet {
+        let mut path = PathBuf::new();
+        path.doc(doc);
+        let mut policy = DotSet::new();
         for k in self.store.scan_prefix(&path) {
             let path = Path::new(&k[..]);
             let dot = path.dot();
-            if !store_dots.contains(&dot) {
-                continue;
-            }
-            if !self.can(peer_id, Permission::Read, path)? {
-                tracing::info!("unjoin: peer is unauthorized to read");
+            if !dots.contains(&dot) {
                 continue;
             }
-            if store_dots.contains(&dot) {
-                store.insert(path.to_owned());
+            if path
+                .parent()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .last()
+                .unwrap()
+                .policy()
+                .is_some()
+            {
+                policy.insert(dot);
             }
         }
+        policy
+    }
+
+    /// Returns the paths of the store and expired dots a peer is authorized to read.
+    pub fn unjoin_dots(
+        &self,
+        peer_id: &PeerId,
+        doc: &DocId,
+        store_dots: &DotSet,
+        expired_dots: &DotSet,
+    ) -> Result<Causal> {
+        let time = self.tick();
+        let mut store = DotStore::new();
         let mut expired = DotStore::new();
-        for k in self.expired.scan_prefix(&path) {
-            let path = Path::new(&k);
-            let dot = path.parent().unwrap().parent().unwrap().dot();
-            if !expired_dots.contains(&dot) {
-                continue;
+        for dot in store_dots.iter().chain(expired_dots.iter()) {
+            let mut prefix = PathBuf::new();
+            prefix.doc(doc);
+            prefix.dot(dot);
+            let n = prefix.as_ref().len();
+            for k in self.dots.scan_prefix(&prefix) {
+                let path = Path::new(&k[n..]);
+                let is_store = store_dots.contains(dot) && self.store.contains(path);
+                let is_expired = expired_dots.contains(dot) && self.expired.contains(path);
+                if !is_store && !is_expired {
+                    continue;
+                }
+                if !self.can(peer_id, Permission::Read, path, time)? {
+                    tracing::info!("unjoin: peer is unauthorized to read {}", path);
+                    continue;
+                }
+                if is_store {
+                    store.insert(path.to_owned());
+                } else {
+                    expired.insert(path.to_owned());
+                }
             }
-            if !self.can(peer_id, Permission::Read, path)? {
-                tracing::info!("unjoin: peer is unauthorized to read {}", path);
+        }
+        Ok(Causal {
+            expired,
+            store,
+            time,
+        })
+    }
+
+    /// Replaces the tombstones of stable dots with their compacted form. A dot is stable
+    /// when all active peers of the document are known to have seen its removal. The
+    /// compacted form only retains the dot, which is enough to reject the removed path
+    /// if it is sent again. Returns the number of compacted tombstones.
+    pub fn compact(&self, doc: &DocId, stable: &DotSet) -> Result<usize> {
+        let mut path = PathBuf::new();
+        path.doc(doc);
+        let mut n = 0;
+        for k in self.expired.scan_prefix(&path) {
+            let dot = Path::new(&k).parent().unwrap().parent().unwrap().dot();
+            if !stable.contains(&dot) {
                 continue;
             }
-            if expired_dots.contains(&dot) {
-                expired.insert(path.to_owned());
-            }
+            let mut key = path.clone();
+            key.dot(&dot);
+            self.compacted.insert(key);
+            self.remove_expired(Path::new(&k));
+            n += 1;
         }
-        Ok(Causal { expired, store })
+        self.compacted.flush()?;
+        self.flush()?;
+        Ok(n)
     }
 
-    pub fn remove(&self, doc: &DocId) -> Result<()> {
+    /// Removes a document. Returns the removed store paths.
+    pub fn remove(&self, doc: &DocId) -> Result<DotStore> {
         let mut path = PathBuf::new();
         path.doc(doc);
+        let mut removed = DotStore::new();
         for k in self.store.scan_prefix(&path) {
+            removed.insert(Path::new(&k).to_owned());
             self.store.remove(k);
         }
         for k in self.expired.scan_prefix(&path) {
             self.expired.remove(k);
         }
-        self.expired.flush()?;
-        self.store.flush()?;
-        Ok(())
+        for k in self.compacted.scan_prefix(&path) {
+            self.compacted.remove(k);
+        }
+        for k in self.dots.scan_prefix(&path) {
+            self.dots.remove(k);
+        }
+        self.compacted.flush()?;
+        self.flush()?;
+        self.invalidate_keys(doc);
+        Ok(removed)
     }
 
-    pub fn transform(&self, doc: &DocId, from: LensesRef, to: LensesRef) -> Result<()> {
+    /// Transforms the paths of a document. Returns the store paths that were removed or
+    /// added.
+    pub fn transform(&self, doc: &DocId, from: LensesRef, to: LensesRef) -> Result<DotStore> {
         let mut path = PathBuf::new();
         path.doc(doc);
-        for k in self.scan_path(path.as_path()) {
-            let path = Path::new(&k);
-            if let Some(path) = from.transform_path(path, to) {
-                self.store.insert(path);
+        let mut changed = DotStore::new();
+        for k in self.store.scan_prefix(&path) {
+            match from.transform_path(Path::new(&k), to) {
+                Some(path) if path.as_ref() == &k[..] => continue,
+                Some(path) => {
+                    self.insert_store(path.as_path());
+                    changed.insert(path);
+                }
+                None => {}
             }
-            self.store.remove(k);
+            changed.insert(Path::new(&k).to_owned());
+            self.remove_store(Path::new(&k));
         }
-        for k in self.scan_path(path.as_path()) {
-            let path = Path::new(&k);
-            if let Some(path) = from.transform_path(path, to) {
-                self.expired.insert(path);
+        for k in self.expired.scan_prefix(&path) {
+            match from.transform_path(Path::new(&k), to) {
+                Some(path) if path.as_ref() == &k[..] => continue,
+                Some(path) => self.insert_expired(path.as_path()),
+                None => {}
             }
-            self.expired.remove(k);
+            self.remove_expired(Path::new(&k));
         }
-        self.expired.flush()?;
-        self.store.flush()?;
-        Ok(())
+        self.flush()?;
+        self.invalidate_keys(doc);
+        Ok(changed)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::acl::Actor;
     use crate::doc::Backend;
     use crate::util::Ref;
     use crate::{props::*, Keypair};
@@ -584,6 +1003,180 @@ mod tests {
         Ok(())
     }
 
+    #[async_std::test]
+    async fn test_quarantine() -> Result<()> {
+        let packages = r#"
+            test {
+                0.1.0 {
+                    .: Struct
+                    .flag: EWFlag
+                    .value: MVReg<u64>
+                }
+            }
+        "#;
+        let la = Keypair::generate();
+        let key = Keypair::generate();
+        let peer = key.peer_id();
+        let mallory = Keypair::generate();
+
+        let mut sdk1 = Backend::test(packages)?;
+        sdk1.frontend().add_keypair(key)?;
+        sdk1.frontend().add_keypair(mallory)?;
+        let fut = sdk1.frontend().create_doc(peer, "test", la)?;
+        Pin::new(&mut sdk1).await?;
+        let doc1 = fut.await;
+
+        let mut sdk2 = Backend::test(packages)?;
+        sdk2.frontend().add_keypair(key)?;
+        let fut = sdk2.frontend().create_doc(peer, "test", la)?;
+        Pin::new(&mut sdk2).await?;
+        let doc2 = fut.await;
+
+        let op = doc1
+            .cursor()
+            .say_can(Some(mallory.peer_id()), Permission::Write)?;
+        doc1.apply(&op)?;
+        doc2.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+        Pin::new(&mut sdk2).await?;
+
+        let mdoc = sdk1.frontend().doc_as(*doc1.id(), &mallory.peer_id())?;
+        let mut cur = mdoc.cursor();
+        cur.field("value")?;
+        let op = cur.assign_u64(42)?;
+        doc1.apply(&op)?;
+        doc2.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+        Pin::new(&mut sdk2).await?;
+        // the context at the time of the compromise
+        let keep = doc1.ctx()?;
+
+        let mut cur = mdoc.cursor();
+        cur.field("flag")?;
+        let flag = cur.enable()?;
+        doc1.apply(&flag)?;
+        doc2.apply(&flag)?;
+        Pin::new(&mut sdk1).await?;
+        Pin::new(&mut sdk2).await?;
+        assert!(doc2.cursor().field("flag")?.enabled()?);
+
+        sdk1.frontend()
+            .quarantine_peer(doc1.id(), &mallory.peer_id(), Some(&keep))?;
+        Pin::new(&mut sdk1).await?;
+        assert!(!doc1.cursor().field("flag")?.enabled()?);
+
+        let delta = sdk1.unjoin(&peer, doc1.id(), Ref::archive(&doc2.ctx()?).as_ref())?;
+        let hash = sdk1.frontend().schema(doc1.id())?.as_ref().hash();
+        sdk2.join(&peer, doc1.id(), &hash, delta)?;
+
+        for doc in [&doc1, &doc2] {
+            assert!(!doc.cursor().field("flag")?.enabled()?);
+            let values = doc
+                .cursor()
+                .field("value")?
+                .u64s()?
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(values, vec![42]);
+        }
+        assert!(doc1.ctx()? == doc2.ctx()?);
+
+        // updates of the quarantined peer are rejected
+        sdk2.join(&peer, doc1.id(), &hash, flag)?;
+        assert!(!doc2.cursor().field("flag")?.enabled()?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_compact() -> Result<()> {
+        let packages = r#"
+            test {
+                0.1.0 {
+                    .: Struct
+                    .flag: EWFlag
+                }
+            }
+        "#;
+        let la = Keypair::generate();
+        let key = Keypair::generate();
+        let peer = key.peer_id();
+        let bob = Keypair::generate();
+
+        let mut sdk1 = Backend::test(packages)?;
+        sdk1.frontend().add_keypair(key)?;
+        let fut = sdk1.frontend().create_doc(peer, "test", la)?;
+        Pin::new(&mut sdk1).await?;
+        let doc1 = fut.await;
+
+        let mut sdk2 = Backend::test(packages)?;
+        sdk2.frontend().add_keypair(key)?;
+        sdk2.frontend().add_keypair(bob)?;
+        let fut = sdk2.frontend().create_doc(peer, "test", la)?;
+        Pin::new(&mut sdk2).await?;
+        let doc2 = fut.await;
+
+        let op = doc1
+            .cursor()
+            .say_can(Some(bob.peer_id()), Permission::Read)?;
+        doc1.apply(&op)?;
+        doc2.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+        Pin::new(&mut sdk2).await?;
+
+        let enable = doc1.cursor().field("flag")?.enable()?;
+        doc1.apply(&enable)?;
+        doc2.apply(&enable)?;
+        Pin::new(&mut sdk1).await?;
+        Pin::new(&mut sdk2).await?;
+        let disable = doc1.cursor().field("flag")?.disable()?;
+        doc1.apply(&disable)?;
+        doc2.apply(&disable)?;
+        Pin::new(&mut sdk1).await?;
+        Pin::new(&mut sdk2).await?;
+        assert!(doc1.ctx()?.expired().iter().next().is_some());
+
+        // bob hasn't acknowledged the removal
+        assert_eq!(sdk1.compact(doc1.id())?, 0);
+        sdk1.ack(
+            &bob.peer_id(),
+            doc1.id(),
+            Ref::archive(&doc2.ctx()?).as_ref(),
+        )?;
+        assert_eq!(sdk1.compact(doc1.id())?, 1);
+        assert!(doc1.ctx()?.expired().iter().next().is_none());
+        assert_eq!(sdk2.compact(doc2.id())?, 1);
+
+        let delta = sdk2.unjoin(&peer, doc2.id(), Ref::archive(&doc1.ctx()?).as_ref())?;
+        assert!(delta.is_empty());
+        assert!(doc1.ctx()? == doc2.ctx()?);
+
+        // compacted paths are rejected
+        let hash = sdk1.frontend().schema(doc1.id())?.as_ref().hash();
+        sdk1.join(&peer, doc1.id(), &hash, enable)?;
+        for doc in [&doc1, &doc2] {
+            assert!(!doc.cursor().field("flag")?.enabled()?);
+        }
+
+        // tombstones aren't compacted while anonymous peers can read
+        let op = doc1.cursor().say_can(None, Permission::Read)?;
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+        let enable = doc1.cursor().field("flag")?.enable()?;
+        doc1.apply(&enable)?;
+        Pin::new(&mut sdk1).await?;
+        let disable = doc1.cursor().field("flag")?.disable()?;
+        doc1.apply(&disable)?;
+        Pin::new(&mut sdk1).await?;
+        sdk1.ack(
+            &bob.peer_id(),
+            doc1.id(),
+            Ref::archive(&doc1.ctx()?).as_ref(),
+        )?;
+        assert_eq!(sdk1.compact(doc1.id())?, 0);
+
+        Ok(())
+    }
+
     #[async_std::test]
     async fn test_mvreg() -> Result<()> {
         let packages = r#"
@@ -627,6 +1220,112 @@ mod tests {
         Ok(())
     }
 
+    #[async_std::test]
+    async fn test_encrypt() -> Result<()> {
+        let packages = r#"
+            test {
+                0.1.0 {
+                    .: MVReg<u64>
+                }
+            }
+        "#;
+        let la = Keypair::generate();
+        let key = Keypair::generate();
+        let peer = key.peer_id();
+
+        let mut sdk1 = Backend::test(packages)?;
+        sdk1.frontend().add_keypair(key)?;
+        let fut = sdk1.frontend().create_doc(peer, "test", la)?;
+        Pin::new(&mut sdk1).await?;
+        let doc1 = fut.await;
+
+        let reader = sdk1.frontend().generate_keypair()?;
+        let op = doc1.cursor().say_can(Some(reader), Permission::Read)?;
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+        let rdoc = sdk1.frontend().doc_as(*doc1.id(), &reader)?;
+
+        let op = doc1.cursor().encrypt()?;
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+        let op = doc1.cursor().assign_u64(42)?;
+        assert!(op.store().iter().all(|path| path
+            .as_path()
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .last()
+            .unwrap()
+            .encrypted()
+            .is_some()));
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+
+        let values = doc1.cursor().u64s()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(values, vec![42]);
+        let values = rdoc.cursor().u64s()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(values, vec![42]);
+
+        // replicas join encrypted values without keys
+        let mut sdk2 = Backend::test(packages)?;
+        sdk2.frontend().add_keypair(key)?;
+        let fut = sdk2.frontend().create_doc(peer, "test", la)?;
+        Pin::new(&mut sdk2).await?;
+        let doc2 = fut.await;
+        let other = sdk2.frontend().generate_keypair()?;
+
+        let delta = sdk1.unjoin(&peer, doc1.id(), Ref::archive(&doc2.ctx()?).as_ref())?;
+        let hash = sdk1.frontend().schema(doc1.id())?.as_ref().hash();
+        sdk2.join(&peer, doc1.id(), &hash, delta)?;
+
+        let values = doc2.cursor().u64s()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(values, vec![42]);
+        let odoc = sdk2.frontend().doc_as(*doc1.id(), &other)?;
+        assert!(odoc.cursor().u64s()?.collect::<Result<Vec<_>>>().is_err());
+
+        // encrypted values are rejected in documents without key statements
+        let mut sdk3 = Backend::test(packages)?;
+        sdk3.frontend().add_keypair(key)?;
+        let fut = sdk3.frontend().create_doc(peer, "test", la)?;
+        Pin::new(&mut sdk3).await?;
+        fut.await;
+        assert!(sdk3.join(&peer, doc1.id(), &hash, op).is_err());
+
+        // new readers are sealed the current key
+        let late = sdk1.frontend().generate_keypair()?;
+        let op = doc1.cursor().say_can(Some(late), Permission::Read)?;
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+        let ldoc = sdk1.frontend().doc_as(*doc1.id(), &late)?;
+        let values = ldoc.cursor().u64s()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(values, vec![42]);
+
+        // encrypted values can't be read by anonymous peers
+        assert!(doc1.cursor().say_can(None, Permission::Read).is_err());
+
+        // revoking the reader rotates the key
+        let id = doc1
+            .cursor()
+            .policies()
+            .into_iter()
+            .find(|st| st.actor() == Actor::Peer(reader))
+            .unwrap()
+            .id();
+        let op = doc1.cursor().revoke(id)?;
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+        let op = doc1.cursor().assign_u64(43)?;
+        doc1.apply(&op)?;
+        Pin::new(&mut sdk1).await?;
+
+        let values = doc1.cursor().u64s()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(values, vec![43]);
+        assert!(rdoc.cursor().u64s()?.collect::<Result<Vec<_>>>().is_err());
+
+        Ok(())
+    }
+
     #[async_std::test]
     async fn test_orarray_smoke() -> Result<()> {
         let packages = r#"
@@ -1,7 +1,14 @@
-use crate::id::PeerId;
-use anyhow::Result;
+use crate::id::{DocId, PeerId};
+use crate::path::Path;
+use anyhow::{anyhow, Context, Result};
 use bytecheck::CheckBytes;
-use ed25519_dalek::{PublicKey, SecretKey, Signature, Signer};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::{ExpandedSecretKey, PublicKey, SecretKey, Signature, Signer};
 use rkyv::{Archive, Deserialize, Serialize};
 
 /// ed25519 keypair.
@@ -38,6 +45,13 @@ impl Keypair {
     pub fn sign(self, msg: &[u8]) -> Signature {
         self.to_keypair().sign(msg)
     }
+
+    /// Returns the x25519 secret of the [`Keypair`].
+    fn x25519(self) -> Scalar {
+        let secret = SecretKey::from_bytes(&self.0).unwrap();
+        let expanded = ExpandedSecretKey::from(&secret).to_bytes();
+        Scalar::from_bits(expanded[..32].try_into().unwrap())
+    }
 }
 
 impl std::fmt::Debug for Keypair {
@@ -57,3 +71,142 @@ impl AsRef<[u8]> for Keypair {
         self.0.as_ref()
     }
 }
+
+/// Returns the x25519 public key of a [`PeerId`].
+fn x25519(peer: &PeerId) -> Result<MontgomeryPoint> {
+    Ok(CompressedEdwardsY::from_slice(peer.as_ref())
+        .decompress()
+        .context("invalid public key")?
+        .to_montgomery())
+}
+
+/// Symmetric key used to encrypt the values of a document.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Key([u8; 32]);
+
+impl Key {
+    /// Generates a new [`Key`].
+    pub fn generate() -> Self {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).unwrap();
+        Self(key)
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&self.0))
+    }
+
+    /// Encrypts a value of the encrypted path of a document. The document id and the path
+    /// are authenticated, so the ciphertext can't be moved to another document or path.
+    pub fn encrypt(&self, doc: &DocId, path: Path, msg: &[u8]) -> Result<Vec<u8>> {
+        self.seal_message(&associated_data(doc, path), msg)
+    }
+
+    /// Decrypts a value encrypted with [`Key::encrypt`].
+    pub fn decrypt(&self, doc: &DocId, path: Path, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        self.open_message(&associated_data(doc, path), ciphertext)
+    }
+
+    /// Encrypts a message. The random nonce is prepended to the ciphertext.
+    fn seal_message(&self, aad: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0; 24];
+        getrandom::getrandom(&mut nonce).map_err(|err| anyhow!("{}", err))?;
+        let mut ciphertext = nonce.to_vec();
+        ciphertext.extend(
+            self.cipher()
+                .encrypt(XNonce::from_slice(&nonce), Payload { msg, aad })
+                .map_err(|_| anyhow!("encryption failed"))?,
+        );
+        Ok(ciphertext)
+    }
+
+    /// Decrypts a message encrypted with [`Key::seal_message`].
+    fn open_message(&self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        if ciphertext.len() < 24 {
+            return Err(anyhow!("invalid ciphertext"));
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(24);
+        self.cipher()
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow!("decryption failed"))
+    }
+
+    /// Seals the [`Key`] to a reader. Only the reader can unseal it.
+    pub fn seal(&self, reader: &PeerId) -> Result<SealedKey> {
+        let public = x25519(reader)?;
+        let mut secret = [0; 32];
+        getrandom::getrandom(&mut secret).unwrap();
+        secret[0] &= 248;
+        secret[31] &= 127;
+        secret[31] |= 64;
+        let secret = Scalar::from_bits(secret);
+        let ephemeral = X25519_BASEPOINT * secret;
+        let key = wrapping_key(&(public * secret), &ephemeral, &public);
+        Ok(SealedKey {
+            reader: *reader,
+            ephemeral: ephemeral.to_bytes(),
+            ciphertext: key.seal_message(reader.as_ref(), &self.0)?,
+        })
+    }
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Key")
+    }
+}
+
+/// Returns the associated data of an encrypted value.
+fn associated_data(doc: &DocId, path: Path) -> Vec<u8> {
+    let mut aad = doc.as_ref().to_vec();
+    aad.extend_from_slice(path.as_ref());
+    aad
+}
+
+/// Derives the key wrapping a sealed [`Key`] from the shared secret.
+fn wrapping_key(
+    shared: &MontgomeryPoint,
+    ephemeral: &MontgomeryPoint,
+    public: &MontgomeryPoint,
+) -> Key {
+    let mut material = [0; 96];
+    material[..32].copy_from_slice(shared.as_bytes());
+    material[32..64].copy_from_slice(ephemeral.as_bytes());
+    material[64..].copy_from_slice(public.as_bytes());
+    Key(blake3::derive_key("tlfs sealed key", &material))
+}
+
+/// [`Key`] sealed to a reader.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Ord, PartialOrd, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(Debug, Eq, Hash, PartialEq, Ord, PartialOrd, CheckBytes))]
+#[repr(C)]
+pub struct SealedKey {
+    reader: PeerId,
+    ephemeral: [u8; 32],
+    ciphertext: Vec<u8>,
+}
+
+impl SealedKey {
+    /// Returns the [`PeerId`] of the reader.
+    pub fn reader(&self) -> PeerId {
+        self.reader
+    }
+
+    /// Unseals the [`Key`] with the [`Keypair`] of the reader.
+    pub fn unseal(&self, keypair: Keypair) -> Result<Key> {
+        if keypair.peer_id() != self.reader {
+            return Err(anyhow!("key is sealed to {}", self.reader));
+        }
+        let secret = keypair.x25519();
+        let ephemeral = MontgomeryPoint(self.ephemeral);
+        let key = wrapping_key(&(ephemeral * secret), &ephemeral, &x25519(&self.reader)?);
+        let key = key.open_message(self.reader.as_ref(), &self.ciphertext)?;
+        Ok(Key(key.try_into().map_err(|_| anyhow!("invalid key"))?))
+    }
+}
@@ -1,13 +1,18 @@
 use std::collections::{BTreeMap, BTreeSet};
-
-use crate::acl::{Actor, Can, Permission, Policy};
-use crate::crdt::{Causal, Crdt, DotStore};
-use crate::crypto::Keypair;
+use std::sync::Arc;
+
+use crate::acl::{
+    keep_hash, Actor, Can, Capability, Explanation, Membership, Permission, Policy, PolicyStatus,
+    Statement,
+};
+use crate::clock::Timestamp;
+use crate::crdt::{author, Causal, CausalContext, Crdt, DotStore, KeyStatement};
+use crate::crypto::{Key, Keypair};
 use crate::cursor::array_util::ArrayMetaEntry;
 use crate::dotset::Dot;
 use crate::fraction::Fraction;
-use crate::id::{DocId, PeerId};
-use crate::path::{Path, PathBuf};
+use crate::id::{DocId, GroupId, PeerId};
+use crate::path::{Path, PathBuf, Segment};
 use crate::schema::{ArchivedSchema, PrimitiveKind, Schema};
 use crate::subscriber::Subscriber;
 use anyhow::{anyhow, Context, Result};
@@ -49,9 +54,47 @@ impl<'a> Cursor<'a> {
         self.crdt.watch_path(self.path.as_path())
     }
 
-    /// Checks permissions.
+    /// Checks permissions. Expired grants are no longer in effect.
     pub fn can(&self, peer: &PeerId, perm: Permission) -> Result<bool> {
-        self.crdt.can(peer, perm, self.path.as_path())
+        self.crdt.can_now(peer, perm, self.path.as_path())
+    }
+
+    /// Explains why a peer has or lacks a permission.
+    pub fn explain(&self, peer: &PeerId, perm: Permission) -> Result<Explanation> {
+        self.crdt.explain(peer, perm, self.path.as_path())
+    }
+
+    /// Returns the policy statements that apply at or below the cursor.
+    pub fn policies(&self) -> Vec<Statement> {
+        let path = self.path.as_path();
+        let doc = path.first().unwrap().doc().unwrap();
+        self.crdt
+            .policies(&doc)
+            .into_iter()
+            .filter(|st| st.path().is_ancestor(path) || path.is_ancestor(st.path()))
+            .collect()
+    }
+
+    fn memberships(&self, group: GroupId) -> impl Iterator<Item = Membership> {
+        let path = self.path.as_path();
+        let doc = path.first().unwrap().doc().unwrap();
+        self.crdt
+            .memberships(&doc)
+            .into_iter()
+            .filter(move |m| m.group() == group && m.status() == PolicyStatus::Authorized)
+    }
+
+    /// Returns the members of a group with the permissions of the group at the cursor.
+    pub fn members(&self, group: GroupId) -> Vec<PeerId> {
+        let path = self.path.as_path();
+        let mut members: Vec<_> = self
+            .memberships(group)
+            .filter(|m| m.path().is_ancestor(path))
+            .map(|m| m.member())
+            .collect();
+        members.sort();
+        members.dedup();
+        members
     }
 
     /// Return the current schema.
@@ -72,16 +115,154 @@ impl<'a> Cursor<'a> {
         }
     }
 
+    /// Returns the values of a register. Encrypted values are decrypted with the keys
+    /// sealed to the peer of the cursor.
+    fn values(&self) -> impl Iterator<Item = Result<Segment>> {
+        let keys = self.keyring();
+        let statements = self.key_statements();
+        self.crdt
+            .scan_path(self.path.as_path())
+            .filter_map(move |path| {
+                let value = Path::new(&path).parent()?.parent()?.last()?;
+                Some(decrypt(&keys, &statements, value))
+            })
+    }
+
+    /// Returns the key statements of the document.
+    fn key_statements(&self) -> Arc<Vec<KeyStatement>> {
+        let doc = self.path.as_path().first().unwrap().doc().unwrap();
+        self.crdt.key_statements(&doc)
+    }
+
+    /// Returns the keys sealed to the peer of the cursor.
+    fn keyring(&self) -> Arc<BTreeMap<Dot, Key>> {
+        let doc = self.path.as_path().first().unwrap().doc().unwrap();
+        self.crdt.keyring(&doc, self.key)
+    }
+
+    /// Returns the current key statement of each encrypted path. Key statements issued
+    /// without control permission are ignored.
+    fn current_keys(&self) -> Result<BTreeMap<PathBuf, KeyStatement>> {
+        let mut current: BTreeMap<PathBuf, KeyStatement> = BTreeMap::new();
+        for st in self.key_statements().iter() {
+            if !self
+                .crdt
+                .can_now(&st.issuer, Permission::Control, st.path.as_path())?
+            {
+                continue;
+            }
+            match current.get(&st.path) {
+                Some(cur) if (cur.epoch, cur.id) >= (st.epoch, st.id) => {}
+                _ => {
+                    current.insert(st.path.clone(), st.clone());
+                }
+            }
+        }
+        Ok(current)
+    }
+
+    /// Returns the current key of the innermost encrypted path containing the cursor.
+    fn encryption_key(&self) -> Result<Option<(KeyStatement, Key)>> {
+        let path = self.path.as_path();
+        let st = self
+            .current_keys()?
+            .into_values()
+            .filter(|st| st.path.as_path().is_ancestor(path))
+            .max_by_key(|st| st.path.as_ref().len());
+        if let Some(st) = st {
+            let key = self
+                .keyring()
+                .get(&st.id)
+                .copied()
+                .context("no key to encrypt values")?;
+            Ok(Some((st, key)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Appends a primitive value, encrypted if the values at the cursor are encrypted.
+    fn push_value(&self, path: &mut PathBuf, value: Segment) -> Result<()> {
+        if let Some((st, key)) = self.encryption_key()? {
+            let doc = self.path.as_path().first().unwrap().doc().unwrap();
+            let mut plaintext = PathBuf::new();
+            plaintext.push_segment(value);
+            let mut data = st.id.as_ref().to_vec();
+            data.extend(key.encrypt(&doc, st.path.as_path(), plaintext.as_ref())?);
+            path.encrypted(&data);
+        } else {
+            path.push_segment(value);
+        }
+        Ok(())
+    }
+
+    /// Returns a key statement sealing a new key to the readers of `path`. Readers only
+    /// granted by the `revoked` statement are excluded. Paths readable by anonymous peers
+    /// can't be encrypted.
+    fn new_key(&self, path: Path, revoked: Option<Dot>) -> Result<Policy> {
+        if self.crdt.has_anonymous_reader(path) {
+            return Err(anyhow!("{} is readable by anonymous peers", path));
+        }
+        let mut readers: BTreeSet<PeerId> = self
+            .crdt
+            .readers(path)
+            .into_iter()
+            .filter(|(_, id)| Some(*id) != revoked)
+            .map(|(peer, _)| peer)
+            .collect();
+        readers.insert(self.peer_id);
+        let epoch = self
+            .key_statements()
+            .iter()
+            .filter(|st| st.path.as_path() == path)
+            .map(|st| st.epoch + 1)
+            .max()
+            .unwrap_or_default();
+        let key = Key::generate();
+        let keys = readers
+            .iter()
+            .map(|reader| key.seal(reader))
+            .collect::<Result<_>>()?;
+        Ok(Policy::Encrypts(epoch, keys))
+    }
+
+    /// Returns the key statements sealing the current keys of the encrypted paths at and
+    /// below the cursor to a new reader, so that it can decrypt the values assigned before
+    /// it was granted permission. The keys keep their epoch. Keys that weren't sealed to
+    /// the peer of the cursor can't be sealed to the reader.
+    fn reseal(&self, reader: &PeerId) -> Result<DotStore> {
+        let cursor = self.path.as_path();
+        let keyring = self.keyring();
+        let mut store = DotStore::new();
+        for (mut path, st) in self.current_keys()? {
+            if !(path.as_path().is_ancestor(cursor) || cursor.is_ancestor(path.as_path()))
+                || st.keys.iter().any(|key| key.reader() == *reader)
+                || !self
+                    .crdt
+                    .can_now(&self.peer_id, Permission::Control, path.as_path())?
+            {
+                continue;
+            }
+            let key = if let Some(key) = keyring.get(&st.id) {
+                key
+            } else {
+                continue;
+            };
+            let mut keys = st.keys.clone();
+            keys.push(key.seal(reader)?);
+            path.policy(&Policy::Encrypts(st.epoch, keys));
+            self.sign(&mut path);
+            store.insert(path);
+        }
+        Ok(store)
+    }
+
     /// Returns an iterator of bools.
     pub fn bools(&self) -> Result<impl Iterator<Item = Result<bool>>> {
         if let ArchivedSchema::Reg(PrimitiveKind::Bool) = &self.schema {
-            Ok(self.crdt.scan_path(self.path.as_path()).filter_map(|path| {
-                Some(Ok(Path::new(&path)
-                    .parent()?
-                    .parent()?
-                    .last()?
-                    .prim_bool()?))
-            }))
+            Ok(self
+                .values()
+                .filter_map(|value| value.map(Segment::prim_bool).transpose()))
         } else {
             Err(anyhow!("not a Reg<bool>"))
         }
@@ -90,13 +271,9 @@ impl<'a> Cursor<'a> {
     /// Returns an iterator of u64s.
     pub fn u64s(&self) -> Result<impl Iterator<Item = Result<u64>>> {
         if let ArchivedSchema::Reg(PrimitiveKind::U64) = &self.schema {
-            Ok(self.crdt.scan_path(self.path.as_path()).filter_map(|path| {
-                Some(Ok(Path::new(&path)
-                    .parent()?
-                    .parent()?
-                    .last()?
-                    .prim_u64()?))
-            }))
+            Ok(self
+                .values()
+                .filter_map(|value| value.map(Segment::prim_u64).transpose()))
         } else {
             Err(anyhow!("not a Reg<u64>"))
         }
@@ -105,13 +282,9 @@ impl<'a> Cursor<'a> {
     /// Returns an iterator of i64s.
     pub fn i64s(&self) -> Result<impl Iterator<Item = Result<i64>>> {
         if let ArchivedSchema::Reg(PrimitiveKind::I64) = &self.schema {
-            Ok(self.crdt.scan_path(self.path.as_path()).filter_map(|path| {
-                Some(Ok(Path::new(&path)
-                    .parent()?
-                    .parent()?
-                    .last()?
-                    .prim_i64()?))
-            }))
+            Ok(self
+                .values()
+                .filter_map(|value| value.map(Segment::prim_i64).transpose()))
         } else {
             Err(anyhow!("not a Reg<i64>"))
         }
@@ -120,14 +293,9 @@ impl<'a> Cursor<'a> {
     /// Returns an iterator of strs.
     pub fn strs(&self) -> Result<impl Iterator<Item = Result<String>>> {
         if let ArchivedSchema::Reg(PrimitiveKind::Str) = &self.schema {
-            Ok(self.crdt.scan_path(self.path.as_path()).filter_map(|path| {
-                Some(Ok(Path::new(&path)
-                    .parent()?
-                    .parent()?
-                    .last()?
-                    .prim_str()?
-                    .to_owned()))
-            }))
+            Ok(self
+                .values()
+                .filter_map(|value| value.map(Segment::prim_string).transpose()))
         } else {
             Err(anyhow!("not a Reg<String>"))
         }
@@ -338,10 +506,16 @@ impl<'a> Cursor<'a> {
         path.sig(sig);
     }
 
+    /// Tombstones the paths at the cursor. Without write permission only the paths
+    /// authored by the peer are tombstoned.
     fn tombstone(&self) -> Result<DotStore> {
+        let write = self.can(&self.peer_id, Permission::Write)?;
         let mut expired = DotStore::new();
         for k in self.crdt.scan_path(self.path.as_path()) {
             let path = Path::new(&k);
+            if !write && author(path) != Some(self.peer_id) {
+                continue;
+            }
             if path
                 .parent()
                 .unwrap()
@@ -365,7 +539,7 @@ impl<'a> Cursor<'a> {
         if *self.schema != ArchivedSchema::Flag {
             return Err(anyhow!("not a flag"));
         }
-        if !self.can(&self.peer_id, Permission::Write)? {
+        if !self.can(&self.peer_id, Permission::Append)? {
             return Err(anyhow!("unauthorized"));
         }
         let mut path = self.path.to_owned();
@@ -376,6 +550,7 @@ impl<'a> Cursor<'a> {
         let c = Causal {
             store,
             expired: Default::default(),
+            time: self.crdt.tick(),
         };
         self.augment_array(c)
     }
@@ -385,19 +560,20 @@ impl<'a> Cursor<'a> {
         if *self.schema != ArchivedSchema::Flag {
             return Err(anyhow!("not a flag"));
         }
-        if !self.can(&self.peer_id, Permission::Write)? {
+        if !self.can(&self.peer_id, Permission::Append)? {
             return Err(anyhow!("unauthorized"));
         }
 
         let c = Causal {
             store: DotStore::new(),
             expired: self.tombstone()?,
+            time: self.crdt.tick(),
         };
         self.augment_array(c)
     }
 
     fn assign(&self, kind: PrimitiveKind) -> Result<(PathBuf, DotStore)> {
-        if !self.can(&self.peer_id, Permission::Write)? {
+        if !self.can(&self.peer_id, Permission::Append)? {
             return Err(anyhow!("unauthorized"));
         }
         if *self.schema != ArchivedSchema::Reg(kind) {
@@ -412,11 +588,15 @@ impl<'a> Cursor<'a> {
     pub fn assign_bool(&self, value: bool) -> Result<Causal> {
         let (mut path, expired) = self.assign(PrimitiveKind::Bool)?;
         let mut store = DotStore::new();
-        path.prim_bool(value);
+        self.push_value(&mut path, Segment::Bool(value))?;
         self.sign(&mut path);
         store.insert(path);
 
-        let c = Causal { store, expired };
+        let c = Causal {
+            store,
+            expired,
+            time: self.crdt.tick(),
+        };
         self.augment_array(c)
     }
 
@@ -424,11 +604,15 @@ impl<'a> Cursor<'a> {
     pub fn assign_u64(&self, value: u64) -> Result<Causal> {
         let (mut path, expired) = self.assign(PrimitiveKind::U64)?;
         let mut store = DotStore::new();
-        path.prim_u64(value);
+        self.push_value(&mut path, Segment::U64(value))?;
         self.sign(&mut path);
         store.insert(path);
 
-        let c = Causal { store, expired };
+        let c = Causal {
+            store,
+            expired,
+            time: self.crdt.tick(),
+        };
         self.augment_array(c)
     }
 
@@ -436,11 +620,15 @@ impl<'a> Cursor<'a> {
     pub fn assign_i64(&self, value: i64) -> Result<Causal> {
         let (mut path, expired) = self.assign(PrimitiveKind::I64)?;
         let mut store = DotStore::new();
-        path.prim_i64(value);
+        self.push_value(&mut path, Segment::I64(value))?;
         self.sign(&mut path);
         store.insert(path);
 
-        let c = Causal { store, expired };
+        let c = Causal {
+            store,
+            expired,
+            time: self.crdt.tick(),
+        };
         self.augment_array(c)
     }
 
@@ -448,29 +636,59 @@ impl<'a> Cursor<'a> {
     pub fn assign_str(&self, value: &str) -> Result<Causal> {
         let (mut path, expired) = self.assign(PrimitiveKind::Str)?;
         let mut store = DotStore::new();
-        path.prim_str(value);
+        self.push_value(&mut path, Segment::Str(value.to_owned()))?;
         self.sign(&mut path);
         store.insert(path);
 
-        let c = Causal { store, expired };
+        let c = Causal {
+            store,
+            expired,
+            time: self.crdt.tick(),
+        };
         self.augment_array(c)
     }
 
     /// Removes a value from a map.
     pub fn remove(&self) -> Result<Causal> {
-        if !self.can(&self.peer_id, Permission::Write)? {
+        if !self.can(&self.peer_id, Permission::Append)? {
             return Err(anyhow!("unauthorized"));
         }
         let c = Causal {
             store: DotStore::new(),
             expired: self.tombstone()?,
+            time: self.crdt.tick(),
         };
         self.augment_array(c)
     }
 
     fn say(&self, policy: &Policy) -> Result<Causal> {
+        let mut causal = self.statement(policy)?.1;
+        match policy {
+            Policy::Can(Actor::Peer(reader), _) | Policy::CanUntil(Actor::Peer(reader), _, _) => {
+                causal.store.union(&self.reseal(reader)?);
+            }
+            Policy::Can(Actor::Anonymous, _) | Policy::CanUntil(Actor::Anonymous, _, _) => {
+                let path = self.path.as_path();
+                if self
+                    .current_keys()?
+                    .keys()
+                    .any(|p| p.as_path().is_ancestor(path) || path.is_ancestor(p.as_path()))
+                {
+                    return Err(anyhow!("{} is encrypted", path));
+                }
+            }
+            _ => {}
+        }
+        Ok(causal)
+    }
+
+    /// Signs a policy statement. Returns the [`Dot`] identifying the statement.
+    fn statement(&self, policy: &Policy) -> Result<(Dot, Causal)> {
         if !match &policy {
-            Policy::Can(_, perm) | Policy::CanIf(_, perm, _) => {
+            Policy::Can(_, perm)
+            | Policy::CanIf(_, perm, _)
+            | Policy::CanUntil(_, perm, _)
+            | Policy::CanIfUntil(_, perm, _, _) => {
                 if perm.controllable() {
                     self.can(&self.peer_id, Permission::Control)?
                 } else {
@@ -478,20 +696,36 @@ impl<'a> Cursor<'a> {
                 }
             }
             Policy::Revokes(_) => self.can(&self.peer_id, Permission::Control)?,
+            Policy::Member(_, _) => self.can(&self.peer_id, Permission::Own)?,
+            Policy::Token(_, perm, _) => {
+                perm.controllable() && self.can(&self.peer_id, Permission::Control)?
+            }
+            // redemptions are authorized by the proof
+            Policy::Redeems(_, _) => true,
+            // certifications only apply to grants of the issuer
+            Policy::Device(_) | Policy::Unlinks(_) | Policy::Rotates(_) => true,
+            Policy::Quarantines(_, _) | Policy::Keeps(_, _) => {
+                self.can(&self.peer_id, Permission::Own)?
+            }
+            Policy::Encrypts(_, _) => self.can(&self.peer_id, Permission::Control)?,
+            // only the issuer of the token accepts redemptions
+            Policy::Accepts(_, _) => true,
         } {
             return Err(anyhow!("unauthorized"));
         }
         let mut path = self.path.clone();
         path.policy(policy);
         self.sign(&mut path);
+        let id = path.as_path().dot();
         let mut store = DotStore::new();
         store.insert(path);
 
         let c = Causal {
             store,
             expired: DotStore::new(),
+            time: self.crdt.tick(),
         };
-        self.augment_array(c)
+        Ok((id, self.augment_array(c)?))
     }
 
     /// Gives permission to a peer.
@@ -499,6 +733,16 @@ impl<'a> Cursor<'a> {
         self.say(&Policy::Can(actor.into(), perm))
     }
 
+    /// Gives permission to a peer until `expiry`.
+    pub fn say_can_until(
+        &self,
+        actor: Option<PeerId>,
+        perm: Permission,
+        expiry: Timestamp,
+    ) -> Result<Causal> {
+        self.say(&Policy::CanUntil(actor.into(), perm, expiry))
+    }
+
     /// Constructs a new condition.
     pub fn cond(&self, actor: Actor, perm: Permission) -> Can {
         Can::new(actor, perm, self.path.clone())
@@ -509,9 +753,171 @@ impl<'a> Cursor<'a> {
         self.say(&Policy::CanIf(actor, perm, cond))
     }
 
-    /// Revokes a policy.
+    /// Gives conditional permission to a peer until `expiry`.
+    pub fn say_can_if_until(
+        &self,
+        actor: Actor,
+        perm: Permission,
+        cond: Can,
+        expiry: Timestamp,
+    ) -> Result<Causal> {
+        self.say(&Policy::CanIfUntil(actor, perm, cond, expiry))
+    }
+
+    /// Revokes a policy. The keys of the encrypted paths controlled by the peer of the
+    /// cursor are rotated, peers only granted by the revoked statement can't decrypt the
+    /// values assigned afterwards. Keys can be rotated explicitly with [`Cursor::encrypt`]
+    /// once the revocation took effect.
     pub fn revoke(&self, claim: Dot) -> Result<Causal> {
-        self.say(&Policy::Revokes(claim))
+        let mut causal = self.say(&Policy::Revokes(claim))?;
+        for mut path in self.current_keys()?.into_keys() {
+            if !self
+                .crdt
+                .can_now(&self.peer_id, Permission::Control, path.as_path())?
+            {
+                continue;
+            }
+            let policy = self.new_key(path.as_path(), Some(claim))?;
+            path.policy(&policy);
+            self.sign(&mut path);
+            causal.store.insert(path);
+        }
+        Ok(causal)
+    }
+
+    /// Encrypts the values assigned at and below the cursor with a new key sealed to the
+    /// peers that can read them. Values assigned before stay readable without a key. Peers
+    /// without keys still join and sync the encrypted values. Table keys, struct fields and
+    /// policy statements are not encrypted. Encrypting the values again rotates the key.
+    /// Peers granted permission afterwards are sealed the current key. Values readable by
+    /// anonymous peers can't be encrypted.
+    pub fn encrypt(&self) -> Result<Causal> {
+        self.say(&self.new_key(self.path.as_path(), None)?)
+    }
+
+    /// Gives permission on each entry of a table or array to the peer that created it.
+    pub fn say_creator_can(&self, perm: Permission) -> Result<Causal> {
+        if !matches!(
+            self.schema,
+            ArchivedSchema::Table(_, _) | ArchivedSchema::Array(_)
+        ) {
+            return Err(anyhow!("not a Table<_, _> or Array<_>"));
+        }
+        self.say(&Policy::Can(Actor::Creator, perm))
+    }
+
+    /// Gives permission to the members of a group.
+    pub fn say_can_group(&self, group: GroupId, perm: Permission) -> Result<Causal> {
+        self.say(&Policy::Can(Actor::Group(group), perm))
+    }
+
+    /// Adds a peer to a group. The peer has the permissions of the group at and below
+    /// the cursor.
+    pub fn add_member(&self, group: GroupId, member: PeerId) -> Result<Causal> {
+        self.say(&Policy::Member(group, member))
+    }
+
+    /// Removes a peer from a group by revoking it's memberships at and below the cursor.
+    pub fn remove_member(&self, group: GroupId, member: PeerId) -> Result<Causal> {
+        if !self.can(&self.peer_id, Permission::Own)? {
+            return Err(anyhow!("unauthorized"));
+        }
+        let path = self.path.as_path();
+        let mut causal = Causal::default();
+        for m in self.memberships(group) {
+            if m.member() == member && path.is_ancestor(m.path()) {
+                causal.join(&self.revoke(m.id())?);
+            }
+        }
+        Ok(causal)
+    }
+
+    /// Mints a capability for a permission that can be redeemed by up to `uses` peers.
+    /// Only read, append and write permissions can be minted. Redeemed permissions can't be
+    /// delegated and are lost when the capability statement is revoked.
+    pub fn say_token(&self, perm: Permission, uses: u32) -> Result<(Causal, Capability)> {
+        let secret = Keypair::generate();
+        let (id, causal) = self.statement(&Policy::Token(secret.peer_id(), perm, uses))?;
+        Ok((causal, Capability::new(id, self.path.clone(), secret)))
+    }
+
+    /// Redeems a capability of the document for the permission of the token.
+    pub fn redeem(&self, capability: &Capability) -> Result<Causal> {
+        let doc = self.path.as_path().first().unwrap().doc().unwrap();
+        if capability.doc() != doc {
+            return Err(anyhow!("capability of doc {}", capability.doc()));
+        }
+        let proof = capability.prove(self.peer_id);
+        let mut path = capability.path().to_owned();
+        path.policy(&Policy::Redeems(capability.id(), proof));
+        self.sign(&mut path);
+        let mut store = DotStore::new();
+        store.insert(path);
+        Ok(Causal {
+            store,
+            expired: DotStore::new(),
+            time: self.crdt.tick(),
+        })
+    }
+
+    /// Accepts a redemption of a capability minted by the peer of the cursor as the next use
+    /// of the capability.
+    pub fn accept(&self, redemption: Dot) -> Result<Causal> {
+        let doc = self.path.as_path().first().unwrap().doc().unwrap();
+        let index = self
+            .crdt
+            .next_use(&doc, redemption, &self.peer_id)
+            .ok_or_else(|| anyhow!("can't accept redemption {}", redemption))?;
+        self.say(&Policy::Accepts(redemption, index))
+    }
+
+    /// Certifies a device key. The device has the permissions granted to the peer of the
+    /// cursor in the document.
+    pub fn say_device(&self, device: PeerId) -> Result<Causal> {
+        self.say(&Policy::Device(device))
+    }
+
+    /// Unlinks a device key certified by the peer of the cursor.
+    pub fn unlink_device(&self, device: PeerId) -> Result<Causal> {
+        self.say(&Policy::Unlinks(device))
+    }
+
+    /// Transfers the permissions of the peer of the cursor in the document to a new key. Only
+    /// permissions the peer could delegate or granted itself are transferred, the peer of the
+    /// cursor loses all of it's permissions.
+    pub fn rotate(&self, key: PeerId) -> Result<Causal> {
+        self.say(&Policy::Rotates(key))
+    }
+
+    /// Quarantines a peer. All replicas expire the paths signed by the peer below the
+    /// cursor and reject them from then on. Paths contained in `keep` are kept, which
+    /// allows keeping the updates made before the peer was compromised. The kept paths are
+    /// listed in separate keep statements referenced by their hash.
+    pub fn quarantine(&self, peer: PeerId, keep: Option<&CausalContext>) -> Result<Causal> {
+        let keep = if let Some(keep) = keep {
+            keep
+        } else {
+            return self.say(&Policy::Quarantines(peer, None));
+        };
+        let keep: BTreeSet<Dot> = self
+            .crdt
+            .scan_path(self.path.as_path())
+            .filter_map(|key| {
+                let path = Path::new(&key);
+                if author(path) == Some(peer) {
+                    Some(path.dot())
+                } else {
+                    None
+                }
+            })
+            .filter(|dot| keep.store().contains(dot))
+            .collect();
+        let hash = keep_hash(&keep);
+        let mut causal = self.say(&Policy::Quarantines(peer, Some(hash)))?;
+        for dot in keep {
+            causal.join(&self.say(&Policy::Keeps(hash, dot))?);
+        }
+        Ok(causal)
     }
 
     /// Moves the entry inside an array.
@@ -536,6 +942,35 @@ impl<'a> Cursor<'a> {
     }
 }
 
+/// Decrypts an encrypted primitive with the key of the statement it references, other
+/// primitives are returned unchanged.
+fn decrypt(
+    keys: &BTreeMap<Dot, Key>,
+    statements: &[KeyStatement],
+    value: Segment,
+) -> Result<Segment> {
+    let data = if let Segment::Encrypted(data) = value {
+        data
+    } else {
+        return Ok(value);
+    };
+    if data.len() < 32 {
+        return Err(anyhow!("invalid encrypted value"));
+    }
+    let (id, ciphertext) = data.split_at(32);
+    let id = Dot::new(id.try_into().unwrap());
+    let key = keys.get(&id).with_context(|| format!("no key {}", id))?;
+    let st = statements
+        .iter()
+        .find(|st| st.id == id)
+        .with_context(|| format!("no key statement {}", id))?;
+    let doc = st.path.as_path().first().unwrap().doc().unwrap();
+    let plaintext = key.decrypt(&doc, st.path.as_path(), ciphertext)?;
+    Path::new(&plaintext)
+        .last()
+        .context("invalid encrypted value")
+}
+
 fn nonce() -> u64 {
     let mut nonce = [0; 8];
     getrandom::getrandom(&mut nonce).unwrap();
@@ -761,7 +1196,11 @@ impl ArrayWrapper {
         cursor.sign(&mut new_value_path);
         store.insert(new_value_path);
 
-        Ok(Causal { store, expired })
+        Ok(Causal {
+            store,
+            expired,
+            time: cursor.crdt.tick(),
+        })
     }
 
     /// Tombstones all value and meta paths
@@ -827,6 +1266,7 @@ impl ArrayWrapper {
         Ok(Causal {
             expired: self.tombstone(cursor)?,
             store: Default::default(),
+            time: cursor.crdt.tick(),
         })
     }
     fn get_meta_data(&self, path: Path) -> Result<array_util::ArrayMetaEntry> {
@@ -838,7 +1278,7 @@ impl ArrayWrapper {
     }
 }
 
-mod array_util {
+pub(crate) mod array_util {
     use super::*;
     use crate::Segment;
     use anyhow::Context;
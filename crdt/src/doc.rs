@@ -1,23 +1,30 @@
 use crate::acl::{Acl, Engine, Permission};
-use crate::crdt::{Causal, CausalContext, Crdt};
+use crate::clock::Timestamp;
+use crate::crdt::{Causal, CausalContext, Crdt, DotStore};
 use crate::crypto::Keypair;
 use crate::cursor::Cursor;
+use crate::dotset::{Dot, DotSet};
 use crate::id::{DocId, PeerId};
 use crate::lens::LensesRef;
-use crate::path::Path;
+use crate::path::{Path, PathBuf};
 use crate::radixdb::{BlobMap, BlobSet, Storage};
+use crate::reconcile::{self, Pending, Range, Sketch, MAX_CHUNK};
 use crate::registry::{Expanded, Hash, Registry};
 use crate::util::Ref;
 use crate::MemStorage;
 use anyhow::{anyhow, Result};
+use bytecheck::CheckBytes;
 use futures::channel::{mpsc, oneshot};
 use futures::prelude::*;
+use futures_timer::Delay;
 use rkyv::{Archive, Archived, Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 /// Information about the schema of a document.
 #[derive(Debug, Archive, Deserialize, Serialize)]
@@ -90,6 +97,8 @@ impl Docs {
         self.0.remove(key)?;
         key[32] = 1;
         self.0.remove(key)?;
+        key[32] = 4;
+        self.0.remove(key)?;
         Ok(())
     }
 
@@ -134,6 +143,25 @@ impl Docs {
         Ok(())
     }
 
+    pub fn pinned(&self, id: &DocId) -> Result<bool> {
+        let mut key = [0; 33];
+        key[..32].copy_from_slice(id.as_ref());
+        key[32] = 4;
+        Ok(self.0.get(key)?.is_some())
+    }
+
+    pub fn set_pinned(&self, id: &DocId, pinned: bool) -> Result<()> {
+        let mut key = [0; 33];
+        key[..32].copy_from_slice(id.as_ref());
+        key[32] = 4;
+        if pinned {
+            self.0.insert(key, b"")?;
+        } else {
+            self.0.remove(key)?;
+        }
+        Ok(())
+    }
+
     pub fn peer_id(&self, id: &DocId) -> Result<PeerId> {
         let mut key = [0; 33];
         key[..32].copy_from_slice(id.as_ref());
@@ -199,6 +227,32 @@ impl Docs {
         Ok(())
     }
 
+    pub fn devices(&self, root: PeerId) -> impl Iterator<Item = Result<PeerId>> + '_ {
+        self.0.iter().filter_map(move |(k, v)| {
+            if k[32] == 5 && v.as_ref() == root.as_ref() {
+                Some(Ok(PeerId::new(k[..32].try_into().unwrap())))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn add_device(&self, root: &PeerId, device: &PeerId) -> Result<()> {
+        let mut key = [0; 33];
+        key[..32].copy_from_slice(device.as_ref());
+        key[32] = 5;
+        self.0.insert(key, root.as_ref())?;
+        Ok(())
+    }
+
+    pub fn remove_device(&self, device: &PeerId) -> Result<()> {
+        let mut key = [0; 33];
+        key[..32].copy_from_slice(device.as_ref());
+        key[32] = 5;
+        self.0.remove(key)?;
+        Ok(())
+    }
+
     pub fn subscribe(&self) -> impl Stream<Item = ()> {
         self.0.watch_prefix(&[]).map(|_| ())
     }
@@ -250,44 +304,127 @@ impl std::fmt::Debug for Docs {
     }
 }
 
+/// Returns the key of a tombstone a peer acknowledged, or the prefix of the keys of the
+/// tombstones a peer acknowledged.
+fn ack_key(doc: &DocId, peer: &PeerId, dot: Option<&Dot>) -> PathBuf {
+    let mut key = PathBuf::new();
+    key.doc(doc);
+    key.peer(peer);
+    if let Some(dot) = dot {
+        key.dot(dot);
+    }
+    key
+}
+
+/// Returns the store paths added or removed by a change.
+fn changed(causal: &Causal) -> DotStore {
+    let mut changed = causal.store.clone();
+    for path in causal.expired.iter() {
+        changed.insert(
+            path.as_path()
+                .parent()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .to_owned(),
+        );
+    }
+    changed
+}
+
+/// Maximum number of remote changes buffered while waiting for the lenses of their schema.
+const MAX_BUFFERED: usize = 256;
+
+/// Remote change waiting for the lenses of its schema.
+#[derive(Clone, Debug, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(CheckBytes))]
+#[repr(C)]
+pub struct Buffered {
+    /// Hash of the schema of the change.
+    pub schema: [u8; 32],
+    /// Document of the change.
+    pub doc: DocId,
+    /// Peer that sent the change.
+    pub peer: PeerId,
+    /// The change.
+    pub causal: Causal,
+}
+
+impl Buffered {
+    /// Returns the hash of the schema of the change.
+    pub fn schema(&self) -> Hash {
+        self.schema.into()
+    }
+}
+
 /// The crdt [`Backend`] is the main entry point to interact with this crate.
 pub struct Backend {
     registry: Registry,
     crdt: Crdt,
     docs: Docs,
     engine: Engine,
-    tx: mpsc::UnboundedSender<oneshot::Sender<()>>,
-    rx: mpsc::UnboundedReceiver<oneshot::Sender<()>>,
+    buffer: BlobMap,
+    tx: mpsc::UnboundedSender<(DotStore, oneshot::Sender<()>)>,
+    rx: mpsc::UnboundedReceiver<(DotStore, oneshot::Sender<()>)>,
+    acks: BlobSet,
+    timer: Option<Delay>,
 }
 
 impl Backend {
     /// Creates a new [`Backend`] from a radixdb storage.
     pub fn new(storage: Arc<dyn Storage>, package: &[u8]) -> Result<Self> {
-        let registry = Registry::new(package)?;
+        let registry = Registry::new(BlobSet::load(storage.clone(), "trusted")?, package)?;
         let docs = Docs::new(BlobMap::load(storage.clone(), "docs")?);
         let acl = Acl::new(BlobMap::load(storage.clone(), "acl")?);
+        let buffer = BlobMap::load(storage.clone(), "buffer")?;
+        let acks = BlobSet::load(storage.clone(), "acks")?;
+        let store = BlobSet::load(storage.clone(), "store")?;
         let crdt = Crdt::new(
-            BlobSet::load(storage.clone(), "store")?,
-            BlobSet::load(storage, "expired")?,
+            store.clone(),
+            BlobSet::load(storage.clone(), "expired")?,
+            BlobSet::load(storage.clone(), "compacted")?,
+            BlobSet::load(storage, "dots")?,
             acl.clone(),
-        );
-        let engine = Engine::new(acl)?;
+        )?;
+        let engine = Engine::new(acl, store)?;
         let (tx, rx) = mpsc::unbounded();
         let mut me = Self {
             registry,
             crdt,
             docs,
             engine,
+            buffer,
             tx,
             rx,
+            acks,
+            timer: None,
         };
+        me.load_policy();
+        me.migrate()?;
         me.update_acl()?;
+        Ok(me)
+    }
 
-        // migrate docs
-        for res in me.docs.docs() {
+    /// Migrates all documents that aren't pinned to the newest version of their schema.
+    fn migrate(&mut self) -> Result<()> {
+        for res in self.docs.docs() {
             let id = res?;
-            let info = me.docs.schema(&id)?;
-            let (version, hash) = me.registry.lookup(&info.as_ref().name).unwrap();
+            let info = self.docs.schema(&id)?;
+            let (version, hash) = if let Some(entry) = self.registry.lookup(&info.as_ref().name) {
+                entry
+            } else {
+                tracing::warn!(
+                    "document {} has unknown schema {}",
+                    id,
+                    info.as_ref().name()
+                );
+                continue;
+            };
+            if self.docs.pinned(&id)? {
+                self.registry
+                    .register_version(&hash, info.as_ref().version)?;
+                continue;
+            }
             if version > info.as_ref().version {
                 tracing::info!(
                     "migrating document {} from {} to {}",
@@ -295,16 +432,26 @@ impl Backend {
                     info.as_ref().version,
                     version
                 );
-                let lenses = me.registry.get(&hash).unwrap();
+                let lenses = self.registry.get(&hash).unwrap();
                 let end = info.as_ref().version as usize;
                 let curr_lenses = LensesRef::new(&lenses.lenses().lenses()[..end]);
-                me.crdt
+                let changed = self
+                    .crdt
                     .transform(&id, curr_lenses, lenses.lenses().to_ref())?;
+                self.add_policy(&changed);
                 let info = SchemaInfo::new(info.as_ref().name.to_string(), version, hash);
-                me.docs.set_schema(&id, &info)?;
+                self.docs.set_schema(&id, &info)?;
             }
         }
-        Ok(me)
+        Ok(())
+    }
+
+    /// Installs a package of lenses at runtime. Schemas with new names become available
+    /// and existing documents are migrated to newer versions of their schema.
+    pub fn install_package(&mut self, package: &[u8]) -> Result<()> {
+        self.registry.install(package)?;
+        self.migrate()?;
+        self.update_acl()
     }
 
     /// Creates a new in memory [`Backend`].
@@ -338,12 +485,76 @@ impl Backend {
         &self.registry
     }
 
-    fn update_acl(&mut self) -> Result<()> {
+    /// Feeds all paths in the store to the acl engine.
+    fn load_policy(&mut self) {
         for key in self.crdt.iter() {
-            let path = Path::new(&key[..]);
-            self.engine.add_policy(path);
+            self.engine.add_policy(Path::new(&key[..]));
         }
-        self.engine.update_acl()
+    }
+
+    /// Feeds the store paths added or removed by a change to the acl engine.
+    fn add_policy(&mut self, store: &DotStore) {
+        for path in store.iter() {
+            if self.crdt.contains(path.as_path()) {
+                self.engine.add_policy(path.as_path());
+            } else {
+                self.engine.remove_policy(path.as_path());
+            }
+        }
+    }
+
+    /// Sets the time used to judge the expiry of statements.
+    #[cfg(test)]
+    pub(crate) fn set_time(&mut self, now: Timestamp) {
+        self.engine.set_time(now);
+        self.schedule_expiry();
+    }
+
+    /// Evaluates the policies of all documents from scratch.
+    pub fn reload_acl(&mut self) -> Result<()> {
+        self.engine.reset();
+        self.load_policy();
+        self.update_acl()
+    }
+
+    fn update_acl(&mut self) -> Result<()> {
+        self.engine.update_acl()?;
+        // purging quarantined paths can change the creators of entries
+        loop {
+            let purge = self.engine.take_purge();
+            if purge.is_empty() {
+                self.schedule_expiry();
+                return Ok(());
+            }
+            for (doc, store) in purge {
+                let key = self.docs.keypair(&self.docs.peer_id(&doc)?)?;
+                let mut expired = DotStore::new();
+                for mut path in store.iter() {
+                    let sig = key.sign(path.as_ref());
+                    path.peer(&key.peer_id());
+                    path.sig(sig);
+                    expired.insert(path);
+                }
+                self.crdt.purge(&expired)?;
+                self.add_policy(&store);
+            }
+            self.engine.update_acl()?;
+        }
+    }
+
+    /// Arms the timer for the next grant that expires.
+    fn schedule_expiry(&mut self) {
+        self.timer = self
+            .engine
+            .next_expiry()
+            .map(|delay| Delay::new(delay.max(Duration::from_millis(1))));
+    }
+
+    /// Evaluates the policies again once grants expired.
+    fn expire(&mut self) -> Result<()> {
+        self.engine.expire()?;
+        self.schedule_expiry();
+        Ok(())
     }
 
     /// Checks if the store contains a document.
@@ -367,30 +578,246 @@ impl Backend {
         if causal.is_empty() {
             return Ok(());
         }
+        let other_doc = causal
+            .store
+            .iter()
+            .chain(causal.expired.iter())
+            .any(|path| path.as_path().first().and_then(|s| s.doc()) != Some(*doc));
+        if other_doc {
+            return Err(anyhow!("change to a document other than {}", doc));
+        }
         let doc_schema = self.docs.schema(doc)?;
         let doc_lenses = self.registry.get(&doc_schema.as_ref().hash.into()).unwrap();
         let lenses = self
             .registry
             .get(causal_schema)
             .ok_or_else(|| anyhow!("missing lenses with hash {}", causal_schema))?;
-        if !lenses.schema().validate(&causal) {
+        // remote lenses are unsigned
+        self.registry
+            .check_trusted(doc_schema.as_ref().name(), causal_schema)?;
+        // encrypted values are only accepted in documents with key statements
+        let encrypted = causal.has_key_statements() || !self.crdt.key_statements(doc).is_empty();
+        if !lenses.schema().validate(&causal, encrypted) {
             return Err(anyhow!("crdt failed schema validation"));
         }
         causal.transform(lenses.lenses().to_ref(), doc_lenses.lenses().to_ref());
+        self.crdt.update_clock(causal.time());
+        // the clock can pass the expiry of a grant before the timer fires
+        self.expire()?;
         self.crdt.join_policy(&causal)?;
+        self.add_policy(&causal.store);
         self.update_acl()?;
         self.crdt.join(peer_id, &causal)?;
+        // new entries can change the creators of tables and arrays
+        self.add_policy(&changed(&causal));
+        self.update_acl()?;
+        Ok(())
+    }
+
+    /// Buffers a remote change until the lenses of its schema are available. When the
+    /// buffer is full the oldest changes are dropped and returned.
+    pub fn buffer(
+        &self,
+        peer_id: &PeerId,
+        doc: &DocId,
+        schema: &Hash,
+        causal: Causal,
+    ) -> Result<Vec<Buffered>> {
+        let buffered = Buffered {
+            schema: (*schema).into(),
+            doc: *doc,
+            peer: *peer_id,
+            causal,
+        };
+        let value = Ref::archive(&buffered);
+        // keys are ordered by the time they were buffered
+        let mut key = [0; 40];
+        key[..8].copy_from_slice(&u64::from(Timestamp::now()).to_be_bytes());
+        key[8..].copy_from_slice(blake3::hash(value.as_bytes()).as_bytes());
+        self.buffer.insert(key, value.as_bytes())?;
+        let keys: Vec<_> = self.buffer.iter().map(|(k, _)| k).collect();
+        let mut dropped = vec![];
+        for key in &keys[..keys.len().saturating_sub(MAX_BUFFERED)] {
+            if let Some(value) = self.buffer.get(key)? {
+                dropped.push(Ref::<Buffered>::new(value).to_owned()?);
+            }
+            self.buffer.remove(key)?;
+        }
+        Ok(dropped)
+    }
+
+    /// Returns the buffered remote changes.
+    pub fn buffered(&self) -> Result<Vec<Buffered>> {
+        self.buffer
+            .iter()
+            .map(|(_, v)| Ref::<Buffered>::new(v.clone()).to_owned())
+            .collect()
+    }
+
+    /// Joins the buffered remote changes whose lenses are available. Changes that fail to
+    /// join stay buffered until they are retried or dropped.
+    pub fn join_buffered(&mut self) -> Result<()> {
+        let entries: Vec<_> = self.buffer.iter().map(|(k, v)| (k, v.clone())).collect();
+        for (key, value) in entries {
+            let buffered = Ref::<Buffered>::new(value).to_owned()?;
+            if !self.registry.contains(&buffered.schema()) {
+                continue;
+            }
+            match self.join(
+                &buffered.peer,
+                &buffered.doc,
+                &buffered.schema(),
+                buffered.causal,
+            ) {
+                Ok(()) => self.buffer.remove(&key)?,
+                Err(err) => tracing::error!("{}", err),
+            }
+        }
         Ok(())
     }
 
-    /// Returns the changes required to bring a peer up to speed.
+    /// Removes and returns the buffered remote changes with a schema.
+    pub fn drop_buffered(&self, schema: &Hash) -> Result<Vec<Buffered>> {
+        let mut dropped = vec![];
+        for (key, value) in self.buffer.iter() {
+            let buffered = Ref::<Buffered>::new(value.clone()).to_owned()?;
+            if buffered.schema() == *schema {
+                self.buffer.remove(&key)?;
+                dropped.push(buffered);
+            }
+        }
+        Ok(dropped)
+    }
+
+    /// Returns the changes required to bring a peer up to speed. Tombstones the peer
+    /// acknowledged are not sent again, as the peer may have compacted them.
     pub fn unjoin(
         &self,
         peer_id: &PeerId,
         doc: &DocId,
         ctx: &Archived<CausalContext>,
     ) -> Result<Causal> {
-        self.crdt.unjoin(peer_id, doc, ctx)
+        let causal = self.crdt.unjoin(peer_id, doc, ctx)?;
+        Ok(self.unacked(peer_id, doc, causal))
+    }
+
+    /// Reconciles the [`Sketch`]es of a peer with the document. Returns the first chunk
+    /// of changes required to bring the peer up to speed, the dots of the remaining
+    /// chunks and the ranges the peer needs to sketch again. Expired dots that both peers
+    /// have are acknowledged. Only the dots the peer may read are reconciled.
+    pub fn reconcile(
+        &mut self,
+        peer_id: &PeerId,
+        doc: &DocId,
+        sketches: &Archived<Vec<Sketch>>,
+    ) -> Result<(Causal, Pending, Vec<Range>)> {
+        let ctx = self.crdt.readable_ctx(peer_id, doc)?;
+        let res = reconcile::reconcile(&ctx, &self.crdt.compacted(doc), sketches);
+        self.ack_dots(peer_id, doc, res.acked.iter())?;
+        // policies are sent first, so that later chunks can be joined
+        let policy = self.crdt.policy_dots(doc, &res.store);
+        let mut pending = Pending::with_first(&policy, &res.store, &res.expired);
+        let chunk = pending.next_chunk();
+        let store = chunk.store().iter().copied().collect();
+        let expired = chunk.expired().iter().copied().collect();
+        let causal = self.crdt.unjoin_dots(peer_id, doc, &store, &expired)?;
+        Ok((self.unacked(peer_id, doc, causal), pending, res.split))
+    }
+
+    /// Returns the paths of a chunk of dots a peer is missing. At most [`MAX_CHUNK`]
+    /// dots of the chunk are unjoined.
+    pub fn unjoin_chunk(
+        &self,
+        peer_id: &PeerId,
+        doc: &DocId,
+        chunk: &Archived<Pending>,
+    ) -> Result<Causal> {
+        let n = chunk.store().len().min(MAX_CHUNK);
+        let store = chunk.store()[..n].iter().copied().collect();
+        let m = chunk.expired().len().min(MAX_CHUNK - n);
+        let expired = chunk.expired()[..m].iter().copied().collect();
+        let causal = self.crdt.unjoin_dots(peer_id, doc, &store, &expired)?;
+        Ok(self.unacked(peer_id, doc, causal))
+    }
+
+    /// Removes the tombstones a peer acknowledged.
+    fn unacked(&self, peer_id: &PeerId, doc: &DocId, mut causal: Causal) -> Causal {
+        causal.expired = causal
+            .expired
+            .iter()
+            .filter(|path| {
+                let dot = path.as_path().parent().unwrap().parent().unwrap().dot();
+                !self.acks.contains(ack_key(doc, peer_id, Some(&dot)))
+            })
+            .collect();
+        causal
+    }
+
+    /// Records the tombstones a peer has seen. The causal context is the one a peer
+    /// sends when requesting changes. Returns if the peer acknowledged new tombstones.
+    pub fn ack(
+        &mut self,
+        peer_id: &PeerId,
+        doc: &DocId,
+        ctx: &Archived<CausalContext>,
+    ) -> Result<bool> {
+        self.ack_dots(peer_id, doc, ctx.expired.iter())
+    }
+
+    /// Records the acknowledged tombstones. Returns if any of them weren't acknowledged
+    /// before.
+    fn ack_dots<'a>(
+        &mut self,
+        peer_id: &PeerId,
+        doc: &DocId,
+        dots: impl Iterator<Item = &'a Dot>,
+    ) -> Result<bool> {
+        let mut advanced = false;
+        for dot in dots {
+            let key = ack_key(doc, peer_id, Some(dot));
+            if !self.acks.contains(&key) {
+                self.acks.insert(key);
+                advanced = true;
+            }
+        }
+        self.acks.flush()?;
+        Ok(advanced)
+    }
+
+    /// Compacts the tombstones of a document that were acknowledged by all active peers
+    /// of the document. Peers that read a document through an anonymous grant are not
+    /// tracked, so tombstones aren't compacted while anonymous peers can read the
+    /// document. Returns the number of compacted tombstones.
+    pub fn compact(&mut self, doc: &DocId) -> Result<usize> {
+        if self.crdt.has_anonymous_readers(doc) {
+            return Ok(0);
+        }
+        let mut path = PathBuf::new();
+        path.doc(doc);
+        let mut stable = self.crdt.ctx(doc)?.expired;
+        for (peer, _) in self.crdt.readers(path.as_path()) {
+            if self.docs.keypair(&peer).is_ok() {
+                continue;
+            }
+            let acked: DotSet = self
+                .acks
+                .scan_prefix(ack_key(doc, &peer, None))
+                .map(|k| Path::new(&k).last().unwrap().dot().unwrap())
+                .collect();
+            stable = stable.intersection(&acked);
+        }
+        if stable.iter().next().is_none() {
+            return Ok(0);
+        }
+        let n = self.crdt.compact(doc, &stable)?;
+        let keys: Vec<_> = self.acks.scan_prefix(&path).collect();
+        for k in keys {
+            if stable.contains(&Path::new(&k).last().unwrap().dot().unwrap()) {
+                self.acks.remove(k);
+            }
+        }
+        self.acks.flush()?;
+        Ok(n)
     }
 
     /// Returns a clonable [`Frontend`].
@@ -408,7 +835,14 @@ impl Future for Backend {
     type Output = Result<()>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        if let Poll::Ready(Some(tx)) = Pin::new(&mut self.rx).poll_next(cx) {
+        if let Some(timer) = self.timer.as_mut() {
+            if Pin::new(timer).poll(cx).is_ready() {
+                self.timer = None;
+                return Poll::Ready(self.expire());
+            }
+        }
+        if let Poll::Ready(Some((store, tx))) = Pin::new(&mut self.rx).poll_next(cx) {
+            self.add_policy(&store);
             let res = self.update_acl();
             tx.send(()).ok();
             Poll::Ready(res)
@@ -424,7 +858,7 @@ pub struct Frontend {
     crdt: Crdt,
     docs: Docs,
     registry: Registry,
-    tx: mpsc::UnboundedSender<oneshot::Sender<()>>,
+    tx: mpsc::UnboundedSender<(DotStore, oneshot::Sender<()>)>,
 }
 
 impl Frontend {
@@ -432,7 +866,7 @@ impl Frontend {
         crdt: Crdt,
         docs: Docs,
         registry: Registry,
-        tx: mpsc::UnboundedSender<oneshot::Sender<()>>,
+        tx: mpsc::UnboundedSender<(DotStore, oneshot::Sender<()>)>,
     ) -> Self {
         Self {
             crdt,
@@ -468,6 +902,12 @@ impl Frontend {
         self.docs.remove_keypair(peer)
     }
 
+    /// Only accept packages named `name` signed by one of the trusted publishers. The
+    /// trusted publishers are persisted.
+    pub fn trust_publisher(&self, name: &str, publisher: PeerId) -> Result<()> {
+        self.registry.trust(name, publisher)
+    }
+
     /// Returns an iterator of [`DocId`].
     pub fn docs(&self) -> impl Iterator<Item = Result<DocId>> + '_ {
         self.docs.docs()
@@ -478,7 +918,8 @@ impl Frontend {
         self.docs.docs_by_schema(schema)
     }
 
-    /// Creates a new document using [`Keypair`] with initial schema and owner.
+    /// Creates a new document using [`Keypair`] with initial schema and owner. The devices
+    /// linked to the owner are certified in the new document.
     pub fn create_doc(
         &self,
         owner: PeerId,
@@ -495,10 +936,13 @@ impl Frontend {
         self.docs.set_peer_id(&id, &id.into())?;
         self.docs.set_schema(&id, &info)?;
         let doc = Doc::new(id, self.clone(), la, schema);
-        let delta = doc.cursor().say_can(Some(owner), Permission::Own)?;
+        let mut delta = doc.cursor().say_can(Some(owner), Permission::Own)?;
+        let doc = self.doc_as(id, &owner)?;
+        for res in self.docs.devices(owner) {
+            delta.join(&doc.cursor().say_device(res?)?);
+        }
         let fut = self.apply(&id, &delta)?;
         self.docs.set_peer_id(&id, &owner)?;
-        let doc = self.doc(id)?;
         Ok(async move {
             fut.await;
             doc
@@ -518,13 +962,140 @@ impl Frontend {
         self.doc(id)
     }
 
+    /// Migrates a document forward or backward to `version` of its schema. Documents
+    /// migrated to an older version are pinned and excluded from automatic migration
+    /// until they are migrated to the newest version.
+    pub fn migrate_doc(&self, id: &DocId, version: u32) -> Result<()> {
+        let info = self.schema(id)?;
+        let name = info.as_ref().name();
+        let (newest, hash) = self
+            .registry
+            .lookup(name)
+            .ok_or_else(|| anyhow!("missing schema {}", name))?;
+        if version > newest {
+            return Err(anyhow!("schema {} has no version {}", name, version));
+        }
+        let lenses = self.lenses(&hash)?;
+        let from = LensesRef::new(&lenses.lenses().lenses()[..info.as_ref().version as usize]);
+        let to = LensesRef::new(&lenses.lenses().lenses()[..version as usize]);
+        let changed = self.crdt.transform(id, from, to)?;
+        let hash = self.registry.register_version(&hash, version)?;
+        let info = SchemaInfo::new(name.into(), version, hash);
+        self.docs.set_schema(id, &info)?;
+        self.docs.set_pinned(id, version < newest)?;
+        let (tx, _) = oneshot::channel();
+        self.tx.clone().unbounded_send((changed, tx))?;
+        Ok(())
+    }
+
     /// Removes a document identified by [`DocId`].
     pub fn remove_doc(&self, id: &DocId) -> Result<()> {
-        self.crdt.remove(id)?;
+        let removed = self.crdt.remove(id)?;
         self.docs.remove(id)?;
+        let (tx, _) = oneshot::channel();
+        self.tx.clone().unbounded_send((removed, tx))?;
         Ok(())
     }
 
+    /// Returns the devices linked to the local `root` keypair.
+    pub fn devices(&self, root: PeerId) -> impl Iterator<Item = Result<PeerId>> + '_ {
+        self.docs.devices(root)
+    }
+
+    /// Links a device to the local `root` keypair. The device is certified in every
+    /// document associated with `root` and in documents created for `root` later on. Returns
+    /// the applied certification statements to broadcast.
+    pub fn link_device(&self, root: &PeerId, device: &PeerId) -> Result<Vec<(DocId, Causal)>> {
+        self.docs.add_device(root, device)?;
+        let mut statements = vec![];
+        for id in self.root_docs(root)? {
+            let causal = self.doc(id)?.cursor().say_device(*device)?;
+            self.apply_policy(&causal)?;
+            statements.push((id, causal));
+        }
+        Ok(statements)
+    }
+
+    /// Accepts a redemption of a capability minted in a document as the next use of the
+    /// capability. Returns the applied acceptance statement to broadcast.
+    pub fn accept_redemption(&self, doc: &DocId, redemption: Dot) -> Result<Causal> {
+        let causal = self.doc(*doc)?.cursor().accept(redemption)?;
+        self.apply_policy(&causal)?;
+        Ok(causal)
+    }
+
+    /// Unlinks a device from the local `root` keypair in every document associated with
+    /// `root`. Returns the applied unlink statements to broadcast.
+    pub fn unlink_device(&self, root: &PeerId, device: &PeerId) -> Result<Vec<(DocId, Causal)>> {
+        self.docs.remove_device(device)?;
+        let mut statements = vec![];
+        for id in self.root_docs(root)? {
+            let causal = self.doc(id)?.cursor().unlink_device(*device)?;
+            self.apply_policy(&causal)?;
+            statements.push((id, causal));
+        }
+        Ok(statements)
+    }
+
+    /// Rotates the local `old` keypair to a newly generated keypair. The permissions `old` could
+    /// delegate or granted itself are transferred to the new keypair and all permissions of
+    /// `old` are revoked in every document `old` has permissions in, documents and devices
+    /// associated with `old` are associated with the new keypair.
+    /// Returns the new keypair and the applied statements to broadcast.
+    pub fn rotate_keypair(&self, old: &PeerId) -> Result<(PeerId, Vec<(DocId, Causal)>)> {
+        let new = self.generate_keypair()?;
+        let mut statements = vec![];
+        for res in self.docs() {
+            let id = res?;
+            let associated = self.peer_id(&id)? == *old;
+            if !associated && !self.crdt.has_rules(old, &id) {
+                continue;
+            }
+            let causal = self.doc_as(id, old)?.cursor().rotate(new)?;
+            self.apply_policy(&causal)?;
+            statements.push((id, causal));
+            if associated {
+                self.docs.set_peer_id(&id, &new)?;
+            }
+        }
+        let devices = self.devices(*old).collect::<Result<Vec<_>>>()?;
+        for device in devices {
+            statements.extend(self.link_device(&new, &device)?);
+        }
+        if self.default_keypair()?.peer_id() == *old {
+            self.docs.set_default_keypair(&new)?;
+        }
+        Ok((new, statements))
+    }
+
+    /// Quarantines a compromised peer in a document. Every replica expires the paths
+    /// signed by the peer and rejects them from then on, except for the paths contained in
+    /// `keep`. Passing the [`CausalContext`] of a replica at the time of the compromise only
+    /// purges the updates made afterwards. Policy statements are not affected and must be
+    /// revoked. Returns the applied statement to broadcast.
+    pub fn quarantine_peer(
+        &self,
+        doc: &DocId,
+        peer: &PeerId,
+        keep: Option<&CausalContext>,
+    ) -> Result<Causal> {
+        let causal = self.doc(*doc)?.cursor().quarantine(*peer, keep)?;
+        self.apply_policy(&causal)?;
+        Ok(causal)
+    }
+
+    /// Returns the documents associated with the local `root` keypair.
+    pub fn root_docs(&self, root: &PeerId) -> Result<Vec<DocId>> {
+        let mut docs = vec![];
+        for res in self.docs() {
+            let id = res?;
+            if self.peer_id(&id)? == *root {
+                docs.push(id);
+            }
+        }
+        Ok(docs)
+    }
+
     /// Returns the local [`PeerId`] associated with a document.
     pub fn peer_id(&self, id: &DocId) -> Result<PeerId> {
         self.docs.peer_id(id)
@@ -547,12 +1118,55 @@ impl Frontend {
         self.crdt.ctx(id)
     }
 
+    /// Computes the [`Sketch`]es of ranges of the [`CausalContext`] to sync with a remote
+    /// peer.
+    pub fn sketch(&self, id: &DocId, ranges: &[Range]) -> Result<Vec<Sketch>> {
+        Ok(Sketch::new(
+            &self.crdt.ctx(id)?,
+            &self.crdt.compacted(id),
+            ranges,
+        ))
+    }
+
+    /// Compares the [`Sketch`]es of the [`Range::roots`] of a remote peer with the part of
+    /// the [`CausalContext`] the peer may read. Returns `None` if the sketches are not of
+    /// the roots.
+    pub fn is_synced(
+        &self,
+        peer_id: &PeerId,
+        id: &DocId,
+        sketches: &Archived<Vec<Sketch>>,
+    ) -> Result<Option<bool>> {
+        let ctx = self.crdt.readable_ctx(peer_id, id)?;
+        Ok(reconcile::is_synced(
+            &ctx,
+            &self.crdt.compacted(id),
+            sketches,
+        ))
+    }
+
     /// Opens a document.
     pub fn doc(&self, id: DocId) -> Result<Doc> {
         let peer_id = self.peer_id(&id)?;
         self.doc_as(id, &peer_id)
     }
 
+    /// Opens a document at `version` of its schema. Returns an error if the document is at
+    /// a different version, documents are moved between versions with
+    /// [`Frontend::migrate_doc`].
+    pub fn doc_at(&self, id: DocId, version: u32) -> Result<Doc> {
+        let current = self.schema(&id)?.as_ref().version();
+        if current != version {
+            return Err(anyhow!(
+                "document {} is at version {} instead of {}",
+                id,
+                current,
+                version
+            ));
+        }
+        self.doc(id)
+    }
+
     /// Opens a document with a local keypair identified by [`PeerId`].
     pub fn doc_as(&self, id: DocId, peer_id: &PeerId) -> Result<Doc> {
         let info = self.schema(&id)?;
@@ -566,12 +1180,21 @@ impl Frontend {
         let peer = self.peer_id(doc)?;
         self.crdt.join(&peer, causal)?;
         let (tx, rx) = oneshot::channel();
-        self.tx.clone().unbounded_send(tx)?;
+        self.tx.clone().unbounded_send((changed(causal), tx))?;
         Ok(async move {
             rx.await.ok();
         })
     }
 
+    /// Applies local policy statements that are authorized by their signature instead of
+    /// the permissions of the issuer.
+    fn apply_policy(&self, causal: &Causal) -> Result<()> {
+        self.crdt.join_policy(causal)?;
+        let (tx, _) = oneshot::channel();
+        self.tx.clone().unbounded_send((causal.store.clone(), tx))?;
+        Ok(())
+    }
+
     /// Subscribes to document changes.
     pub fn subscribe(&self) -> impl Stream<Item = ()> {
         self.docs.subscribe()
@@ -704,4 +1327,460 @@ mod tests {
 
         Ok(())
     }
+
+    #[async_std::test]
+    async fn test_install_package() -> Result<()> {
+        let v1 = r#"
+            todoapp {
+                0.1.0 {
+                    .: Struct
+                    .todos: Table<u64>
+                    .todos.{}: Struct
+                    .todos.{}.title: MVReg<String>
+                }
+            }
+        "#;
+        let v2 = r#"
+            todoapp {
+                0.1.0 {
+                    .: Struct
+                    .todos: Table<u64>
+                    .todos.{}: Struct
+                    .todos.{}.title: MVReg<String>
+                }
+                0.1.1 {
+                    .todos.rename(tasks)
+                }
+            }
+            notes {
+                0.1.0 {
+                    .: Table<String>
+                    .{}: MVReg<String>
+                }
+            }
+        "#;
+        let mut sdk = Backend::test(v1)?;
+        let peer = sdk.frontend().default_keypair()?.peer_id();
+        let fut = sdk
+            .frontend()
+            .create_doc(peer, "todoapp", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+        let op = doc
+            .cursor()
+            .field("todos")?
+            .key_u64(0)?
+            .field("title")?
+            .assign_str("title")?;
+        doc.apply(&op)?;
+        assert!(sdk.registry().lookup("notes").is_none());
+
+        let package = Ref::archive(&tlfsc::compile_lenses(v2)?);
+        sdk.install_package(package.as_bytes())?;
+        assert!(sdk.registry().lookup("notes").is_some());
+        assert_eq!(sdk.frontend().schema(doc.id())?.as_ref().version(), 7);
+
+        let doc = sdk.frontend().doc(*doc.id())?;
+        let value = doc
+            .cursor()
+            .field("tasks")?
+            .key_u64(0)?
+            .field("title")?
+            .strs()?
+            .next()
+            .unwrap()?;
+        assert_eq!(value, "title");
+
+        let package = Ref::archive(&tlfsc::compile_lenses(v1)?);
+        sdk.install_package(package.as_bytes())?;
+        assert_eq!(sdk.registry().lookup("todoapp").unwrap().0, 7);
+
+        let fut = sdk
+            .frontend()
+            .create_doc(peer, "notes", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let notes = fut.await;
+        assert!(notes.cursor().can(&peer, Permission::Write)?);
+
+        Ok(())
+    }
+
+    /// Checks that evaluating the acl from scratch doesn't change it.
+    fn assert_incremental(sdk: &mut Backend, doc: &DocId) -> Result<()> {
+        let rules = sdk.engine.rules();
+        let policies = sdk.crdt.policies(doc);
+        sdk.reload_acl()?;
+        assert_eq!(sdk.engine.rules(), rules);
+        assert_eq!(sdk.crdt.policies(doc), policies);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_incremental_acl() -> Result<()> {
+        let lenses = r#"
+            todoapp {
+                0.1.0 {
+                    .: Struct
+                    .todos: Table<u64>
+                    .todos.{}: Struct
+                    .todos.{}.title: MVReg<String>
+                }
+                0.1.1 {
+                    .todos.rename(tasks)
+                }
+            }
+        "#;
+        let mut sdk = Backend::test(lenses)?;
+        let hash = sdk.registry().lookup("todoapp").unwrap().1;
+        let a = sdk.frontend().generate_keypair()?;
+        let b = sdk.frontend().generate_keypair()?;
+        let fut = sdk
+            .frontend()
+            .create_doc(a, "todoapp", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+        let id = *doc.id();
+
+        let grant = doc
+            .cursor()
+            .field("tasks")?
+            .say_can(Some(b), Permission::Write)?;
+        doc.apply(&grant)?;
+        Pin::new(&mut sdk).await?;
+        let op = doc
+            .cursor()
+            .field("tasks")?
+            .say_creator_can(Permission::Own)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        let op = sdk
+            .frontend()
+            .doc_as(id, &b)?
+            .cursor()
+            .field("tasks")?
+            .key_u64(0)?
+            .field("title")?
+            .assign_str("b's task")?;
+        sdk.join(&b, &id, &hash, op)?;
+        let mut task = doc.cursor();
+        task.field("tasks")?.key_u64(0)?;
+        assert!(task.can(&b, Permission::Own)?);
+        assert_incremental(&mut sdk, &id)?;
+
+        let op = doc
+            .cursor()
+            .field("tasks")?
+            .revoke(grant.store.iter().next().unwrap().as_path().dot())?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        assert!(!doc.cursor().field("tasks")?.can(&b, Permission::Write)?);
+        assert_incremental(&mut sdk, &id)?;
+
+        sdk.frontend().migrate_doc(&id, 6)?;
+        Pin::new(&mut sdk).await?;
+        let doc = sdk.frontend().doc(id)?;
+        let mut todo = doc.cursor();
+        todo.field("todos")?.key_u64(0)?;
+        assert!(todo.can(&b, Permission::Own)?);
+        assert_incremental(&mut sdk, &id)?;
+
+        sdk.frontend().quarantine_peer(&id, &b, None)?;
+        Pin::new(&mut sdk).await?;
+        let mut todo = doc.cursor();
+        todo.field("todos")?.key_u64(0)?;
+        assert!(!todo.can(&b, Permission::Own)?);
+        assert_incremental(&mut sdk, &id)?;
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_migrate_doc() -> Result<()> {
+        let lenses = r#"
+            todoapp {
+                0.1.0 {
+                    .: Struct
+                    .todos: Table<u64>
+                    .todos.{}: Struct
+                    .todos.{}.title: MVReg<String>
+                }
+                0.1.1 {
+                    .todos.rename(tasks)
+                }
+            }
+        "#;
+        let mut sdk = Backend::test(lenses)?;
+        let peer = sdk.frontend().default_keypair()?.peer_id();
+        let fut = sdk
+            .frontend()
+            .create_doc(peer, "todoapp", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+        let id = *doc.id();
+        let op = doc
+            .cursor()
+            .field("tasks")?
+            .key_u64(0)?
+            .field("title")?
+            .assign_str("title")?;
+        doc.apply(&op)?;
+
+        // opening a document at another version doesn't migrate it
+        assert!(sdk.frontend().doc_at(id, 6).is_err());
+        assert_eq!(sdk.frontend().schema(&id)?.as_ref().version(), 7);
+        sdk.frontend().migrate_doc(&id, 6)?;
+        let doc = sdk.frontend().doc_at(id, 6)?;
+        assert_eq!(sdk.frontend().schema(&id)?.as_ref().version(), 6);
+        let value = doc
+            .cursor()
+            .field("todos")?
+            .key_u64(0)?
+            .field("title")?
+            .strs()?
+            .next()
+            .unwrap()?;
+        assert_eq!(value, "title");
+        assert!(doc.cursor().field("tasks").is_err());
+
+        let package = Ref::archive(&tlfsc::compile_lenses(lenses)?);
+        sdk.install_package(package.as_bytes())?;
+        assert_eq!(sdk.frontend().schema(&id)?.as_ref().version(), 6);
+
+        sdk.frontend().migrate_doc(&id, 7)?;
+        let doc = sdk.frontend().doc(id)?;
+        let value = doc
+            .cursor()
+            .field("tasks")?
+            .key_u64(0)?
+            .field("title")?
+            .strs()?
+            .next()
+            .unwrap()?;
+        assert_eq!(value, "title");
+        assert!(sdk.frontend().migrate_doc(&id, 8).is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_install_signed_package() -> Result<()> {
+        let schema = r#"
+            notes {
+                0.1.0 {
+                    .: Table<String>
+                    .{}: MVReg<String>
+                }
+            }
+        "#;
+        let storage = Arc::new(MemStorage::default());
+        let package = Ref::archive(&tlfsc::compile_lenses("todoapp {}")?);
+        let sdk = Backend::new(storage.clone(), package.as_bytes())?;
+        let publisher = Keypair::generate();
+        sdk.frontend()
+            .trust_publisher("notes", publisher.peer_id())?;
+        // the installed package isn't signed by the publisher
+        assert!(sdk
+            .frontend()
+            .trust_publisher("todoapp", publisher.peer_id())
+            .is_err());
+
+        let package = Ref::archive(&tlfsc::compile_lenses(schema)?);
+        assert!(sdk.registry().install(package.as_bytes()).is_err());
+
+        let mut packages = tlfsc::compile_lenses(schema)?;
+        tlfsc::sign_packages(&mut packages, Keypair::generate());
+        let package = Ref::archive(&packages);
+        assert!(sdk.registry().install(package.as_bytes()).is_err());
+
+        let mut packages = tlfsc::compile_lenses(schema)?;
+        tlfsc::sign_packages(&mut packages, publisher);
+        let package = Ref::archive(&packages);
+        let archived = &package.as_ref()[0];
+        assert_eq!(archived.verify()?, Some(publisher.peer_id()));
+        assert_eq!(sdk.registry().install(package.as_bytes())?, vec!["notes"]);
+        assert!(sdk.registry().lookup("notes").is_some());
+
+        // trusted publishers survive a restart
+        drop(sdk);
+        let package = Ref::archive(&tlfsc::compile_lenses(schema)?);
+        assert!(Backend::new(storage.clone(), package.as_bytes()).is_err());
+        let package = Ref::archive(&tlfsc::compile_lenses("todoapp {}")?);
+        let sdk = Backend::new(storage, package.as_bytes())?;
+
+        // unsigned lenses can't extend a package from a trusted publisher
+        let v1 = r#"
+            todos {
+                0.1.0 {
+                    .: Struct
+                    .title: MVReg<String>
+                }
+            }
+        "#;
+        let v2 = r#"
+            todos {
+                0.1.0 {
+                    .: Struct
+                    .title: MVReg<String>
+                }
+                0.1.1 {
+                    .title.rename(name)
+                }
+            }
+        "#;
+        sdk.frontend()
+            .trust_publisher("todos", publisher.peer_id())?;
+        let mut packages = tlfsc::compile_lenses(v1)?;
+        tlfsc::sign_packages(&mut packages, publisher);
+        sdk.registry().install(Ref::archive(&packages).as_bytes())?;
+        let package = Ref::archive(&tlfsc::compile_lenses(v2)?);
+        assert!(sdk
+            .registry()
+            .register(package.as_ref()[0].lenses())
+            .is_err());
+        let package = Ref::archive(&tlfsc::compile_lenses(v1)?);
+        let hash = sdk.registry().register(package.as_ref()[0].lenses())?;
+        sdk.registry().check_trusted("todos", &hash)?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_unjoin_chunks() -> Result<()> {
+        let packages = r#"
+            todoapp {
+                0.1.0 {
+                    .: Struct
+                    .todos: Table<u64>
+                    .todos.{}: Struct
+                    .todos.{}.title: MVReg<String>
+                }
+            }
+        "#;
+        let mut sdk = Backend::test(packages)?;
+        let peer = sdk.frontend().default_keypair()?.peer_id();
+        let fut = sdk
+            .frontend()
+            .create_doc(peer, "todoapp", Keypair::generate())?;
+        Pin::new(&mut sdk).await?;
+        let doc = fut.await;
+        for i in 0..MAX_CHUNK as u64 + 10 {
+            let op = doc
+                .cursor()
+                .field("todos")?
+                .key_u64(i)?
+                .field("title")?
+                .assign_str("todo")?;
+            doc.apply(&op)?;
+        }
+
+        let mut sdk2 = Backend::test(packages)?;
+        let peer2 = sdk2.frontend().default_keypair()?.peer_id();
+        let op = doc.cursor().say_can(Some(peer2), Permission::Read)?;
+        doc.apply(&op)?;
+        Pin::new(&mut sdk).await?;
+        let doc2 = sdk2.frontend().add_doc(*doc.id(), &peer2, "todoapp")?;
+        let hash = sdk2.frontend().registry.lookup("todoapp").unwrap().1;
+
+        let mut ranges = Range::roots().to_vec();
+        let mut pending = Pending::default();
+        while !ranges.is_empty() {
+            let sketches = Ref::archive(&sdk2.frontend().sketch(doc.id(), &ranges)?);
+            let (causal, rest, split) = sdk.reconcile(&peer2, doc.id(), sketches.as_ref())?;
+            sdk2.join(&peer, doc.id(), &hash, causal)?;
+            pending.extend(rest);
+            ranges = split;
+        }
+        assert!(!pending.is_empty());
+
+        // each chunk is joined on its own
+        while !pending.is_empty() {
+            pending.retain_missing(&doc2.ctx()?);
+            let chunk = pending.next_chunk();
+            assert!(chunk.len() <= MAX_CHUNK);
+            let chunk = Ref::archive(&chunk);
+            let causal = sdk.unjoin_chunk(&peer2, doc.id(), chunk.as_ref())?;
+            sdk2.join(&peer, doc.id(), &hash, causal)?;
+        }
+        assert_eq!(doc2.ctx()?, doc.ctx()?);
+
+        // dots a peer may not read aren't reconciled
+        let sdk3 = Backend::test(packages)?;
+        let peer3 = sdk3.frontend().default_keypair()?.peer_id();
+        sdk3.frontend().add_doc(*doc.id(), &peer3, "todoapp")?;
+        let sketches = Ref::archive(&sdk3.frontend().sketch(doc.id(), &Range::roots())?);
+        let (_, rest, _) = sdk.reconcile(&peer3, doc.id(), sketches.as_ref())?;
+        assert!(rest.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_buffer() -> Result<()> {
+        let v1 = r#"
+            todoapp {
+                0.1.0 {
+                    .: Struct
+                    .title: MVReg<String>
+                }
+            }
+        "#;
+        let v2 = r#"
+            todoapp {
+                0.1.0 {
+                    .: Struct
+                    .title: MVReg<String>
+                }
+                0.1.1 {
+                    .title.rename(name)
+                }
+            }
+        "#;
+        let la = Keypair::generate();
+        let key = Keypair::generate();
+        let peer = key.peer_id();
+
+        let mut sdk1 = Backend::test(v2)?;
+        sdk1.frontend().add_keypair(key)?;
+        let fut = sdk1.frontend().create_doc(peer, "todoapp", la)?;
+        Pin::new(&mut sdk1).await?;
+        let doc1 = fut.await;
+        let hash = sdk1.frontend().schema(doc1.id())?.as_ref().hash();
+
+        let storage = Arc::new(MemStorage::default());
+        let v1 = Ref::archive(&tlfsc::compile_lenses(v1)?);
+        let mut sdk2 = Backend::new(storage.clone(), v1.as_bytes())?;
+        sdk2.frontend().add_keypair(key)?;
+        let fut = sdk2.frontend().create_doc(peer, "todoapp", la)?;
+        Pin::new(&mut sdk2).await?;
+        let doc2 = fut.await;
+        assert!(!sdk2.registry().contains(&hash));
+
+        let op = doc1.cursor().field("name")?.assign_str("hello")?;
+        assert!(sdk2.buffer(&peer, doc2.id(), &hash, op)?.is_empty());
+
+        // the buffer survives a restart
+        drop(sdk2);
+        let mut sdk2 = Backend::new(storage, v1.as_bytes())?;
+        assert_eq!(sdk2.buffered()?.len(), 1);
+        sdk2.join_buffered()?;
+        assert_eq!(sdk2.buffered()?.len(), 1);
+
+        let v2 = Ref::archive(&tlfsc::compile_lenses(v2)?);
+        sdk2.install_package(v2.as_bytes())?;
+        sdk2.join_buffered()?;
+        assert!(sdk2.buffered()?.is_empty());
+        let doc2 = sdk2.frontend().doc(*doc1.id())?;
+        let value = doc2.cursor().field("name")?.strs()?.next().unwrap()?;
+        assert_eq!(value, "hello");
+
+        // the oldest changes are dropped when the buffer is full
+        let mut dropped = vec![];
+        for i in 0..MAX_BUFFERED + 1 {
+            let op = doc1.cursor().field("name")?.assign_str(&i.to_string())?;
+            dropped.extend(sdk2.buffer(&peer, doc2.id(), &hash, op)?);
+        }
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(sdk2.buffered()?.len(), MAX_BUFFERED);
+        assert_eq!(sdk2.drop_buffered(&hash)?.len(), MAX_BUFFERED);
+        assert!(sdk2.buffered()?.is_empty());
+
+        Ok(())
+    }
 }
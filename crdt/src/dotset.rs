@@ -95,6 +95,11 @@ impl DotSet {
         }
     }
 
+    /// Returns an iterator of the [`Dot`]s between `start` and `end` inclusive.
+    pub fn range(&self, start: &Dot, end: &Dot) -> impl Iterator<Item = &Dot> + '_ {
+        self.0.range(*start..=*end)
+    }
+
     /// Returns a new [`DotSet`] containing all [`Dot`]s not in [`ArchivedDotSet`].
     #[must_use]
     pub fn difference(&self, other: &ArchivedDotSet) -> DotSet {
@@ -114,6 +119,13 @@ impl DotSet {
     }
 }
 
+impl ArchivedDotSet {
+    /// Returns an iterator of [`Dot`].
+    pub fn iter(&self) -> impl Iterator<Item = &Dot> + '_ {
+        self.0.iter()
+    }
+}
+
 impl FromIterator<Dot> for DotSet {
     fn from_iter<T: IntoIterator<Item = Dot>>(iter: T) -> Self {
         let mut res = Self::new();
@@ -118,3 +118,64 @@ impl From<DocId> for PeerId {
         Self::new(id.into())
     }
 }
+
+/// Group identifier is the hash of the group name.
+#[derive(
+    Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd, Archive, CheckBytes, Deserialize, Serialize,
+)]
+#[archive(as = "GroupId")]
+#[repr(transparent)]
+pub struct GroupId([u8; 32]);
+
+impl GroupId {
+    /// Creates a new [`GroupId`] from a `[[u8; 32]]`.
+    pub fn new(id: [u8; 32]) -> Self {
+        Self(id)
+    }
+
+    /// Creates a new [`GroupId`] from a group name.
+    pub fn from_name(name: &str) -> Self {
+        Self(blake3::hash(name.as_bytes()).into())
+    }
+}
+
+impl From<GroupId> for [u8; 32] {
+    fn from(id: GroupId) -> Self {
+        id.0
+    }
+}
+
+impl AsRef<[u8; 32]> for GroupId {
+    fn as_ref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for GroupId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut id = [0; 44];
+        base64::encode_config_slice(&self.0, base64::URL_SAFE, &mut id);
+        write!(f, "Group({})", std::str::from_utf8(&id[..4]).expect("wtf?"))
+    }
+}
+
+impl std::fmt::Display for GroupId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut id = [0; 44];
+        base64::encode_config_slice(&self.0, base64::URL_SAFE, &mut id);
+        write!(f, "{}", std::str::from_utf8(&id).expect("wtf?"))
+    }
+}
+
+impl std::str::FromStr for GroupId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 44 {
+            return Err(anyhow::anyhow!("invalid group_id length {}", s.len()));
+        }
+        let mut group_id = [0; 32];
+        base64::decode_config_slice(s, base64::URL_SAFE, &mut group_id)?;
+        Ok(Self(group_id))
+    }
+}
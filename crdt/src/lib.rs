@@ -118,6 +118,14 @@
 //! a `Causal` which includes a set of active paths not contained in the set active dots or expired
 //! dots and the set of expired paths not contained in the set of expired dots.
 //!
+//! Since the `CausalContext` grows with the document, peers supporting it reconcile ranges of
+//! dots instead. A `Sketch` contains a fingerprint of the dots in a range, or the dots themselves
+//! when the range is small. Ranges with differing fingerprints are split until the missing dots
+//! are found, so the traffic is proportional to the difference of the two sets. The paths of
+//! the missing dots are sent in chunks of at most `MAX_CHUNK` dots. The remaining dots are
+//! `Pending` and requested chunk by chunk, from any peer since dots are content addressed, which
+//! allows an interrupted transfer to resume where it left off.
+//!
 //! To ensure the correct nodes form a fully connected component we use a point to point broadcast
 //! protocol. This makes the broadcast protocol sybil resistant and prevents eclipse attacks.
 //!
@@ -130,6 +138,7 @@
 //! transforms which preserve the zero knowledge proofs will be necessary.
 #![warn(missing_docs)]
 mod acl;
+mod clock;
 mod crdt;
 mod crypto;
 mod cursor;
@@ -142,21 +151,30 @@ mod path;
 #[cfg(test)]
 mod props;
 mod radixdb;
+mod reconcile;
 mod registry;
 mod schema;
 mod subscriber;
 mod util;
 
-pub use crate::acl::{Actor, Can, Permission, Policy};
+pub use crate::acl::{
+    Actor, Can, Capability, Explanation, Inference, Membership, Permission, Policy, PolicyStatus,
+    Statement, StatementKind, Step,
+};
+pub use crate::clock::Timestamp;
 pub use crate::crdt::{Causal, CausalContext};
-pub use crate::crypto::Keypair;
+pub use crate::crypto::{Keypair, SealedKey};
 pub use crate::cursor::Cursor;
-pub use crate::doc::{Backend, Doc, Frontend, SchemaInfo};
+pub use crate::doc::{Backend, Buffered, Doc, Frontend, SchemaInfo};
 pub use crate::dotset::{ArchivedDotSet, Dot, DotSet};
-pub use crate::id::{DocId, PeerId};
+pub use crate::id::{DocId, GroupId, PeerId};
 pub use crate::lens::{ArchivedKind, ArchivedLens, ArchivedLenses, Kind, Lens, LensRef, Lenses};
 pub use crate::path::{Path, PathBuf, Segment};
 pub use crate::radixdb::{FileStorage, MemStorage, Storage};
+pub use crate::reconcile::{
+    ArchivedPending, ArchivedSketch, ArchivedSummary, Part, Pending, Range, Sketch, Summary,
+    MAX_CHUNK,
+};
 pub use crate::registry::{Expanded, Hash, Package, Registry};
 pub use crate::schema::{ArchivedSchema, PrimitiveKind, Schema};
 pub use crate::subscriber::{Batch, Event, Iter, Subscriber};
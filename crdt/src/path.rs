@@ -28,6 +28,7 @@ pub enum SegmentType {
     Dot,
     Position,
     Sig,
+    Encrypted,
 }
 
 impl SegmentType {
@@ -45,6 +46,7 @@ impl SegmentType {
             u if u == Dot as u8 => Some(Dot),
             u if u == Position as u8 => Some(Position),
             u if u == Sig as u8 => Some(Sig),
+            u if u == Encrypted as u8 => Some(Encrypted),
             _ => unreachable!("Unexpected SegmentType: {}", u),
         }
     }
@@ -62,7 +64,10 @@ impl SegmentType {
             SegmentType::I64 => size_of::<i64>(),
             SegmentType::Dot => size_of::<Dot>(),
             SegmentType::Sig => size_of::<Signature>(),
-            SegmentType::Str | SegmentType::Position | SegmentType::Policy => {
+            SegmentType::Str
+            | SegmentType::Position
+            | SegmentType::Policy
+            | SegmentType::Encrypted => {
                 if data.len() < 3 {
                     return None;
                 }
@@ -96,7 +101,7 @@ impl SegmentType {
     fn is_variable_length(&self) -> bool {
         matches!(
             self,
-            SegmentType::Position | SegmentType::Str | SegmentType::Policy
+            SegmentType::Position | SegmentType::Str | SegmentType::Policy | SegmentType::Encrypted
         )
     }
 }
@@ -126,6 +131,8 @@ pub enum Segment {
     Position(Fraction),
     /// Signature primitive.
     Sig(Signature),
+    /// Encrypted primitive.
+    Encrypted(Vec<u8>),
 }
 
 impl Segment {
@@ -147,6 +154,7 @@ impl Segment {
             SegmentType::Dot => Self::Dot(Dot::new(data.try_into().unwrap())),
             SegmentType::Position => Self::Position(Fraction::new(data.into())),
             SegmentType::Sig => Self::Sig(Signature::from_bytes(data).unwrap()),
+            SegmentType::Encrypted => Self::Encrypted(data.to_vec()),
         }
     }
 
@@ -256,6 +264,15 @@ impl Segment {
             None
         }
     }
+
+    /// Returns the encrypted primitive.
+    pub fn encrypted(self) -> Option<Vec<u8>> {
+        if let Segment::Encrypted(data) = self {
+            Some(data)
+        } else {
+            None
+        }
+    }
 }
 
 impl std::fmt::Debug for Segment {
@@ -272,6 +289,7 @@ impl std::fmt::Debug for Segment {
             Self::Dot(s) => write!(f, "{:?}", s),
             Self::Position(s) => write!(f, "Position({})", base64::encode(s)),
             Self::Sig(_) => write!(f, "Sig"),
+            Self::Encrypted(_) => write!(f, "Encrypted"),
         }
     }
 }
@@ -321,6 +339,7 @@ impl PathBuf {
             Segment::Dot(d) => self.dot(&d),
             Segment::Position(d) => self.position(&d),
             Segment::Sig(d) => self.sig(d),
+            Segment::Encrypted(d) => self.encrypted(&d),
         }
     }
 
@@ -380,6 +399,11 @@ impl PathBuf {
         self.push(SegmentType::Sig, sig.as_ref());
     }
 
+    /// Appends an encrypted segment.
+    pub fn encrypted(&mut self, data: &[u8]) {
+        self.push(SegmentType::Encrypted, data);
+    }
+
     /// Pops the last segment.
     pub fn pop(&mut self) {
         if let Some(path) = self.as_path().parent() {
@@ -142,6 +142,7 @@ fn arb_causal_for_dotstore(
             // TODO: add some expired
             expired: Default::default(),
             store: store.prefix(path.as_path()),
+            time: Default::default(),
         }
     })
 }
@@ -201,7 +202,7 @@ pub fn arb_causal_for_schema(schema: Schema) -> impl Strategy<Value = Causal> {
 
 pub fn validate(schema: &Schema, value: &Causal) -> bool {
     let schema = Ref::archive(schema);
-    schema.as_ref().validate(value)
+    schema.as_ref().validate(value, false)
 }
 
 prop_compose! {
@@ -331,8 +332,10 @@ pub fn causal_to_crdt(doc: &DocId, causal: &Causal) -> Crdt {
     let storage = Arc::new(MemStorage::default());
     let store = BlobSet::load(storage.clone(), "store").unwrap();
     let expired = BlobSet::load(storage.clone(), "expired").unwrap();
+    let compacted = BlobSet::load(storage.clone(), "compacted").unwrap();
+    let dots = BlobSet::load(storage.clone(), "dots").unwrap();
     let acl = Acl::new(BlobMap::load(storage, "acl").unwrap());
-    let crdt = Crdt::new(store, expired, acl);
+    let crdt = Crdt::new(store, expired, compacted, dots, acl).unwrap();
     crdt.join(&(*doc).into(), causal).unwrap();
     crdt
 }
@@ -0,0 +1,568 @@
+//! Range based set reconciliation of [`CausalContext`]s.
+//!
+//! Instead of sending all dots of a document, a peer sends a [`Sketch`] of a range of dots.
+//! When the fingerprint of a range differs, the range is split into smaller ranges until
+//! the ranges are small enough to send the dots. Since dots are hashes they are uniformly
+//! distributed, so the ranges can be split by the bits of their prefix. The traffic is
+//! proportional to the number of differing dots times the number of rounds.
+use crate::crdt::CausalContext;
+use crate::dotset::{Dot, DotSet};
+use bytecheck::CheckBytes;
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+
+/// Ranges with at most this many dots are sent as is.
+const MAX_DOTS: usize = 16;
+/// Number of bits a range is extended by when it is split.
+const SPLIT_BITS: u8 = 4;
+/// Ranges with this many bits are not split any further.
+const MAX_BITS: u8 = 252;
+/// Maximum number of dots whose paths are sent in a single response.
+pub const MAX_CHUNK: usize = 512;
+
+/// Set of dots of a [`CausalContext`].
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Archive,
+    CheckBytes,
+    Deserialize,
+    Serialize,
+)]
+#[archive(as = "Part")]
+#[repr(u8)]
+pub enum Part {
+    /// Store and expired dots.
+    Seen,
+    /// Expired dots.
+    Expired,
+}
+
+/// Range of dots of a [`Part`] whose first `bits` bits are equal to the `prefix`.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Archive,
+    CheckBytes,
+    Deserialize,
+    Serialize,
+)]
+#[archive(as = "Range")]
+#[repr(C)]
+pub struct Range {
+    part: Part,
+    prefix: [u8; 32],
+    bits: u8,
+}
+
+impl Range {
+    /// Returns the ranges containing all dots of a [`CausalContext`].
+    pub fn roots() -> [Self; 2] {
+        [Part::Seen, Part::Expired].map(|part| Self {
+            part,
+            prefix: [0; 32],
+            bits: 0,
+        })
+    }
+
+    /// Returns the [`Part`] of the range.
+    pub fn part(&self) -> Part {
+        self.part
+    }
+
+    /// Returns the first dot of the range.
+    pub fn start(&self) -> Dot {
+        Dot::new(self.fill(0x00))
+    }
+
+    /// Returns the last dot of the range.
+    pub fn end(&self) -> Dot {
+        Dot::new(self.fill(0xff))
+    }
+
+    /// Replaces the bits after the prefix.
+    fn fill(&self, byte: u8) -> [u8; 32] {
+        let mut dot = self.prefix;
+        for (i, b) in dot.iter_mut().enumerate() {
+            let bits = (self.bits as usize).saturating_sub(i * 8).min(8);
+            let mask = !(0xffu16 >> bits) as u8;
+            *b = (*b & mask) | (byte & !mask);
+        }
+        dot
+    }
+
+    /// Returns true if the range can be split.
+    pub fn is_splittable(&self) -> bool {
+        self.bits % SPLIT_BITS == 0 && self.bits < MAX_BITS
+    }
+
+    /// Splits the range into `2^SPLIT_BITS` ranges.
+    pub fn split(&self) -> impl Iterator<Item = Self> + '_ {
+        let n = if self.is_splittable() {
+            1u8 << SPLIT_BITS
+        } else {
+            0
+        };
+        (0..n).map(move |i| {
+            let mut prefix = self.fill(0x00);
+            prefix[self.bits as usize / 8] |= i << (8 - SPLIT_BITS - self.bits % 8);
+            Self {
+                part: self.part,
+                prefix,
+                bits: self.bits + SPLIT_BITS,
+            }
+        })
+    }
+}
+
+/// Summary of the dots in a range.
+#[derive(Clone, Debug, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(Debug, CheckBytes))]
+#[repr(C)]
+pub enum Summary {
+    /// Number of dots and the truncated hash of the dots.
+    Fingerprint(u64, [u8; 16]),
+    /// Dots in the range.
+    Dots(Vec<Dot>),
+}
+
+/// [`Summary`] of a [`Range`].
+#[derive(Clone, Debug, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(Debug, CheckBytes))]
+#[repr(C)]
+pub struct Sketch {
+    range: Range,
+    summary: Summary,
+}
+
+impl Sketch {
+    /// Summarizes the dots of a [`CausalContext`] in each range. The `compacted` dots are
+    /// counted as expired.
+    pub fn new(ctx: &CausalContext, compacted: &DotSet, ranges: &[Range]) -> Vec<Self> {
+        let parts = Parts::new(ctx, compacted);
+        ranges
+            .iter()
+            .map(|range| {
+                let dots = parts.dots(range);
+                let summary = if dots.len() <= MAX_DOTS || !range.is_splittable() {
+                    Summary::Dots(dots)
+                } else {
+                    fingerprint(&dots)
+                };
+                Self {
+                    range: *range,
+                    summary,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the [`Range`] of the sketch.
+    pub fn range(&self) -> &Range {
+        &self.range
+    }
+
+    /// Returns the [`Summary`] of the sketch.
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+}
+
+/// Result of reconciling [`Sketch`]es with a local [`CausalContext`].
+#[derive(Debug, Default)]
+pub(crate) struct Reconciliation {
+    /// Store dots missing in the remote set.
+    pub store: DotSet,
+    /// Expired dots missing in the remote set.
+    pub expired: DotSet,
+    /// Expired dots contained in the remote set.
+    pub acked: DotSet,
+    /// Ranges that need to be summarized again.
+    pub split: Vec<Range>,
+}
+
+/// Dots a peer is missing whose paths are sent in later chunks. Dots are content
+/// addressed, so the chunks can be requested from any peer that has them.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(Debug, CheckBytes))]
+#[repr(C)]
+pub struct Pending {
+    store: Vec<Dot>,
+    expired: Vec<Dot>,
+}
+
+impl Pending {
+    /// Creates a new [`Pending`] from the missing store and expired dots.
+    pub fn new(store: &DotSet, expired: &DotSet) -> Self {
+        Self {
+            store: store.iter().copied().collect(),
+            expired: expired.iter().copied().collect(),
+        }
+    }
+
+    /// Creates a new [`Pending`] whose first chunks contain the `first` store dots.
+    pub fn with_first(first: &DotSet, store: &DotSet, expired: &DotSet) -> Self {
+        Self {
+            store: first
+                .iter()
+                .chain(store.iter().filter(|dot| !first.contains(dot)))
+                .copied()
+                .collect(),
+            expired: expired.iter().copied().collect(),
+        }
+    }
+
+    /// Returns the missing store dots.
+    pub fn store(&self) -> &[Dot] {
+        &self.store
+    }
+
+    /// Returns the missing expired dots.
+    pub fn expired(&self) -> &[Dot] {
+        &self.expired
+    }
+
+    /// Returns the number of missing dots.
+    pub fn len(&self) -> usize {
+        self.store.len() + self.expired.len()
+    }
+
+    /// Returns true if no dots are missing.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes and returns the next chunk of at most [`MAX_CHUNK`] dots.
+    pub fn next_chunk(&mut self) -> Self {
+        let n = self.store.len().min(MAX_CHUNK);
+        let store = self.store.drain(..n).collect();
+        let n = self.expired.len().min(MAX_CHUNK - n);
+        let expired = self.expired.drain(..n).collect();
+        Self { store, expired }
+    }
+
+    /// Removes the dots that are no longer missing in a [`CausalContext`].
+    pub fn retain_missing(&mut self, ctx: &CausalContext) {
+        self.store
+            .retain(|dot| !ctx.store.contains(dot) && !ctx.expired.contains(dot));
+        self.expired.retain(|dot| !ctx.expired.contains(dot));
+    }
+
+    /// Adds the dots of another [`Pending`].
+    pub fn extend(&mut self, other: Self) {
+        let store: DotSet = self.store.drain(..).chain(other.store).collect();
+        let expired: DotSet = self.expired.drain(..).chain(other.expired).collect();
+        *self = Self::new(&store, &expired);
+    }
+}
+
+impl ArchivedPending {
+    /// Returns the missing store dots.
+    pub fn store(&self) -> &[Dot] {
+        &self.store
+    }
+
+    /// Returns the missing expired dots.
+    pub fn expired(&self) -> &[Dot] {
+        &self.expired
+    }
+}
+
+/// Reconciles the remote [`Sketch`]es with the local [`CausalContext`]. The `compacted`
+/// dots are counted as expired, but are never missing in the remote set.
+pub(crate) fn reconcile(
+    ctx: &CausalContext,
+    compacted: &DotSet,
+    sketches: &Archived<Vec<Sketch>>,
+) -> Reconciliation {
+    let parts = Parts::new(ctx, compacted);
+    let mut res = Reconciliation::default();
+    for sketch in sketches.iter() {
+        let range = &sketch.range;
+        if range.bits > MAX_BITS {
+            tracing::info!("reconcile: invalid range {:?}", range);
+            continue;
+        }
+        let local = parts.dots(range);
+        let (missing, acked): (Vec<Dot>, Vec<Dot>) = match &sketch.summary {
+            ArchivedSummary::Dots(remote) => {
+                let remote: DotSet = remote.iter().copied().collect();
+                local.into_iter().partition(|dot| !remote.contains(dot))
+            }
+            ArchivedSummary::Fingerprint(len, hash) => {
+                if fingerprint(&local) == Summary::Fingerprint(*len, *hash) {
+                    (vec![], local)
+                } else if local.is_empty() {
+                    (vec![], vec![])
+                } else if range.is_splittable() {
+                    res.split.extend(range.split());
+                    (vec![], vec![])
+                } else {
+                    (local, vec![])
+                }
+            }
+        };
+        match range.part {
+            Part::Seen => {
+                for dot in missing.into_iter().filter(|dot| ctx.store.contains(dot)) {
+                    res.store.insert(dot);
+                }
+            }
+            Part::Expired => {
+                for dot in missing.into_iter().filter(|dot| ctx.expired.contains(dot)) {
+                    res.expired.insert(dot);
+                }
+                for dot in acked {
+                    res.acked.insert(dot);
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Compares the [`Sketch`]es of the [`Range::roots`] with the local [`CausalContext`].
+/// Returns `None` if the sketches are not of the roots.
+pub(crate) fn is_synced(
+    ctx: &CausalContext,
+    compacted: &DotSet,
+    sketches: &Archived<Vec<Sketch>>,
+) -> Option<bool> {
+    let roots = Range::roots();
+    if sketches.len() != roots.len()
+        || sketches
+            .iter()
+            .zip(roots.iter())
+            .any(|(sketch, root)| sketch.range != *root)
+    {
+        return None;
+    }
+    let local = Sketch::new(ctx, compacted, &roots);
+    let synced = local.iter().zip(sketches.iter()).all(|(local, remote)| {
+        match (&local.summary, &remote.summary) {
+            (Summary::Dots(a), ArchivedSummary::Dots(b)) => a.as_slice() == b.as_slice(),
+            (Summary::Fingerprint(a, b), ArchivedSummary::Fingerprint(c, d)) => a == c && b == d,
+            _ => false,
+        }
+    });
+    Some(synced)
+}
+
+/// Dots of each [`Part`]. Compacted tombstones are no longer part of the
+/// [`CausalContext`], but the peer has seen their removal.
+struct Parts {
+    seen: DotSet,
+    expired: DotSet,
+}
+
+impl Parts {
+    fn new(ctx: &CausalContext, compacted: &DotSet) -> Self {
+        let mut expired = ctx.expired.clone();
+        expired.union(compacted);
+        let mut seen = ctx.store.clone();
+        seen.union(&expired);
+        Self { seen, expired }
+    }
+
+    fn dots(&self, range: &Range) -> Vec<Dot> {
+        let set = match range.part {
+            Part::Seen => &self.seen,
+            Part::Expired => &self.expired,
+        };
+        set.range(&range.start(), &range.end()).copied().collect()
+    }
+}
+
+fn fingerprint(dots: &[Dot]) -> Summary {
+    let mut hasher = blake3::Hasher::new();
+    for dot in dots {
+        hasher.update(dot.as_ref());
+    }
+    let mut hash = [0; 16];
+    hash.copy_from_slice(&hasher.finalize().as_bytes()[..16]);
+    Summary::Fingerprint(dots.len() as u64, hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Ref;
+
+    fn dot(i: u64) -> Dot {
+        Dot::new(blake3::hash(&i.to_be_bytes()).into())
+    }
+
+    fn ctx(store: impl Iterator<Item = u64>) -> CausalContext {
+        let mut ctx = CausalContext::new();
+        for i in store {
+            ctx.store.insert(dot(i));
+        }
+        ctx
+    }
+
+    /// Runs the protocol until `b` knows which of its dots `a` is missing. Returns the
+    /// reconciliation and the number of bytes sent by both peers.
+    fn run(a: &CausalContext, b: &CausalContext) -> (Reconciliation, usize) {
+        run_compacted(a, &DotSet::new(), b, &DotSet::new())
+    }
+
+    fn run_compacted(
+        a: &CausalContext,
+        a_compacted: &DotSet,
+        b: &CausalContext,
+        b_compacted: &DotSet,
+    ) -> (Reconciliation, usize) {
+        let mut ranges = Range::roots().to_vec();
+        let mut res = Reconciliation::default();
+        let mut bytes = 0;
+        while !ranges.is_empty() {
+            let sketches = Ref::archive(&Sketch::new(a, a_compacted, &ranges));
+            bytes += sketches.as_bytes().len();
+            let round = reconcile(b, b_compacted, sketches.as_ref());
+            ranges = round.split;
+            bytes += Ref::archive(&ranges).as_bytes().len();
+            res.store.union(&round.store);
+            res.expired.union(&round.expired);
+            res.acked.union(&round.acked);
+        }
+        (res, bytes)
+    }
+
+    #[test]
+    fn test_range_split() {
+        let [root, _] = Range::roots();
+        assert_eq!(root.start(), Dot::new([0; 32]));
+        assert_eq!(root.end(), Dot::new([0xff; 32]));
+        let children: Vec<_> = root.split().collect();
+        assert_eq!(children.len(), 16);
+        assert_eq!(children[1].start().as_ref()[0], 0x10);
+        assert_eq!(children[1].end().as_ref()[0], 0x1f);
+        let grandchildren: Vec<_> = children[1].split().collect();
+        assert_eq!(grandchildren[2].start().as_ref()[0], 0x12);
+        assert_eq!(grandchildren[2].end().as_ref()[0], 0x12);
+        assert_eq!(grandchildren[2].end().as_ref()[1], 0xff);
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let a = ctx(0..1000);
+        let mut b = ctx(0..1010);
+        b.expired.insert(dot(0));
+        b.store = b.store.iter().filter(|d| **d != dot(0)).copied().collect();
+        let (res, _) = run(&a, &b);
+        let store: DotSet = (1000..1010).map(dot).collect();
+        assert_eq!(res.store, store);
+        assert_eq!(res.expired, [dot(0)].into_iter().collect());
+
+        let (res, _) = run(&b, &a);
+        assert_eq!(res.store, DotSet::new());
+        assert_eq!(res.expired, DotSet::new());
+    }
+
+    #[test]
+    fn test_reconcile_compacted() {
+        let mut a = ctx(1..1000);
+        a.expired.insert(dot(0));
+        let compacted: DotSet = [dot(0)].into_iter().collect();
+        let b = ctx(1..1000);
+
+        // the removal is acknowledged by a peer that compacted it
+        let (res, _) = run_compacted(&b, &compacted, &a, &DotSet::new());
+        assert_eq!(res.store, DotSet::new());
+        assert_eq!(res.expired, DotSet::new());
+        assert_eq!(res.acked, compacted);
+
+        // compacted tombstones can't be sent
+        let (res, _) = run_compacted(&ctx(0..1000), &DotSet::new(), &b, &compacted);
+        assert_eq!(res.store, DotSet::new());
+        assert_eq!(res.expired, DotSet::new());
+    }
+
+    #[test]
+    fn test_is_synced() {
+        let a = ctx(0..100);
+        let none = DotSet::new();
+        let sketches = Ref::archive(&Sketch::new(&a, &none, &Range::roots()));
+        assert_eq!(is_synced(&a, &none, sketches.as_ref()), Some(true));
+        assert_eq!(
+            is_synced(&ctx(0..101), &none, sketches.as_ref()),
+            Some(false)
+        );
+        assert_eq!(
+            is_synced(&ctx(0..10), &none, sketches.as_ref()),
+            Some(false)
+        );
+
+        let ranges: Vec<_> = Range::roots()[0].split().collect();
+        let sketches = Ref::archive(&Sketch::new(&a, &none, &ranges));
+        assert_eq!(is_synced(&a, &none, sketches.as_ref()), None);
+    }
+
+    #[test]
+    fn test_pending_chunks() {
+        let a = ctx(0..10);
+        let b = ctx(0..(MAX_CHUNK as u64 * 2 + 10));
+        let (res, _) = run(&a, &b);
+        let mut pending = Pending::new(&res.store, &res.expired);
+        assert_eq!(pending.len(), MAX_CHUNK * 2);
+
+        let mut a = a;
+        let chunk = pending.next_chunk();
+        assert_eq!(chunk.len(), MAX_CHUNK);
+        for dot in chunk.store() {
+            a.store.insert(*dot);
+        }
+        assert_eq!(pending.len(), MAX_CHUNK);
+
+        // dots received from elsewhere are no longer requested
+        pending.extend(Pending::new(&res.store, &res.expired));
+        pending.retain_missing(&a);
+        assert_eq!(pending.len(), MAX_CHUNK);
+        assert_eq!(pending.next_chunk().len(), MAX_CHUNK);
+        assert!(pending.next_chunk().is_empty());
+    }
+
+    #[test]
+    fn test_pending_first() {
+        let store = ctx(0..(MAX_CHUNK as u64 + 10)).store;
+        let first = ctx((MAX_CHUNK as u64 - 10)..(MAX_CHUNK as u64 + 10)).store;
+        let mut pending = Pending::with_first(&first, &store, &DotSet::new());
+        assert_eq!(pending.len(), MAX_CHUNK + 10);
+        let chunk = pending.next_chunk();
+        assert_eq!(chunk.len(), MAX_CHUNK);
+        assert!(first.iter().all(|dot| chunk.store().contains(dot)));
+        assert_eq!(pending.next_chunk().len(), 10);
+    }
+
+    #[test]
+    fn test_reconcile_traffic() {
+        let a = ctx(0..10_000);
+        // a full causal context is ~320kb
+        let full = Ref::archive(&a).as_bytes().len();
+        assert!(full > 320_000);
+
+        let (res, one) = run(&a, &ctx(0..10_001));
+        assert_eq!(res.store.iter().count(), 1);
+        let (res, ten) = run(&a, &ctx(0..10_010));
+        assert_eq!(res.store.iter().count(), 10);
+        tracing::info!("full: {} one: {} ten: {}", full, one, ten);
+        assert!(one * 20 < full);
+        assert!(one < ten && ten < one * 10);
+
+        // the traffic grows logarithmically with the size of the set
+        let a = ctx(0..100_000);
+        let (_, large) = run(&a, &ctx(0..100_001));
+        assert!(large < one * 2);
+    }
+}
@@ -1,31 +1,40 @@
-use crate::lens::Lenses;
+use crate::crypto::Keypair;
+use crate::id::PeerId;
+use crate::lens::{Lens, Lenses};
+use crate::radixdb::BlobSet;
 use crate::schema::Schema;
 use crate::util::Ref;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 pub use blake3::Hash;
+use bytecheck::CheckBytes;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
 use parking_lot::RwLock;
 use rkyv::{Archive, Archived, Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
 /// A package of lenses.
 #[derive(Clone, Debug, Eq, PartialEq, Archive, Deserialize, Serialize)]
-#[archive_attr(derive(Debug, Eq, PartialEq))]
+#[archive_attr(derive(Debug, Eq, PartialEq, CheckBytes))]
 #[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
 #[repr(C)]
 pub struct Package {
     name: String,
     version: u32,
     lenses: Vec<u8>,
+    publisher: Option<PeerId>,
+    signature: [u8; 64],
 }
 
 impl Package {
-    /// Creates a new [`Package`].
+    /// Creates a new unsigned [`Package`].
     pub fn new(name: String, version: u32, lenses: &Lenses) -> Self {
         Self {
             name,
             version,
             lenses: Ref::archive(lenses).into(),
+            publisher: None,
+            signature: [0; 64],
         }
     }
 
@@ -35,8 +44,27 @@ impl Package {
             name,
             version: 0,
             lenses: [0; 8].to_vec(),
+            publisher: None,
+            signature: [0; 64],
         }
     }
+
+    /// Signs the name, version and archived lenses of the [`Package`] with the publisher
+    /// [`Keypair`].
+    pub fn sign(&mut self, keypair: Keypair) {
+        let msg = signed_message(&self.name, self.version, &self.lenses);
+        self.publisher = Some(keypair.peer_id());
+        self.signature = keypair.sign(&msg).to_bytes();
+    }
+}
+
+fn signed_message(name: &str, version: u32, lenses: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(8 + name.len() + lenses.len());
+    msg.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    msg.extend_from_slice(name.as_bytes());
+    msg.extend_from_slice(&version.to_le_bytes());
+    msg.extend_from_slice(lenses);
+    msg
 }
 
 impl ArchivedPackage {
@@ -54,6 +82,27 @@ impl ArchivedPackage {
     pub fn lenses(&self) -> &[u8] {
         &self.lenses
     }
+
+    /// Returns the [`PeerId`] of the publisher if the package is signed.
+    pub fn publisher(&self) -> Option<PeerId> {
+        self.publisher.as_ref().copied()
+    }
+
+    /// Verifies the signature of a signed package and returns the publisher.
+    pub fn verify(&self) -> Result<Option<PeerId>> {
+        let publisher = if let Some(publisher) = self.publisher() {
+            publisher
+        } else {
+            return Ok(None);
+        };
+        let pubkey = PublicKey::from_bytes(publisher.as_ref())?;
+        let sig = Signature::from_bytes(&self.signature)?;
+        let msg = signed_message(self.name(), self.version(), self.lenses());
+        pubkey
+            .verify(&msg, &sig)
+            .map_err(|_| anyhow!("invalid signature of package {}", self.name()))?;
+        Ok(Some(publisher))
+    }
 }
 
 /// Expanded lenses.
@@ -95,44 +144,230 @@ impl std::fmt::Debug for Expanded {
     }
 }
 
+/// Returns the key prefix of the trusted publishers of a package name.
+fn trusted_prefix(name: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(4 + name.len());
+    prefix.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    prefix.extend_from_slice(name.as_bytes());
+    prefix
+}
+
 /// Lens registry.
 #[derive(Clone)]
 pub struct Registry {
-    table: Arc<BTreeMap<String, Hash>>,
+    /// Installed packages by name with their publisher.
+    table: Arc<RwLock<BTreeMap<String, (Hash, Option<PeerId>)>>>,
+    /// Trusted publishers keyed by package name and publisher.
+    trusted: BlobSet,
     expanded: Arc<RwLock<BTreeMap<[u8; 32], Arc<Expanded>>>>,
 }
 
 impl Registry {
-    /// Creates a new lens registry.
-    pub fn new(packages: &[u8]) -> Result<Self> {
-        let packages = unsafe { rkyv::archived_root::<Vec<Package>>(packages) };
-        let mut table = BTreeMap::new();
-        let mut expanded = BTreeMap::new();
-        for package in packages.as_ref() {
-            let lenses = Ref::new(package.lenses().into());
+    /// Creates a new lens registry. The trusted publishers are loaded before the packages
+    /// are installed.
+    pub fn new(trusted: BlobSet, packages: &[u8]) -> Result<Self> {
+        let registry = Self {
+            table: Default::default(),
+            trusted,
+            expanded: Default::default(),
+        };
+        registry.install(packages)?;
+        Ok(registry)
+    }
+
+    /// Only accept packages named `name` that are signed by one of the trusted publishers.
+    /// Until a publisher is trusted for a name, unsigned packages are accepted. Returns an
+    /// error if an installed package named `name` isn't signed by a trusted publisher.
+    pub fn trust(&self, name: &str, publisher: PeerId) -> Result<()> {
+        if let Some((_, installed)) = self.table.read().get(name) {
+            let trusted = self.trusted(name).unwrap_or_default();
+            if !installed
+                .map(|peer| peer == publisher || trusted.contains(&peer))
+                .unwrap_or_default()
+            {
+                return Err(anyhow!(
+                    "installed package {} is not signed by a trusted publisher",
+                    name
+                ));
+            }
+        }
+        let mut key = trusted_prefix(name);
+        key.extend_from_slice(publisher.as_ref());
+        self.trusted.insert(key);
+        self.trusted.flush()
+    }
+
+    /// Returns the trusted publishers of packages named `name`.
+    fn trusted(&self, name: &str) -> Option<BTreeSet<PeerId>> {
+        let prefix = trusted_prefix(name);
+        let trusted: BTreeSet<PeerId> = self
+            .trusted
+            .scan_prefix(&prefix)
+            .filter_map(|key| {
+                let peer: [u8; 32] = key[prefix.len()..].try_into().ok()?;
+                Some(PeerId::new(peer))
+            })
+            .collect();
+        if trusted.is_empty() {
+            None
+        } else {
+            Some(trusted)
+        }
+    }
+
+    /// Returns the names of the packages with trusted publishers.
+    fn trusted_names(&self) -> BTreeSet<String> {
+        self.trusted
+            .keys()
+            .filter_map(|key| {
+                let len = u32::from_le_bytes(key.get(..4)?.try_into().ok()?) as usize;
+                let name = key.get(4..4 + len)?;
+                Some(std::str::from_utf8(name).ok()?.to_string())
+            })
+            .collect()
+    }
+
+    /// Installs archived [`Package`]s. Packages with an unknown name or a newer version
+    /// than the installed one are added to the registry. A newer version must extend the
+    /// lenses of the installed version and must be signed by a trusted publisher if any
+    /// publishers are trusted for the name. Returns the names of the installed packages.
+    pub fn install(&self, packages: &[u8]) -> Result<Vec<String>> {
+        let packages = rkyv::check_archived_root::<Vec<Package>>(packages)
+            .map_err(|err| anyhow!("{}", err))?;
+        let mut installed = vec![];
+        for package in packages.iter() {
+            let name = package.name();
+            let publisher = package.verify()?;
+            if let Some(trusted) = self.trusted(name) {
+                if !publisher
+                    .map(|peer| trusted.contains(&peer))
+                    .unwrap_or_default()
+                {
+                    return Err(anyhow!(
+                        "package {} is not signed by a trusted publisher",
+                        name
+                    ));
+                }
+            }
+            let lenses = Ref::<Lenses>::checked(package.lenses())?;
+            if let Some((version, hash)) = self.lookup(name) {
+                let len = lenses.as_ref().lenses().len();
+                if len <= version as usize {
+                    tracing::info!(
+                        "Skipping package {}, version {} is installed",
+                        name,
+                        version
+                    );
+                    continue;
+                }
+                let prev = self.get(&hash).unwrap();
+                if lenses.as_ref().lenses()[..version as usize] != *prev.lenses().lenses() {
+                    return Err(anyhow!(
+                        "package {} is incompatible with the installed version",
+                        name
+                    ));
+                }
+            }
             let hash = blake3::hash(lenses.as_bytes());
-            let name = package.name().into();
-            tracing::info!("Loaded package {}", name);
             tracing::debug!("Lenses {:#?}", lenses);
-            table.insert(name, hash);
-            expanded.insert(hash.into(), Arc::new(Expanded::new(lenses)?));
+            installed.push((
+                name.to_string(),
+                hash,
+                publisher,
+                Arc::new(Expanded::new(lenses)?),
+            ));
         }
-        Ok(Self {
-            table: Arc::new(table),
-            expanded: Arc::new(RwLock::new(expanded)),
-        })
+        let mut table = self.table.write();
+        let mut expanded = self.expanded.write();
+        Ok(installed
+            .into_iter()
+            .map(|(name, hash, publisher, lenses)| {
+                tracing::info!("Loaded package {}", name);
+                expanded.insert(hash.into(), lenses);
+                table.insert(name.clone(), (hash, publisher));
+                name
+            })
+            .collect())
     }
 
-    /// Registers archived [`Lenses`] and returns the [`struct@Hash`].
+    /// Registers archived [`Lenses`] and returns the [`struct@Hash`]. Lenses extending a
+    /// package with trusted publishers are rejected, newer versions of these packages
+    /// need to be installed with a valid signature.
     pub fn register(&self, lenses: &[u8]) -> Result<Hash> {
         let lenses = Ref::<Lenses>::checked(lenses)?;
         let hash = blake3::hash(lenses.as_bytes());
+        if !self.contains(&hash) {
+            for name in self.trusted_names() {
+                let installed = match self.lookup(&name).and_then(|(_, hash)| self.get(&hash)) {
+                    Some(installed) => installed,
+                    None => continue,
+                };
+                let installed = installed.lenses().lenses();
+                let remote = lenses.as_ref().lenses();
+                if !installed.is_empty()
+                    && remote.len() > installed.len()
+                    && remote[..installed.len()] == *installed
+                {
+                    return Err(anyhow!(
+                        "lenses extending package {} are not signed by a trusted publisher",
+                        name
+                    ));
+                }
+            }
+        }
         self.expanded
             .write()
             .insert(hash.into(), Arc::new(Expanded::new(lenses)?));
         Ok(hash)
     }
 
+    /// Registers the first `version` lenses of the [`Lenses`] identified by [`struct@Hash`]
+    /// and returns the [`struct@Hash`] of the truncated [`Lenses`].
+    pub fn register_version(&self, hash: &Hash, version: u32) -> Result<Hash> {
+        let expanded = self
+            .get(hash)
+            .ok_or_else(|| anyhow!("missing lenses with hash {}", hash))?;
+        let lenses = expanded.lenses().lenses();
+        if version as usize > lenses.len() {
+            return Err(anyhow!("lenses {} have no version {}", hash, version));
+        }
+        if version as usize == lenses.len() {
+            return Ok(*hash);
+        }
+        let lenses = lenses[..version as usize]
+            .iter()
+            .map(|lens| lens.deserialize(&mut rkyv::Infallible))
+            .collect::<Result<Vec<Lens>, _>>()?;
+        self.register(Ref::archive(&Lenses::new(lenses)).as_bytes())
+    }
+
+    /// Returns an error if publishers are trusted for `name` and the [`Lenses`] identified
+    /// by [`struct@Hash`] aren't a version of the installed package.
+    pub fn check_trusted(&self, name: &str, hash: &Hash) -> Result<()> {
+        if self.trusted(name).is_none() {
+            return Ok(());
+        }
+        let lenses = self
+            .get(hash)
+            .ok_or_else(|| anyhow!("missing lenses with hash {}", hash))?;
+        let lenses = lenses.lenses().lenses();
+        let installed = self.lookup(name).and_then(|(_, hash)| self.get(&hash));
+        let is_version = installed
+            .map(|installed| {
+                let installed = installed.lenses().lenses();
+                lenses.len() <= installed.len() && installed[..lenses.len()] == *lenses
+            })
+            .unwrap_or_default();
+        if !is_version {
+            return Err(anyhow!(
+                "lenses {} are not a version of the trusted package {}",
+                hash,
+                name
+            ));
+        }
+        Ok(())
+    }
+
     /// Returns the schema.
     pub fn get(&self, hash: &Hash) -> Option<Arc<Expanded>> {
         self.expanded.read().get(hash.as_bytes()).cloned()
@@ -140,7 +375,7 @@ impl Registry {
 
     /// Returns the schema by name.
     pub fn lookup(&self, id: &str) -> Option<(u32, Hash)> {
-        let hash = *self.table.get(id)?;
+        let (hash, _) = *self.table.read().get(id)?;
         let len = self
             .expanded
             .read()
@@ -156,3 +391,23 @@ impl Registry {
         self.expanded.read().contains_key(hash.as_bytes())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lens::Kind;
+
+    #[test]
+    fn test_signature_covers_version() -> Result<()> {
+        let lenses = Lenses::new(vec![Lens::Make(Kind::Struct)]);
+        let mut package = Package::new("notes".into(), 1, &lenses);
+        package.sign(Keypair::generate());
+        let signed = Ref::archive(&package);
+        assert!(signed.as_ref().verify()?.is_some());
+
+        package.version = 2;
+        let replayed = Ref::archive(&package);
+        assert!(replayed.as_ref().verify().is_err());
+        Ok(())
+    }
+}
@@ -75,18 +75,19 @@ impl Default for Schema {
 }
 
 impl ArchivedSchema {
-    /// Returns if [`Causal`] matches [`ArchivedSchema`].
-    pub fn validate(&self, causal: &Causal) -> bool {
-        self._validate(causal) == Some(true)
+    /// Returns if [`Causal`] matches [`ArchivedSchema`]. Encrypted values only match if
+    /// `encrypted` is set, which is the case for documents with key statements.
+    pub fn validate(&self, causal: &Causal, encrypted: bool) -> bool {
+        self._validate(causal, encrypted) == Some(true)
     }
 
-    fn _validate(&self, causal: &Causal) -> Option<bool> {
+    fn _validate(&self, causal: &Causal, encrypted: bool) -> Option<bool> {
         for buf in causal.store.iter() {
             let path = buf.as_path();
             let path = verify_sig(path)?;
             let (doc, path) = path.split_first()?;
             doc.doc()?;
-            if self.validate_path(path) != Some(true) {
+            if self.validate_path(path, encrypted) != Some(true) {
                 tracing::error!("invalid path {}", path);
                 return Some(false);
             }
@@ -101,7 +102,7 @@ impl ArchivedSchema {
                 tracing::error!("policy cannot be expired");
                 return Some(false);
             }
-            if self.validate_path(path) != Some(true) {
+            if self.validate_path(path, encrypted) != Some(true) {
                 tracing::error!("invalid expired path {}", path);
                 return Some(false);
             }
@@ -109,7 +110,7 @@ impl ArchivedSchema {
         Some(true)
     }
 
-    fn validate_path(&self, path: Path) -> Option<bool> {
+    fn validate_path(&self, path: Path, encrypted: bool) -> Option<bool> {
         if validate_policy(path) == Some(true) {
             return Some(true);
         }
@@ -124,17 +125,20 @@ impl ArchivedSchema {
                 let (nonce, path) = path.split_first()?;
                 nonce.nonce()?;
                 let (prim, path) = path.split_first()?;
-                Some(kind.validate(prim) && path.is_empty())
+                // encrypted values are validated by the readers
+                let valid =
+                    (encrypted && matches!(prim, Segment::Encrypted(_))) || kind.validate(prim);
+                Some(valid && path.is_empty())
             }
             Self::Table(kind, schema) => {
                 let (key, path) = path.split_first()?;
-                Some(kind.validate(key) && schema.validate_path(path)?)
+                Some(kind.validate(key) && schema.validate_path(path, encrypted)?)
             }
             Self::Struct(fields) => {
                 let (field, path) = path.split_first()?;
                 let field = field.prim_str()?;
                 let schema = fields.get(field)?;
-                Some(schema.validate_path(path)?)
+                Some(schema.validate_path(path, encrypted)?)
             }
             Self::Array(schema) => {
                 let (prim, path) = path.split_first()?;
@@ -145,7 +149,8 @@ impl ArchivedSchema {
                             let mut path = path.into_iter();
                             path.next()?.position()?;
                             path.next()?.prim_u64()?;
-                            schema.validate_path(path.collect::<PathBuf>().as_path())
+                            let path = path.collect::<PathBuf>();
+                            schema.validate_path(path.as_path(), encrypted)
                         }
                         "META" => {
                             // <path_to_array>.META.<uid>.<nonce>.<nonce>.<pos>.<nonce>.<peer>.<sig>
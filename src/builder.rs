@@ -0,0 +1,215 @@
+use crate::{AntiEntropyConfig, Sdk};
+use anyhow::Result;
+use libp2p::Multiaddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tlfs_crdt::{Backend, Storage};
+
+/// DNS resolver used to resolve `/dns` addresses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DnsResolver {
+    /// The resolver configured by the operating system.
+    System,
+    /// Cloudflare's public resolver.
+    Cloudflare,
+    /// Google's public resolver.
+    Google,
+    /// Quad9's public resolver.
+    Quad9,
+}
+
+/// Networking configuration of an [`Sdk`].
+#[derive(Clone, Debug)]
+pub struct NetworkConfig {
+    /// Addresses to listen on.
+    pub listen_on: Vec<Multiaddr>,
+    /// WebRTC signaling servers. The [`Sdk`] listens on `/p2p-webrtc-star` of each server.
+    pub signaling_servers: Vec<Multiaddr>,
+    /// STUN and TURN server urls used to establish WebRTC connections.
+    pub ice_servers: Vec<String>,
+    /// Circuit relay servers. The [`Sdk`] listens on `/p2p-circuit` of each server to be
+    /// reachable behind a NAT. The addresses must end with `/p2p/<peer id>`.
+    pub relays: Vec<Multiaddr>,
+    /// Rendezvous servers used to discover the peers of a document. The addresses must end
+    /// with `/p2p/<peer id>`.
+    pub rendezvous_servers: Vec<Multiaddr>,
+    /// DNS resolver. Ignored in the browser.
+    pub dns: DnsResolver,
+    /// Discover peers in the local network using mdns. Ignored in the browser.
+    pub mdns: bool,
+    /// Interval between two pings of a connected peer.
+    pub ping_interval: Duration,
+    /// Time after which a ping is considered failed.
+    pub ping_timeout: Duration,
+    /// Configuration of the periodic anti-entropy sync.
+    pub anti_entropy: AntiEntropyConfig,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        let mut listen_on = vec![];
+        if !cfg!(target_family = "wasm") {
+            listen_on.push("/ip4/0.0.0.0/tcp/0".parse().unwrap());
+        }
+        Self {
+            listen_on,
+            signaling_servers: vec!["/dns4/local1st.net/tcp/443/wss".parse().unwrap()],
+            ice_servers: vec!["stun:stun.l.google.com:19302".into()],
+            relays: vec![],
+            rendezvous_servers: vec![],
+            dns: DnsResolver::Cloudflare,
+            mdns: true,
+            ping_interval: Duration::from_secs(3),
+            ping_timeout: Duration::from_secs(1),
+            anti_entropy: AntiEntropyConfig::default(),
+        }
+    }
+}
+
+enum StorageConfig {
+    Memory,
+    #[cfg(not(target_family = "wasm"))]
+    Filesystem(std::path::PathBuf),
+    #[cfg(target_family = "wasm")]
+    Browser(String),
+    Custom(Arc<dyn Storage>),
+}
+
+/// Builder for an [`Sdk`]. Defaults to an in-memory [`Sdk`] using the public
+/// infrastructure configured by [`NetworkConfig::default`].
+pub struct SdkBuilder {
+    package: Vec<u8>,
+    storage: StorageConfig,
+    network: NetworkConfig,
+    offline: bool,
+}
+
+impl SdkBuilder {
+    /// Creates a new [`SdkBuilder`] for a schema package.
+    pub fn new(package: &[u8]) -> Self {
+        Self {
+            package: package.to_vec(),
+            storage: StorageConfig::Memory,
+            network: NetworkConfig::default(),
+            offline: false,
+        }
+    }
+
+    /// Keeps the state in memory.
+    pub fn memory(mut self) -> Self {
+        self.storage = StorageConfig::Memory;
+        self
+    }
+
+    /// Persists the state in the file system.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn filesystem(mut self, db: &std::path::Path) -> Self {
+        self.storage = StorageConfig::Filesystem(db.to_owned());
+        self
+    }
+
+    /// Persists the state in the browser cache.
+    #[cfg(target_family = "wasm")]
+    pub fn browser(mut self, name: &str) -> Self {
+        self.storage = StorageConfig::Browser(name.to_owned());
+        self
+    }
+
+    /// Persists the state in a custom [`Storage`].
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = StorageConfig::Custom(storage);
+        self
+    }
+
+    /// Replaces the [`NetworkConfig`].
+    pub fn network(mut self, network: NetworkConfig) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Replaces the addresses to listen on.
+    pub fn listen_on(mut self, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.network.listen_on = addrs.into_iter().collect();
+        self
+    }
+
+    /// Replaces the WebRTC signaling servers.
+    pub fn signaling_servers(mut self, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.network.signaling_servers = addrs.into_iter().collect();
+        self
+    }
+
+    /// Replaces the STUN and TURN servers.
+    pub fn ice_servers(mut self, urls: impl IntoIterator<Item = String>) -> Self {
+        self.network.ice_servers = urls.into_iter().collect();
+        self
+    }
+
+    /// Replaces the circuit relay servers.
+    pub fn relays(mut self, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.network.relays = addrs.into_iter().collect();
+        self
+    }
+
+    /// Replaces the rendezvous servers.
+    pub fn rendezvous_servers(mut self, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.network.rendezvous_servers = addrs.into_iter().collect();
+        self
+    }
+
+    /// Sets the DNS resolver.
+    pub fn dns(mut self, dns: DnsResolver) -> Self {
+        self.network.dns = dns;
+        self
+    }
+
+    /// Enables or disables the discovery of peers in the local network.
+    pub fn mdns(mut self, enabled: bool) -> Self {
+        self.network.mdns = enabled;
+        self
+    }
+
+    /// Sets the ping interval and timeout.
+    pub fn ping(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.network.ping_interval = interval;
+        self.network.ping_timeout = timeout;
+        self
+    }
+
+    /// Sets the configuration of the periodic anti-entropy sync.
+    pub fn anti_entropy(mut self, config: AntiEntropyConfig) -> Self {
+        self.network.anti_entropy = config;
+        self
+    }
+
+    /// Disables networking. The [`NetworkConfig`] is ignored and documents are synced
+    /// using [`Sdk::sync_request`], [`Sdk::answer_sync`] and [`Sdk::apply_sync`].
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Creates the [`Sdk`].
+    pub async fn build(self) -> Result<Sdk> {
+        crate::init_tracing();
+        let storage: Arc<dyn Storage> = match self.storage {
+            StorageConfig::Memory => Arc::new(tlfs_crdt::MemStorage::default()),
+            #[cfg(not(target_family = "wasm"))]
+            StorageConfig::Filesystem(db) => Arc::new(tlfs_crdt::FileStorage::new(&db)),
+            #[cfg(target_family = "wasm")]
+            StorageConfig::Browser(name) => Arc::new(
+                tlfs_crdt::BrowserCacheStorage::new(name)
+                    .await
+                    .map_err(|err| anyhow::anyhow!("{:?}", err))?,
+            ),
+            StorageConfig::Custom(storage) => storage,
+        };
+        if self.offline {
+            let backend = Backend::new(storage, &self.package)?;
+            let frontend = backend.frontend();
+            let peer = frontend.default_keypair()?.peer_id();
+            return Ok(Sdk::new_offline(backend, frontend, peer));
+        }
+        Sdk::new(storage, &self.package, self.network).await
+    }
+}
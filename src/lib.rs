@@ -2,18 +2,27 @@
 //!
 //! See the `tlfs_crdt` docs for details of how it works.
 #![deny(missing_docs)]
+mod builder;
+mod offline;
 mod sync;
 mod transport;
 
-pub use crate::sync::{libp2p_peer_id, Invite, ToLibp2pKeypair, ToLibp2pPublic};
+pub use crate::builder::{DnsResolver, NetworkConfig, SdkBuilder};
+pub use crate::offline::{BackendSync, FrontendSync};
+pub use crate::sync::{
+    libp2p_peer_id, AntiEntropyConfig, AntiEntropyStats, BufferStats, DropReason, DroppedCausal,
+    Invite, InviteLink, PairingLink, PeerSyncStatus, SyncStatus, ToLibp2pKeypair, ToLibp2pPublic,
+};
 pub use libp2p::Multiaddr;
 pub use tlfs_crdt::{
-    Actor, ArchivedSchema, Backend, Can, Causal, Cursor, DocId, Event, Frontend, Keypair, Kind,
-    Lens, Lenses, Package, PathBuf, PeerId, Permission, PrimitiveKind, Ref, Schema, Subscriber,
+    Actor, ArchivedSchema, Backend, Can, Capability, Causal, CausalContext, Cursor, DocId, Dot,
+    Event, Explanation, Frontend, GroupId, Hash, Inference, Keypair, Kind, Lens, Lenses,
+    Membership, Package, PathBuf, PeerId, Permission, PolicyStatus, PrimitiveKind, Ref, Schema,
+    Statement, StatementKind, Step, Storage, Subscriber, Timestamp,
 };
 
 use crate::sync::{notify, Behaviour};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use futures::{
     channel::{mpsc, oneshot},
     future::poll_fn,
@@ -21,10 +30,13 @@ use futures::{
 };
 use libp2p::{
     core::{muxing::StreamMuxerBox, transport::Boxed},
+    multiaddr::Protocol,
+    relay::v1::Relay,
     swarm::{AddressScore, SwarmEvent},
     Swarm,
 };
 use std::collections::BTreeSet;
+use std::pin::Pin;
 use std::task::Poll;
 
 /// Main entry point for `tlfs`.
@@ -32,6 +44,7 @@ pub struct Sdk {
     frontend: Frontend,
     peer: PeerId,
     swarm: mpsc::UnboundedSender<Command>,
+    offline: bool,
     #[cfg(not(target_family = "wasm"))]
     _task: async_global_executor::Task<()>,
 }
@@ -40,32 +53,25 @@ impl Sdk {
     /// Creates a new [`Sdk`] instance using browser persistence.
     #[cfg(target_family = "wasm")]
     pub async fn browser(name: &str, package: &[u8]) -> Result<Self> {
-        init_tracing();
-        let package = package.to_vec();
-        let name = name.to_owned();
-        let storage = std::sync::Arc::new(tlfs_crdt::BrowserCacheStorage::new(name).await.unwrap());
-        Self::new(storage, &package).await
+        SdkBuilder::new(package).browser(name).build().await
     }
 
     /// Creates a new [`Sdk`] instance using file system persistence.
     #[cfg(not(target_family = "wasm"))]
     pub async fn filesystem(db: &std::path::Path, package: &[u8]) -> Result<Self> {
-        init_tracing();
-        Self::new(
-            std::sync::Arc::new(tlfs_crdt::FileStorage::new(db)),
-            package,
-        )
-        .await
+        SdkBuilder::new(package).filesystem(db).build().await
     }
 
     /// Create a new in-memory [`Sdk`] instance.
     pub async fn memory(package: &[u8]) -> Result<Self> {
-        init_tracing();
-        let storage = std::sync::Arc::new(tlfs_crdt::MemStorage::default());
-        Self::new(storage, package).await
+        SdkBuilder::new(package).build().await
     }
 
-    async fn new(storage: std::sync::Arc<dyn tlfs_crdt::Storage>, package: &[u8]) -> Result<Self> {
+    async fn new(
+        storage: std::sync::Arc<dyn tlfs_crdt::Storage>,
+        package: &[u8],
+        network: NetworkConfig,
+    ) -> Result<Self> {
         let backend = Backend::new(storage, package)?;
         let frontend = backend.frontend();
 
@@ -73,13 +79,19 @@ impl Sdk {
         let peer = keypair.peer_id();
         tracing::info!("our peer id is: {}", peer);
 
-        let transport = transport::transport(keypair.to_libp2p())?;
-        let mut listen_on = vec!["/dns4/local1st.net/tcp/443/wss/p2p-webrtc-star"
-            .parse()
-            .unwrap()];
-        if !cfg!(target_family = "wasm") {
-            listen_on.push("/ip4/0.0.0.0/tcp/0".parse().unwrap());
-        }
+        let (transport, relay) = transport::transport(keypair.to_libp2p(), &network)?;
+        let listen_on = network
+            .signaling_servers
+            .iter()
+            .map(|addr| addr.clone().with(Protocol::P2pWebRtcStar))
+            .chain(
+                network
+                    .relays
+                    .iter()
+                    .map(|addr| addr.clone().with(Protocol::P2pCircuit)),
+            )
+            .chain(network.listen_on.iter().cloned())
+            .collect::<Vec<_>>();
 
         //TODO
         //        slf.add_external_address(
@@ -89,7 +101,10 @@ impl Sdk {
         //            // TODO
         //            AddressScore::Infinite,
         //        )
-        Self::new_with_transport(backend, frontend, peer, transport, listen_on.into_iter()).await
+        Self::new_with_config(
+            backend, frontend, peer, transport, relay, &network, listen_on,
+        )
+        .await
     }
 
     /// Creates a new [`Sdk`] instance from the given [`Backend`], [`Frontend`] and libp2p
@@ -101,7 +116,24 @@ impl Sdk {
         transport: Boxed<(libp2p::PeerId, StreamMuxerBox)>,
         listen_on: impl Iterator<Item = Multiaddr>,
     ) -> Result<Self> {
-        let behaviour = Behaviour::new(backend).await?;
+        let network = NetworkConfig::default();
+        let listen_on = listen_on.collect();
+        Self::new_with_config(
+            backend, frontend, peer, transport, None, &network, listen_on,
+        )
+        .await
+    }
+
+    async fn new_with_config(
+        backend: Backend,
+        frontend: Frontend,
+        peer: PeerId,
+        transport: Boxed<(libp2p::PeerId, StreamMuxerBox)>,
+        relay: Option<Relay>,
+        network: &NetworkConfig,
+        listen_on: Vec<Multiaddr>,
+    ) -> Result<Self> {
+        let behaviour = Behaviour::new(backend, &peer, network, relay).await?;
         let mut swarm = Swarm::new(transport, behaviour, peer.to_libp2p().to_peer_id());
         for i in listen_on {
             swarm.listen_on(i)?;
@@ -158,6 +190,17 @@ impl Sdk {
                     Command::Invite(peer, doc, schema) => {
                         swarm.behaviour_mut().invite(&peer, doc, schema);
                     }
+                    Command::Redeem(peer, doc, causal) => {
+                        if let Err(err) = swarm.behaviour_mut().redeem(&peer, doc, causal) {
+                            tracing::error!("{}", err);
+                        }
+                    }
+                    Command::AddPairing(token, root) => {
+                        swarm.behaviour_mut().add_pairing(token, root);
+                    }
+                    Command::Pair(peer, device, secret) => {
+                        swarm.behaviour_mut().pair(&peer, device, secret);
+                    }
                     Command::Invites(tx) => {
                         let invites = swarm.behaviour_mut().clear_invites();
                         tx.send(invites).ok();
@@ -165,15 +208,77 @@ impl Sdk {
                     Command::SubscribeInvites(ch) => {
                         swarm.behaviour_mut().subscribe_invites(ch);
                     }
+                    Command::InstallPackage(package, tx) => {
+                        let res = swarm.behaviour_mut().install_package(&package);
+                        tx.send(res).ok();
+                    }
+                    Command::ConfigureAntiEntropy(config) => {
+                        swarm.behaviour_mut().configure_anti_entropy(config);
+                    }
+                    Command::AntiEntropyStats(doc, tx) => {
+                        let stats = swarm.behaviour_mut().anti_entropy_stats(&doc);
+                        tx.send(stats).ok();
+                    }
+                    Command::SyncStatus(doc, tx) => {
+                        let status = swarm.behaviour_mut().sync_status(&doc);
+                        tx.send(status).ok();
+                    }
+                    Command::SubscribeSyncStatus(doc, ch) => {
+                        swarm.behaviour_mut().subscribe_sync_status(&doc, ch);
+                    }
+                    Command::DroppedCausals(tx) => {
+                        let dropped = swarm.behaviour_mut().clear_dropped();
+                        tx.send(dropped).ok();
+                    }
+                    Command::SubscribeDroppedCausals(ch) => {
+                        swarm.behaviour_mut().subscribe_dropped(ch);
+                    }
+                    Command::BufferStats(tx) => {
+                        let stats = swarm.behaviour_mut().buffer_stats();
+                        tx.send(stats).ok();
+                    }
+                    Command::AnswerSync(peer, request, tx) => {
+                        let res = swarm.behaviour_mut().answer_sync(&peer, &request);
+                        tx.send(res).ok();
+                    }
+                    Command::ApplySync(peer, doc, response, tx) => {
+                        let res = swarm.behaviour_mut().apply_sync(&peer, &doc, &response);
+                        tx.send(res).ok();
+                    }
+                    Command::ApplyDelta(peer, doc, delta, tx) => {
+                        let res = swarm.behaviour_mut().apply_delta(&peer, &doc, &delta);
+                        tx.send(res).ok();
+                    }
                 };
             }
             while swarm.behaviour_mut().poll_backend(cx).is_ready() {}
             while let Poll::Ready(Some(ev)) = swarm.poll_next_unpin(cx) {
                 match ev {
-                    SwarmEvent::NewListenAddr { .. } => notify(&mut sub_addresses),
-                    SwarmEvent::ExpiredListenAddr { .. } => notify(&mut sub_addresses),
-                    SwarmEvent::ConnectionEstablished { .. } => notify(&mut sub_connected_peers),
-                    SwarmEvent::ConnectionClosed { .. } => notify(&mut sub_connected_peers),
+                    SwarmEvent::NewListenAddr { address, .. } => {
+                        // relayed addresses are registered at the rendezvous servers
+                        if address.iter().any(|p| p == Protocol::P2pCircuit) {
+                            swarm.add_external_address(address, AddressScore::Infinite);
+                        }
+                        notify(&mut sub_addresses);
+                    }
+                    SwarmEvent::ExpiredListenAddr { address, .. } => {
+                        swarm.remove_external_address(&address);
+                        notify(&mut sub_addresses);
+                    }
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                        swarm.behaviour_mut().connection_established(&peer_id);
+                        notify(&mut sub_connected_peers);
+                    }
+                    SwarmEvent::ConnectionClosed {
+                        peer_id,
+                        num_established,
+                        ..
+                    } => {
+                        if num_established == 0 {
+                            swarm.behaviour_mut().connection_closed(&peer_id);
+                        }
+                        notify(&mut sub_connected_peers);
+                    }
                     _ => {}
                 }
             }
@@ -191,11 +296,105 @@ impl Sdk {
             frontend,
             peer,
             swarm: tx,
+            offline: false,
             #[cfg(not(target_family = "wasm"))]
             _task,
         })
     }
 
+    /// Creates a new [`Sdk`] instance without networking. Documents are synced using
+    /// [`Sdk::sync_request`], [`Sdk::answer_sync`] and [`Sdk::apply_sync`] over a channel
+    /// chosen by the application. Invites and pairings require networking and return an
+    /// error.
+    pub fn new_offline(mut backend: Backend, frontend: Frontend, peer: PeerId) -> Self {
+        let (tx, mut rx) = mpsc::unbounded();
+        // keeps the subscriptions open, they are never notified
+        let mut subscriptions = vec![];
+        let driver = poll_fn::<(), _>(move |cx| {
+            while let Poll::Ready(Some(cmd)) = rx.poll_next_unpin(cx) {
+                // changes applied before a command are visible to it
+                while Pin::new(&mut backend).poll(cx).is_ready() {}
+                match cmd {
+                    Command::Addresses(ch) => {
+                        ch.send(vec![]).ok();
+                    }
+                    Command::LocalPeers(ch) => {
+                        ch.send(Default::default()).ok();
+                    }
+                    Command::ConnectedPeers(ch) => {
+                        ch.send(vec![]).ok();
+                    }
+                    Command::Invites(tx) => {
+                        tx.send(vec![]).ok();
+                    }
+                    Command::InstallPackage(package, tx) => {
+                        let res = backend
+                            .install_package(&package)
+                            .and_then(|_| backend.join_buffered());
+                        tx.send(res).ok();
+                    }
+                    Command::AntiEntropyStats(_, tx) => {
+                        tx.send(None).ok();
+                    }
+                    Command::SyncStatus(_, tx) => {
+                        tx.send(Default::default()).ok();
+                    }
+                    Command::DroppedCausals(tx) => {
+                        tx.send(vec![]).ok();
+                    }
+                    Command::BufferStats(tx) => {
+                        tx.send(Default::default()).ok();
+                    }
+                    Command::AnswerSync(peer, request, tx) => {
+                        tx.send(backend.answer_sync(&peer, &request)).ok();
+                    }
+                    Command::ApplySync(peer, doc, response, tx) => {
+                        tx.send(backend.apply_sync(&peer, &doc, &response)).ok();
+                    }
+                    Command::ApplyDelta(peer, doc, delta, tx) => {
+                        tx.send(backend.apply_delta(&peer, &doc, &delta)).ok();
+                    }
+                    Command::SubscribeAddresses(ch)
+                    | Command::SubscribeLocalPeers(ch)
+                    | Command::SubscribeConnectedPeers(ch)
+                    | Command::SubscribeInvites(ch)
+                    | Command::SubscribeSyncStatus(_, ch)
+                    | Command::SubscribeDroppedCausals(ch) => {
+                        subscriptions.push(ch);
+                    }
+                    Command::AddAddress(_, _)
+                    | Command::AddExternalAddress(_, _)
+                    | Command::RemoveAddress(_, _)
+                    | Command::Subscribe(_)
+                    | Command::Broadcast(_, _)
+                    | Command::Invite(_, _, _)
+                    | Command::Redeem(_, _, _)
+                    | Command::AddPairing(_, _)
+                    | Command::Pair(_, _, _)
+                    | Command::ConfigureAntiEntropy(_) => {}
+                }
+            }
+            while Pin::new(&mut backend).poll(cx).is_ready() {}
+            Poll::Pending
+        });
+
+        #[cfg(not(target_family = "wasm"))]
+        let _task = async_global_executor::spawn(driver);
+        #[cfg(target_family = "wasm")]
+        wasm_bindgen_futures::spawn_local(async move {
+            driver.await;
+        });
+
+        Self {
+            frontend,
+            peer,
+            swarm: tx,
+            offline: true,
+            #[cfg(not(target_family = "wasm"))]
+            _task,
+        }
+    }
+
     /// Returns the [`PeerId`] of this [`Sdk`].
     pub fn peer_id(&self) -> &PeerId {
         &self.peer
@@ -288,6 +487,143 @@ impl Sdk {
         rx
     }
 
+    /// Installs a schema package at runtime. New schemas become available to
+    /// [`Sdk::create_doc`] and existing documents are migrated to newer versions.
+    pub async fn install_package(&self, package: &[u8]) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm
+            .unbounded_send(Command::InstallPackage(package.to_vec(), tx))
+            .unwrap();
+        rx.await?
+    }
+
+    /// Configures the periodic anti-entropy sync of documents.
+    pub fn configure_anti_entropy(&self, config: AntiEntropyConfig) {
+        self.swarm
+            .unbounded_send(Command::ConfigureAntiEntropy(config))
+            .ok();
+    }
+
+    /// Returns the counters of the anti-entropy sync of a document.
+    pub fn anti_entropy_stats(
+        &self,
+        doc: &DocId,
+    ) -> impl Future<Output = Option<AntiEntropyStats>> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm
+            .unbounded_send(Command::AntiEntropyStats(*doc, tx))
+            .unwrap();
+        async move { rx.await.unwrap() }
+    }
+
+    /// Returns the sync status of a document with each peer it was synced with.
+    pub fn sync_status(&self, doc: &DocId) -> impl Future<Output = SyncStatus> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm
+            .unbounded_send(Command::SyncStatus(*doc, tx))
+            .unwrap();
+        async move { rx.await.unwrap() }
+    }
+
+    /// Subscribe to sync status changes of a document.
+    pub fn subscribe_sync_status(&self, doc: &DocId) -> impl Stream<Item = ()> {
+        let (tx, rx) = mpsc::channel(1);
+        self.swarm
+            .unbounded_send(Command::SubscribeSyncStatus(*doc, tx))
+            .unwrap();
+        rx
+    }
+
+    /// Clears and returns the remote changes that were dropped because their schema
+    /// could not be obtained.
+    pub fn dropped_causals(&self) -> impl Future<Output = Vec<DroppedCausal>> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm
+            .unbounded_send(Command::DroppedCausals(tx))
+            .unwrap();
+        async move { rx.await.unwrap() }
+    }
+
+    /// Subscribe to dropped remote changes.
+    pub fn subscribe_dropped_causals(&self) -> impl Stream<Item = ()> {
+        let (tx, rx) = mpsc::channel(1);
+        self.swarm
+            .unbounded_send(Command::SubscribeDroppedCausals(tx))
+            .unwrap();
+        rx
+    }
+
+    /// Creates a request for the changes to a document other peers have. The request is
+    /// answered by a peer using [`Sdk::answer_sync`].
+    pub fn sync_request(&self, doc: &DocId) -> Result<Vec<u8>> {
+        self.frontend.sync_request(doc)
+    }
+
+    /// Creates a message containing a local change to a document. The message is applied by
+    /// a peer using [`Sdk::apply_delta`].
+    pub fn sync_delta(&self, doc: &DocId, causal: Causal) -> Result<Vec<u8>> {
+        self.frontend.sync_delta(doc, causal)
+    }
+
+    /// Answers a request created by [`Sdk::sync_request`] of an authenticated peer.
+    pub async fn answer_sync(&self, peer: &PeerId, request: &[u8]) -> Result<Vec<u8>> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm
+            .unbounded_send(Command::AnswerSync(*peer, request.to_vec(), tx))
+            .unwrap();
+        rx.await?
+    }
+
+    /// Creates a request for the lenses of a schema returned by [`Sdk::apply_sync`] or
+    /// [`Sdk::apply_delta`]. The request is answered by a peer using [`Sdk::answer_sync`]
+    /// and the response applied using [`Sdk::apply_sync`].
+    pub fn lenses_request(&self, schema: &Hash) -> Result<Vec<u8>> {
+        self.frontend.lenses_request(schema)
+    }
+
+    /// Applies the response of an authenticated peer to a request for a document. Returns
+    /// the schema of the changes that are buffered until its lenses are applied.
+    pub async fn apply_sync(
+        &self,
+        peer: &PeerId,
+        doc: &DocId,
+        response: &[u8],
+    ) -> Result<Option<Hash>> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm
+            .unbounded_send(Command::ApplySync(*peer, *doc, response.to_vec(), tx))
+            .unwrap();
+        rx.await?
+    }
+
+    /// Applies a message created by [`Sdk::sync_delta`] of an authenticated peer. Returns
+    /// the schema of the change if it is buffered, like [`Sdk::apply_sync`].
+    pub async fn apply_delta(
+        &self,
+        peer: &PeerId,
+        doc: &DocId,
+        delta: &[u8],
+    ) -> Result<Option<Hash>> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm
+            .unbounded_send(Command::ApplyDelta(*peer, *doc, delta.to_vec(), tx))
+            .unwrap();
+        rx.await?
+    }
+
+    /// Returns the counters of the remote changes waiting for their schema.
+    pub fn buffer_stats(&self) -> impl Future<Output = BufferStats> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm.unbounded_send(Command::BufferStats(tx)).unwrap();
+        async move { rx.await.unwrap() }
+    }
+
+    /// Only accept schema packages named `name` signed by one of the trusted publishers.
+    /// The trusted publishers are persisted.
+    pub fn trust_publisher(&self, name: &str, publisher: PeerId) -> Result<()> {
+        self.frontend.trust_publisher(name, publisher)
+    }
+
     /// Returns an iterator of [`DocId`].
     pub fn docs(&self, schema: String) -> impl Iterator<Item = Result<DocId>> + '_ {
         self.frontend.docs_by_schema(schema)
@@ -308,7 +644,7 @@ impl Sdk {
         self.swarm
             .unbounded_send(Command::Subscribe(*doc.id()))
             .ok();
-        Ok(Doc::new(doc, self.swarm.clone()))
+        Ok(Doc::new(doc, self.peer, self.swarm.clone(), self.offline))
     }
 
     /// Adds a document with a [`Schema`].
@@ -318,13 +654,121 @@ impl Sdk {
         self.swarm
             .unbounded_send(Command::Subscribe(*doc.id()))
             .ok();
-        Ok(Doc::new(doc, self.swarm.clone()))
+        Ok(Doc::new(doc, self.peer, self.swarm.clone(), self.offline))
+    }
+
+    /// Redeems an [`InviteLink`]. Adds the document and sends the redemption to the peer
+    /// that minted the capability. The document is synced once the redemption is accepted.
+    pub fn redeem_invite(&self, link: &InviteLink) -> Result<Doc> {
+        if self.offline {
+            return Err(anyhow!("redeeming an invite requires networking"));
+        }
+        for addr in &link.addrs {
+            self.add_address(link.peer, addr.clone());
+        }
+        let doc = self.add_doc(link.capability.doc(), &link.schema)?;
+        let causal = doc.cursor().redeem(&link.capability)?;
+        self.swarm
+            .unbounded_send(Command::Redeem(link.peer, *doc.id(), causal))
+            .ok();
+        Ok(doc)
+    }
+
+    /// Creates a [`PairingLink`] to link a new device to the keypair of this [`Sdk`]. The
+    /// link can be used once. The device is certified in every document of this [`Sdk`].
+    pub async fn pairing_link(&self) -> Result<PairingLink> {
+        if self.offline {
+            return Err(anyhow!("pairing requires networking"));
+        }
+        let secret = Keypair::generate();
+        self.swarm
+            .unbounded_send(Command::AddPairing(secret.peer_id(), self.peer))
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        self.swarm.unbounded_send(Command::Addresses(tx)).unwrap();
+        Ok(PairingLink {
+            secret,
+            peer: self.peer,
+            addrs: rx.await?,
+        })
+    }
+
+    /// Pairs this [`Sdk`] as a device of the peer that created the [`PairingLink`]. The
+    /// documents of the peer are added and synced once the pairing is accepted.
+    pub fn pair(&self, link: &PairingLink) -> Result<()> {
+        if self.offline {
+            return Err(anyhow!("pairing requires networking"));
+        }
+        for addr in &link.addrs {
+            self.add_address(link.peer, addr.clone());
+        }
+        self.swarm
+            .unbounded_send(Command::Pair(link.peer, self.peer, link.secret))
+            .ok();
+        Ok(())
+    }
+
+    /// Returns the devices linked to the keypair of this [`Sdk`].
+    pub fn devices(&self) -> Result<Vec<PeerId>> {
+        self.frontend.devices(self.peer).collect()
+    }
+
+    /// Unlinks a device from the keypair of this [`Sdk`]. The device loses the permissions
+    /// of this [`Sdk`] in every document.
+    pub fn unlink_device(&self, device: &PeerId) -> Result<()> {
+        for (doc, causal) in self.frontend.unlink_device(&self.peer, device)? {
+            self.swarm
+                .unbounded_send(Command::Broadcast(doc, causal))
+                .ok();
+        }
+        Ok(())
+    }
+
+    /// Rotates the keypair of this [`Sdk`], for example when it is suspected to be
+    /// compromised. The permissions of the old keypair are transferred to the new keypair in
+    /// every document. The new keypair identifies the [`Sdk`] on the network after a
+    /// restart.
+    pub fn rotate_keypair(&self) -> Result<PeerId> {
+        let (peer, statements) = self.frontend.rotate_keypair(&self.peer)?;
+        for (doc, causal) in statements {
+            self.swarm
+                .unbounded_send(Command::Broadcast(doc, causal))
+                .ok();
+        }
+        Ok(peer)
+    }
+
+    /// Quarantines a compromised peer in a document. All replicas purge the updates of the
+    /// peer, except for the updates contained in `keep`.
+    pub fn quarantine_peer(
+        &self,
+        doc: &DocId,
+        peer: &PeerId,
+        keep: Option<&CausalContext>,
+    ) -> Result<()> {
+        let causal = self.frontend.quarantine_peer(doc, peer, keep)?;
+        self.swarm
+            .unbounded_send(Command::Broadcast(*doc, causal))
+            .ok();
+        Ok(())
     }
 
     /// Returns a document handle.
     pub fn doc(&self, id: DocId) -> Result<Doc> {
         let doc = self.frontend.doc(id)?;
-        Ok(Doc::new(doc, self.swarm.clone()))
+        Ok(Doc::new(doc, self.peer, self.swarm.clone(), self.offline))
+    }
+
+    /// Returns a document handle at a pinned version of its [`Schema`]. The document needs
+    /// to be migrated to the version with [`Sdk::migrate_doc`] first.
+    pub fn doc_at(&self, id: DocId, version: u32) -> Result<Doc> {
+        let doc = self.frontend.doc_at(id, version)?;
+        Ok(Doc::new(doc, self.peer, self.swarm.clone(), self.offline))
+    }
+
+    /// Migrates a document to a version of its [`Schema`].
+    pub fn migrate_doc(&self, id: &DocId, version: u32) -> Result<()> {
+        self.frontend.migrate_doc(id, version)
     }
 
     /// Removes a document.
@@ -375,12 +819,24 @@ fn init_tracing() {
 #[derive(Clone)]
 pub struct Doc {
     doc: tlfs_crdt::Doc,
+    peer: PeerId,
     swarm: mpsc::UnboundedSender<Command>,
+    offline: bool,
 }
 
 impl Doc {
-    fn new(doc: tlfs_crdt::Doc, swarm: mpsc::UnboundedSender<Command>) -> Self {
-        Self { doc, swarm }
+    fn new(
+        doc: tlfs_crdt::Doc,
+        peer: PeerId,
+        swarm: mpsc::UnboundedSender<Command>,
+        offline: bool,
+    ) -> Self {
+        Self {
+            doc,
+            peer,
+            swarm,
+            offline,
+        }
     }
 
     /// Returns the document identifier.
@@ -405,6 +861,9 @@ impl Doc {
     /// Invite peer. Make sure the peer has at least read permission before
     /// doing this.
     pub fn invite(&self, peer: PeerId) -> Result<()> {
+        if self.offline {
+            return Err(anyhow!("inviting a peer requires networking"));
+        }
         let schema = self.doc.schema()?;
         self.swarm
             .unbounded_send(Command::Invite(
@@ -415,6 +874,38 @@ impl Doc {
             .unwrap();
         Ok(())
     }
+
+    /// Creates an [`InviteLink`] for a [`Capability`] minted with
+    /// [`Cursor::say_token`]. The link contains the addresses the [`Sdk`] is listening on.
+    pub async fn invite_link(&self, capability: Capability) -> Result<InviteLink> {
+        let schema = self.doc.schema()?.as_ref().name.to_string();
+        let (tx, rx) = oneshot::channel();
+        self.swarm.unbounded_send(Command::Addresses(tx)).unwrap();
+        Ok(InviteLink {
+            capability,
+            schema,
+            peer: self.peer,
+            addrs: rx.await?,
+        })
+    }
+
+    /// Returns the sync status of the document. See [`Sdk::sync_status`].
+    pub fn sync_status(&self) -> impl Future<Output = SyncStatus> {
+        let (tx, rx) = oneshot::channel();
+        self.swarm
+            .unbounded_send(Command::SyncStatus(*self.id(), tx))
+            .unwrap();
+        async move { rx.await.unwrap() }
+    }
+
+    /// Subscribe to sync status changes of the document.
+    pub fn subscribe_sync_status(&self) -> impl Stream<Item = ()> {
+        let (tx, rx) = mpsc::channel(1);
+        self.swarm
+            .unbounded_send(Command::SubscribeSyncStatus(*self.id(), tx))
+            .unwrap();
+        rx
+    }
 }
 
 enum Command {
@@ -430,8 +921,32 @@ enum Command {
     Subscribe(DocId),
     Broadcast(DocId, Causal),
     Invite(PeerId, DocId, String),
+    Redeem(PeerId, DocId, Causal),
+    AddPairing(PeerId, PeerId),
+    Pair(PeerId, PeerId, Keypair),
     Invites(oneshot::Sender<Vec<Invite>>),
     SubscribeInvites(mpsc::Sender<()>),
+    InstallPackage(Vec<u8>, oneshot::Sender<Result<()>>),
+    ConfigureAntiEntropy(AntiEntropyConfig),
+    AntiEntropyStats(DocId, oneshot::Sender<Option<AntiEntropyStats>>),
+    SyncStatus(DocId, oneshot::Sender<SyncStatus>),
+    SubscribeSyncStatus(DocId, mpsc::Sender<()>),
+    DroppedCausals(oneshot::Sender<Vec<DroppedCausal>>),
+    SubscribeDroppedCausals(mpsc::Sender<()>),
+    BufferStats(oneshot::Sender<BufferStats>),
+    AnswerSync(PeerId, Vec<u8>, oneshot::Sender<Result<Vec<u8>>>),
+    ApplySync(
+        PeerId,
+        DocId,
+        Vec<u8>,
+        oneshot::Sender<Result<Option<Hash>>>,
+    ),
+    ApplyDelta(
+        PeerId,
+        DocId,
+        Vec<u8>,
+        oneshot::Sender<Result<Option<Hash>>>,
+    ),
 }
 
 #[cfg(test)]
@@ -545,4 +1060,320 @@ mod tests {
 
         Ok(())
     }
+
+    #[async_std::test]
+    async fn test_offline_sync() -> Result<()> {
+        let lenses = vec![
+            Lens::Make(Kind::Struct),
+            Lens::AddProperty("title".into()),
+            Lens::Make(Kind::Reg(PrimitiveKind::Str)).lens_in("title"),
+        ];
+        let packages = vec![Package::new("notes".into(), 3, &Lenses::new(lenses))];
+        let package = Ref::archive(&packages);
+        let sdk = SdkBuilder::new(package.as_bytes())
+            .offline()
+            .build()
+            .await?;
+        let sdk2 = SdkBuilder::new(package.as_bytes())
+            .offline()
+            .build()
+            .await?;
+        assert!(sdk.addresses().await.is_empty());
+
+        let doc = sdk.create_doc("notes").await?;
+        let op = doc.cursor().field("title")?.assign_str("offline")?;
+        doc.apply(op)?;
+        let op = doc
+            .cursor()
+            .say_can(Some(*sdk2.peer_id()), Permission::Write)?;
+        doc.apply(op)?;
+
+        let doc2 = sdk2.add_doc(*doc.id(), "notes")?;
+        let request = sdk2.sync_request(doc.id())?;
+        let response = sdk.answer_sync(sdk2.peer_id(), &request).await?;
+        sdk2.apply_sync(sdk.peer_id(), doc.id(), &response).await?;
+        let value = doc2.cursor().field("title")?.strs()?.next().unwrap()?;
+        assert_eq!(value, "offline");
+
+        // responses for a different document are rejected
+        let other = sdk2.create_doc("notes").await?;
+        assert!(sdk2
+            .apply_sync(sdk.peer_id(), other.id(), &response)
+            .await
+            .is_err());
+        assert!(other.cursor().field("title")?.strs()?.next().is_none());
+
+        let op = doc2.cursor().field("title")?.assign_str("synced")?;
+        let delta = sdk2.sync_delta(doc2.id(), op.clone())?;
+        doc2.apply(op)?;
+        assert_eq!(
+            sdk.apply_delta(sdk2.peer_id(), doc.id(), &delta).await?,
+            None
+        );
+        let value = doc.cursor().field("title")?.strs()?.next().unwrap()?;
+        assert_eq!(value, "synced");
+
+        // invites and pairings require networking
+        assert!(doc.invite(*sdk2.peer_id()).is_err());
+        assert!(sdk.pairing_link().await.is_err());
+        let link = PairingLink {
+            secret: Keypair::generate(),
+            peer: *sdk.peer_id(),
+            addrs: vec![],
+        };
+        assert!(sdk2.pair(&link).is_err());
+
+        // the lenses of a newer schema are requested from the peer
+        let lenses = vec![
+            Lens::Make(Kind::Struct),
+            Lens::AddProperty("title".into()),
+            Lens::Make(Kind::Reg(PrimitiveKind::Str)).lens_in("title"),
+            Lens::RenameProperty("title".into(), "name".into()),
+        ];
+        let packages = vec![Package::new("notes".into(), 4, &Lenses::new(lenses))];
+        let package = Ref::archive(&packages);
+        let sdk3 = SdkBuilder::new(package.as_bytes())
+            .offline()
+            .build()
+            .await?;
+        let doc3 = sdk3.create_doc("notes").await?;
+        let op = doc3.cursor().field("name")?.assign_str("renamed")?;
+        doc3.apply(op)?;
+        let op = doc3
+            .cursor()
+            .say_can(Some(*sdk.peer_id()), Permission::Read)?;
+        doc3.apply(op)?;
+
+        let doc = sdk.add_doc(*doc3.id(), "notes")?;
+        let request = sdk.sync_request(doc.id())?;
+        let response = sdk3.answer_sync(sdk.peer_id(), &request).await?;
+        let schema = sdk
+            .apply_sync(sdk3.peer_id(), doc.id(), &response)
+            .await?
+            .unwrap();
+        assert!(doc.cursor().field("title")?.strs()?.next().is_none());
+        let request = sdk.lenses_request(&schema)?;
+        let response = sdk3.answer_sync(sdk.peer_id(), &request).await?;
+        assert_eq!(
+            sdk.apply_sync(sdk3.peer_id(), doc.id(), &response).await?,
+            None
+        );
+        let value = doc.cursor().field("title")?.strs()?.next().unwrap()?;
+        assert_eq!(value, "renamed");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_builder() -> Result<()> {
+        let lenses = vec![
+            Lens::Make(Kind::Struct),
+            Lens::AddProperty("title".into()),
+            Lens::Make(Kind::Reg(PrimitiveKind::Str)).lens_in("title"),
+        ];
+        let packages = vec![Package::new("notes".into(), 3, &Lenses::new(lenses))];
+        let package = Ref::archive(&packages);
+
+        let sdk = SdkBuilder::new(package.as_bytes()).memory().build().await?;
+        let doc = sdk.create_doc("notes").await?;
+        let op = doc.cursor().field("title")?.assign_str("memory")?;
+        doc.apply(op)?;
+        let value = doc.cursor().field("title")?.strs()?.next().unwrap()?;
+        assert_eq!(value, "memory");
+
+        let storage: std::sync::Arc<dyn Storage> =
+            std::sync::Arc::new(tlfs_crdt::MemStorage::default());
+        let sdk = SdkBuilder::new(package.as_bytes())
+            .storage(storage.clone())
+            .offline()
+            .build()
+            .await?;
+        assert!(sdk.addresses().await.is_empty());
+        let doc = sdk.create_doc("notes").await?;
+        let op = doc.cursor().field("title")?.assign_str("offline")?;
+        doc.apply(op)?;
+        drop(sdk);
+
+        // the state is kept in the configured storage
+        let sdk = SdkBuilder::new(package.as_bytes())
+            .storage(storage)
+            .offline()
+            .build()
+            .await?;
+        let doc = sdk.doc(*doc.id())?;
+        let value = doc.cursor().field("title")?.strs()?.next().unwrap()?;
+        assert_eq!(value, "offline");
+
+        Ok(())
+    }
+
+    async fn in_sync(sdk: &Sdk, doc: &DocId, peer: &PeerId) -> bool {
+        sdk.sync_status(doc)
+            .await
+            .peers
+            .get(peer)
+            .map(|status| status.in_sync)
+            .unwrap_or_default()
+    }
+
+    #[async_std::test]
+    async fn test_sync_status() -> Result<()> {
+        let lenses = vec![
+            Lens::Make(Kind::Struct),
+            Lens::AddProperty("title".into()),
+            Lens::Make(Kind::Reg(PrimitiveKind::Str)).lens_in("title"),
+        ];
+        let packages = vec![Package::new("notes".into(), 3, &Lenses::new(lenses))];
+        let package = Ref::archive(&packages);
+        let config = AntiEntropyConfig {
+            interval: Duration::from_millis(100),
+            max_interval: Duration::from_millis(100),
+            jitter: 0.0,
+            peers: 1,
+        };
+        let sdk = Sdk::memory(package.as_bytes()).await?;
+        let sdk2 = Sdk::memory(package.as_bytes()).await?;
+        sdk.configure_anti_entropy(config.clone());
+        sdk2.configure_anti_entropy(config);
+        let mut local_peers = sdk.subscribe_local_peers();
+        local_peers.next().await;
+
+        let doc = sdk.create_doc("notes").await?;
+        let op = doc.cursor().field("title")?.assign_str("synced")?;
+        doc.apply(op)?;
+        let op = doc
+            .cursor()
+            .say_can(Some(*sdk2.peer_id()), Permission::Write)?;
+        doc.apply(op)?;
+        sdk2.add_doc(*doc.id(), "notes")?;
+
+        // both peers report in sync after reconciling
+        let mut synced = false;
+        for _ in 0..100 {
+            if in_sync(&sdk, doc.id(), sdk2.peer_id()).await
+                && in_sync(&sdk2, doc.id(), sdk.peer_id()).await
+            {
+                synced = true;
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(synced);
+
+        // a local change marks the document out of sync
+        let op = doc.cursor().field("title")?.assign_str("edited")?;
+        doc.apply(op)?;
+        assert!(!in_sync(&sdk, doc.id(), sdk2.peer_id()).await);
+
+        Ok(())
+    }
+
+    /// Builds an [`Sdk`] that only discovers peers at a rendezvous server and registers
+    /// its listen addresses as external addresses.
+    async fn rendezvous_sdk(package: &[u8], server: &Multiaddr) -> Result<Sdk> {
+        let sdk = SdkBuilder::new(package)
+            .listen_on(vec!["/ip4/127.0.0.1/tcp/0".parse()?])
+            .signaling_servers(vec![])
+            .rendezvous_servers(vec![server.clone()])
+            .mdns(false)
+            .anti_entropy(AntiEntropyConfig {
+                interval: Duration::from_millis(100),
+                max_interval: Duration::from_millis(100),
+                jitter: 0.0,
+                peers: 1,
+            })
+            .build()
+            .await?;
+        let addrs = loop {
+            let addrs = sdk.addresses().await;
+            if !addrs.is_empty() {
+                break addrs;
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        };
+        for addr in addrs {
+            sdk.add_external_address(addr, AddressScore::Infinite);
+        }
+        Ok(sdk)
+    }
+
+    #[async_std::test]
+    async fn test_rendezvous() -> Result<()> {
+        use libp2p::{identity, rendezvous};
+
+        let lenses = vec![
+            Lens::Make(Kind::Struct),
+            Lens::AddProperty("title".into()),
+            Lens::Make(Kind::Reg(PrimitiveKind::Str)).lens_in("title"),
+        ];
+        let packages = vec![Package::new("notes".into(), 3, &Lenses::new(lenses))];
+        let package = Ref::archive(&packages);
+
+        let keypair = identity::Keypair::generate_ed25519();
+        let server_peer = keypair.public().to_peer_id();
+        let network = NetworkConfig {
+            signaling_servers: vec![],
+            ..Default::default()
+        };
+        let (transport, _) = crate::transport::transport(keypair, &network)?;
+        let behaviour = rendezvous::server::Behaviour::new(Default::default());
+        let mut server = Swarm::new(transport, behaviour, server_peer);
+        server.listen_on("/ip4/127.0.0.1/tcp/0".parse()?)?;
+        let addr = loop {
+            if let Some(SwarmEvent::NewListenAddr { address, .. }) = server.next().await {
+                break address.with(Protocol::P2p(server_peer.into()));
+            }
+        };
+        let (tx, mut registered) = mpsc::unbounded();
+        async_std::task::spawn(async move {
+            while let Some(event) = server.next().await {
+                if let SwarmEvent::Behaviour(rendezvous::server::Event::PeerRegistered {
+                    peer,
+                    ..
+                }) = event
+                {
+                    tx.unbounded_send(peer).ok();
+                }
+            }
+        });
+
+        let sdk = rendezvous_sdk(package.as_bytes(), &addr).await?;
+        let sdk2 = rendezvous_sdk(package.as_bytes(), &addr).await?;
+        let doc = sdk.create_doc("notes").await?;
+        let op = doc.cursor().field("title")?.assign_str("discovered")?;
+        doc.apply(op)?;
+        let op = doc
+            .cursor()
+            .say_can(Some(*sdk2.peer_id()), Permission::Write)?;
+        doc.apply(op)?;
+
+        // the document is registered before the second peer discovers it
+        let peer = sdk.peer_id().to_libp2p().to_peer_id();
+        let wait = async {
+            while let Some(registered) = registered.next().await {
+                if registered == peer {
+                    break;
+                }
+            }
+        };
+        async_std::future::timeout(Duration::from_secs(10), wait).await?;
+        let doc2 = sdk2.add_doc(*doc.id(), "notes")?;
+
+        // the second peer dials the discovered peer and syncs the document
+        let mut synced = false;
+        for _ in 0..100 {
+            if let Some(title) = doc2.cursor().field("title")?.strs()?.next() {
+                assert_eq!(title?, "discovered");
+                synced = true;
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(synced);
+        let peers = sdk2.connected_peers().await;
+        assert!(peers.contains(sdk.peer_id()));
+        assert!(!peers.contains(sdk2.peer_id()));
+
+        Ok(())
+    }
 }
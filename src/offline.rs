@@ -0,0 +1,146 @@
+use crate::sync::{ArchivedSyncRequest, ArchivedSyncResponse, Delta, SyncRequest, SyncResponse};
+use anyhow::{anyhow, Result};
+use rkyv::{Archived, Deserialize};
+use tlfs_crdt::{Backend, Causal, CausalContext, DocId, Frontend, Hash, PeerId, Ref};
+
+/// Creates the messages of the sync protocol without a network. The messages are the
+/// archived [`SyncRequest`], [`SyncResponse`] and [`Delta`] types exchanged by the [`Sdk`]
+/// and can be shipped over any channel.
+///
+/// [`Sdk`]: crate::Sdk
+pub trait FrontendSync {
+    /// Creates a request for the changes to a document we are missing. The request is
+    /// answered using [`BackendSync::answer_sync`].
+    fn sync_request(&self, doc: &DocId) -> Result<Vec<u8>>;
+
+    /// Creates a message containing a local change to a document. The message is applied
+    /// using [`BackendSync::apply_delta`].
+    fn sync_delta(&self, doc: &DocId, causal: Causal) -> Result<Vec<u8>>;
+
+    /// Creates a request for the lenses of a schema reported missing by
+    /// [`BackendSync::apply_sync`] or [`BackendSync::apply_delta`]. The request is answered
+    /// using [`BackendSync::answer_sync`] and the response applied using
+    /// [`BackendSync::apply_sync`].
+    fn lenses_request(&self, schema: &Hash) -> Result<Vec<u8>>;
+}
+
+impl FrontendSync for Frontend {
+    fn sync_request(&self, doc: &DocId) -> Result<Vec<u8>> {
+        let ctx = self.ctx(doc)?;
+        let req = Ref::archive(&SyncRequest::Unjoin(*doc, ctx));
+        Ok(req.as_bytes().to_vec())
+    }
+
+    fn sync_delta(&self, doc: &DocId, causal: Causal) -> Result<Vec<u8>> {
+        let schema = self.schema(doc)?.as_ref().hash();
+        let delta = Ref::archive(&Delta {
+            schema: schema.into(),
+            causal,
+        });
+        Ok(delta.as_bytes().to_vec())
+    }
+
+    fn lenses_request(&self, schema: &Hash) -> Result<Vec<u8>> {
+        let req = Ref::archive(&SyncRequest::Lenses((*schema).into()));
+        Ok(req.as_bytes().to_vec())
+    }
+}
+
+/// Answers and applies the messages of the sync protocol without a network. The
+/// transport is responsible for authenticating the peer a message was received from,
+/// it is used to enforce the read and write permissions of the document.
+pub trait BackendSync {
+    /// Answers a request created by [`FrontendSync::sync_request`].
+    fn answer_sync(&mut self, peer: &PeerId, request: &[u8]) -> Result<Vec<u8>>;
+
+    /// Applies the response to a request for a document. Changes with a schema we
+    /// don't have the lenses for are buffered and the schema is returned. Its lenses are
+    /// requested using [`FrontendSync::lenses_request`].
+    fn apply_sync(&mut self, peer: &PeerId, doc: &DocId, response: &[u8]) -> Result<Option<Hash>>;
+
+    /// Applies a message created by [`FrontendSync::sync_delta`]. Returns the schema of the
+    /// change if it was buffered, like [`BackendSync::apply_sync`].
+    fn apply_delta(&mut self, peer: &PeerId, doc: &DocId, delta: &[u8]) -> Result<Option<Hash>>;
+}
+
+impl BackendSync for Backend {
+    fn answer_sync(&mut self, peer: &PeerId, request: &[u8]) -> Result<Vec<u8>> {
+        let request = Ref::<SyncRequest>::checked(request)?;
+        let resp = match request.as_ref() {
+            ArchivedSyncRequest::Lenses(hash) => {
+                let hash = Hash::from(*hash);
+                let lenses = self
+                    .registry()
+                    .get(&hash)
+                    .ok_or_else(|| anyhow!("missing lenses with hash {}", hash))?;
+                SyncResponse::Lenses(lenses.as_ref().as_ref().to_vec())
+            }
+            ArchivedSyncRequest::Unjoin(doc, ctx) => unjoin(self, peer, doc, ctx)?,
+            _ => return Err(anyhow!("unsupported sync request")),
+        };
+        Ok(Ref::archive(&resp).as_bytes().to_vec())
+    }
+
+    fn apply_sync(&mut self, peer: &PeerId, doc: &DocId, response: &[u8]) -> Result<Option<Hash>> {
+        let response = Ref::<SyncResponse>::checked(response)?;
+        match response.as_ref() {
+            ArchivedSyncResponse::Lenses(lenses) => {
+                self.registry().register(lenses)?;
+                self.join_buffered()?;
+                Ok(None)
+            }
+            ArchivedSyncResponse::Unjoin(schema, causal) => {
+                let causal = causal.deserialize(&mut rkyv::Infallible)?;
+                join(self, peer, doc, &Hash::from(*schema), causal)
+            }
+            _ => Err(anyhow!("unsupported sync response")),
+        }
+    }
+
+    fn apply_delta(&mut self, peer: &PeerId, doc: &DocId, delta: &[u8]) -> Result<Option<Hash>> {
+        let delta = Ref::<Delta>::checked(delta)?.to_owned()?;
+        join(self, peer, doc, &Hash::from(delta.schema), delta.causal)
+    }
+}
+
+/// Answers an unjoin request. The tombstones are compacted when the peer acknowledged new
+/// ones.
+pub(crate) fn unjoin(
+    backend: &mut Backend,
+    peer: &PeerId,
+    doc: &DocId,
+    ctx: &Archived<CausalContext>,
+) -> Result<SyncResponse> {
+    let schema = backend.frontend().schema(doc)?.as_ref().hash();
+    if backend.ack(peer, doc, ctx)? {
+        let n = backend.compact(doc)?;
+        if n > 0 {
+            tracing::debug!("compacted {} tombstones of {}", n, doc);
+        }
+    }
+    let causal = backend.unjoin(peer, doc, ctx)?;
+    Ok(SyncResponse::Unjoin(schema.into(), causal))
+}
+
+/// Joins a remote change or buffers it if the lenses of its schema are missing. Returns the
+/// schema of a buffered change.
+fn join(
+    backend: &mut Backend,
+    peer: &PeerId,
+    doc: &DocId,
+    schema: &Hash,
+    causal: Causal,
+) -> Result<Option<Hash>> {
+    if backend.registry().contains(schema) {
+        backend.join(peer, doc, schema, causal)?;
+        return Ok(None);
+    }
+    for dropped in backend.buffer(peer, doc, schema, causal)? {
+        tracing::warn!(
+            "dropped buffered change to {} from {}",
+            dropped.doc,
+            dropped.peer
+        );
+    }
+    Ok(Some(*schema))
+}
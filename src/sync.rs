@@ -1,22 +1,30 @@
-use anyhow::{bail, Result};
+use crate::offline::{self, BackendSync};
+use crate::NetworkConfig;
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use bytecheck::CheckBytes;
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use futures::{
     channel::mpsc,
     io::{AsyncRead, AsyncWrite},
     prelude::*,
 };
+use futures_timer::Delay;
+use instant::Instant;
 #[cfg(not(target_family = "wasm"))]
 use libp2p::mdns;
 use libp2p::{
+    multiaddr::Protocol,
     ping,
+    relay::v1::Relay,
+    rendezvous::{self, Cookie, Namespace},
     request_response::{
         self, ProtocolName, ProtocolSupport, RequestId, RequestResponse, RequestResponseCodec,
         RequestResponseConfig,
     },
     swarm::{
         dial_opts::{DialOpts, PeerCondition},
+        toggle::Toggle,
         NetworkBehaviour, NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters,
     },
     Multiaddr, NetworkBehaviour,
@@ -24,14 +32,17 @@ use libp2p::{
 use libp2p_broadcast::{Broadcast, BroadcastConfig, BroadcastEvent, Topic};
 use rkyv::{Archive, Deserialize, Serialize};
 use std::{
-    collections::{BTreeSet, VecDeque},
+    collections::{BTreeMap, BTreeSet, VecDeque},
     convert::TryInto,
     io,
     pin::Pin,
     task::{Context, Poll},
     time::Duration,
 };
-use tlfs_crdt::{Backend, Causal, CausalContext, DocId, Hash, Keypair, PeerId, Ref};
+use tlfs_crdt::{
+    Backend, Buffered, Capability, Causal, CausalContext, DocId, Hash, Keypair, PeerId, Pending,
+    Range, Ref, Sketch, Timestamp,
+};
 
 macro_rules! unwrap {
     ($r:expr) => {
@@ -54,6 +65,18 @@ impl ProtocolName for SyncProtocol {
     }
 }
 
+/// Set reconciliation replacing the exchange of full causal contexts, negotiated as version
+/// 1.1.0 of the sync protocol. The changes are sent in chunks of bounded size. Peers that
+/// don't support it are synced using [`SyncRequest::Unjoin`].
+#[derive(Clone)]
+pub struct ReconcileProtocol;
+
+impl ProtocolName for ReconcileProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        "/tlfs/sync/1.1.0".as_bytes()
+    }
+}
+
 #[derive(Debug, Archive, Deserialize, Serialize)]
 #[archive_attr(derive(Debug, CheckBytes))]
 #[repr(C)]
@@ -61,6 +84,8 @@ pub enum SyncRequest {
     Invite(DocId, String),
     Lenses([u8; 32]),
     Unjoin(DocId, CausalContext),
+    Redeem(DocId, [u8; 32], Causal),
+    Pair([u8; 32], [u8; 64]),
 }
 
 #[derive(Debug, Archive, Deserialize, Serialize)]
@@ -70,6 +95,32 @@ pub enum SyncResponse {
     Invite,
     Lenses(Vec<u8>),
     Unjoin([u8; 32], Causal),
+    Redeem,
+    Pair(Vec<PairedDoc>),
+}
+
+#[derive(Debug, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(Debug, CheckBytes))]
+#[repr(C)]
+pub enum ReconcileRequest {
+    Sketches(DocId, Vec<Sketch>),
+    Chunk(DocId, Pending),
+}
+
+#[derive(Debug, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(Debug, CheckBytes))]
+#[repr(C)]
+pub enum ReconcileResponse {
+    Sketches([u8; 32], Causal, Pending, Vec<Range>),
+    Chunk([u8; 32], Causal),
+}
+
+#[derive(Debug, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(Debug, CheckBytes))]
+#[repr(C)]
+pub struct PairedDoc {
+    doc: DocId,
+    schema: String,
 }
 
 #[derive(Debug, Archive, Deserialize, Serialize)]
@@ -90,74 +141,505 @@ pub struct Invite {
     pub schema: String,
 }
 
-#[derive(Clone, Default)]
-pub struct SyncCodec {
-    buffer: Vec<u8>,
-}
-
-#[async_trait]
-impl RequestResponseCodec for SyncCodec {
-    type Protocol = SyncProtocol;
-    type Request = Ref<SyncRequest>;
-    type Response = Ref<SyncResponse>;
-
-    async fn read_request<T>(&mut self, _: &SyncProtocol, io: &mut T) -> io::Result<Self::Request>
-    where
-        T: AsyncRead + Unpin + Send,
-    {
-        self.buffer.clear();
-        io.read_to_end(&mut self.buffer).await?;
-        Ref::checked(&self.buffer).map_err(|err| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("read_request: {} {:?}", err, &self.buffer),
-            )
-        })
+/// Shareable link to redeem a [`Capability`] of a document. The link is formatted as
+/// `tlfs://<capability>?schema=<schema>&peer=<peer>&addr=<addr>`.
+#[derive(Clone, Debug)]
+pub struct InviteLink {
+    /// Capability to redeem.
+    pub capability: Capability,
+    /// Schema of the document.
+    pub schema: String,
+    /// Peer that minted the capability.
+    pub peer: PeerId,
+    /// Addresses of the peer.
+    pub addrs: Vec<Multiaddr>,
+}
+
+impl std::fmt::Display for InviteLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "tlfs://{}?schema={}&peer={}",
+            self.capability, self.schema, self.peer
+        )?;
+        for addr in &self.addrs {
+            write!(f, "&addr={}", addr)?;
+        }
+        Ok(())
     }
+}
+
+impl std::str::FromStr for InviteLink {
+    type Err = anyhow::Error;
 
-    async fn read_response<T>(&mut self, _: &SyncProtocol, io: &mut T) -> io::Result<Self::Response>
-    where
-        T: AsyncRead + Unpin + Send,
-    {
-        self.buffer.clear();
-        io.read_to_end(&mut self.buffer).await?;
-        Ref::checked(&self.buffer).map_err(|err| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("read_response: {} {:?}", err, &self.buffer),
-            )
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s
+            .strip_prefix("tlfs://")
+            .ok_or_else(|| anyhow::anyhow!("invalid invite link"))?;
+        let (capability, query) = s.split_once('?').unwrap_or((s, ""));
+        let mut schema = None;
+        let mut peer = None;
+        let mut addrs = vec![];
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some(("schema", value)) => schema = Some(value.to_string()),
+                Some(("peer", value)) => peer = Some(value.parse()?),
+                Some(("addr", value)) => addrs.push(value.parse()?),
+                _ => bail!("invalid invite link parameter {}", param),
+            }
+        }
+        Ok(Self {
+            capability: capability.parse()?,
+            schema: schema.ok_or_else(|| anyhow::anyhow!("missing schema"))?,
+            peer: peer.ok_or_else(|| anyhow::anyhow!("missing peer"))?,
+            addrs,
         })
     }
+}
 
-    async fn write_request<T>(
-        &mut self,
-        _: &SyncProtocol,
-        io: &mut T,
-        req: Self::Request,
-    ) -> io::Result<()>
-    where
-        T: AsyncWrite + Unpin + Send,
-    {
-        io.write_all(req.as_bytes()).await?;
-        io.close().await?;
+/// Shareable link to pair a device with the keypair of a peer. The link is formatted as
+/// `tlfs-pair://<secret>?peer=<peer>&addr=<addr>`.
+#[derive(Clone, Debug)]
+pub struct PairingLink {
+    /// One-time secret proving the device is allowed to pair.
+    pub secret: Keypair,
+    /// Peer that created the link.
+    pub peer: PeerId,
+    /// Addresses of the peer.
+    pub addrs: Vec<Multiaddr>,
+}
+
+impl std::fmt::Display for PairingLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let secret: [u8; 32] = self.secret.into();
+        write!(
+            f,
+            "tlfs-pair://{}?peer={}",
+            base64::encode_config(&secret, base64::URL_SAFE),
+            self.peer
+        )?;
+        for addr in &self.addrs {
+            write!(f, "&addr={}", addr)?;
+        }
         Ok(())
     }
+}
 
-    async fn write_response<T>(
-        &mut self,
-        _: &SyncProtocol,
-        io: &mut T,
-        res: Self::Response,
-    ) -> io::Result<()>
-    where
-        T: AsyncWrite + Unpin + Send,
-    {
-        io.write_all(res.as_bytes()).await?;
-        io.close().await?;
-        Ok(())
+impl std::str::FromStr for PairingLink {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s
+            .strip_prefix("tlfs-pair://")
+            .ok_or_else(|| anyhow::anyhow!("invalid pairing link"))?;
+        let (secret, query) = s.split_once('?').unwrap_or((s, ""));
+        let mut peer = None;
+        let mut addrs = vec![];
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some(("peer", value)) => peer = Some(value.parse()?),
+                Some(("addr", value)) => addrs.push(value.parse()?),
+                _ => bail!("invalid pairing link parameter {}", param),
+            }
+        }
+        let secret = base64::decode_config(secret, base64::URL_SAFE)?;
+        let secret: [u8; 32] = secret
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid pairing secret"))?;
+        Ok(Self {
+            secret: Keypair::new(secret),
+            peer: peer.ok_or_else(|| anyhow::anyhow!("missing peer"))?,
+            addrs,
+        })
     }
 }
 
+macro_rules! codec {
+    ($codec:ident, $protocol:ident, $request:ident, $response:ident) => {
+        #[derive(Clone, Default)]
+        pub struct $codec {
+            buffer: Vec<u8>,
+        }
+
+        #[async_trait]
+        impl RequestResponseCodec for $codec {
+            type Protocol = $protocol;
+            type Request = Ref<$request>;
+            type Response = Ref<$response>;
+
+            async fn read_request<T>(
+                &mut self,
+                _: &$protocol,
+                io: &mut T,
+            ) -> io::Result<Self::Request>
+            where
+                T: AsyncRead + Unpin + Send,
+            {
+                self.buffer.clear();
+                io.read_to_end(&mut self.buffer).await?;
+                Ref::checked(&self.buffer).map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("read_request: {} {:?}", err, &self.buffer),
+                    )
+                })
+            }
+
+            async fn read_response<T>(
+                &mut self,
+                _: &$protocol,
+                io: &mut T,
+            ) -> io::Result<Self::Response>
+            where
+                T: AsyncRead + Unpin + Send,
+            {
+                self.buffer.clear();
+                io.read_to_end(&mut self.buffer).await?;
+                Ref::checked(&self.buffer).map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("read_response: {} {:?}", err, &self.buffer),
+                    )
+                })
+            }
+
+            async fn write_request<T>(
+                &mut self,
+                _: &$protocol,
+                io: &mut T,
+                req: Self::Request,
+            ) -> io::Result<()>
+            where
+                T: AsyncWrite + Unpin + Send,
+            {
+                io.write_all(req.as_bytes()).await?;
+                io.close().await?;
+                Ok(())
+            }
+
+            async fn write_response<T>(
+                &mut self,
+                _: &$protocol,
+                io: &mut T,
+                res: Self::Response,
+            ) -> io::Result<()>
+            where
+                T: AsyncWrite + Unpin + Send,
+            {
+                io.write_all(res.as_bytes()).await?;
+                io.close().await?;
+                Ok(())
+            }
+        }
+    };
+}
+
+codec!(SyncCodec, SyncProtocol, SyncRequest, SyncResponse);
+codec!(
+    ReconcileCodec,
+    ReconcileProtocol,
+    ReconcileRequest,
+    ReconcileResponse
+);
+
+/// Configuration of the periodic anti-entropy sync. Broadcasts can be missed, so each
+/// document is periodically synced with some of the connected peers.
+#[derive(Clone, Debug)]
+pub struct AntiEntropyConfig {
+    /// Interval between two rounds of a document.
+    pub interval: Duration,
+    /// The interval is doubled after every round that didn't repair any paths up to the
+    /// maximum interval.
+    pub max_interval: Duration,
+    /// Fraction of the interval that is randomly added to it, so that peers don't sync in
+    /// lockstep.
+    pub jitter: f64,
+    /// Number of connected peers a document is synced with in every round.
+    pub peers: usize,
+}
+
+impl Default for AntiEntropyConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            max_interval: Duration::from_secs(600),
+            jitter: 0.2,
+            peers: 2,
+        }
+    }
+}
+
+/// Counters of the anti-entropy sync of a document.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AntiEntropyStats {
+    /// Number of rounds.
+    pub rounds: u64,
+    /// Number of sync requests sent.
+    pub requests: u64,
+    /// Number of paths repaired in total.
+    pub repaired: u64,
+    /// Number of paths repaired by the last completed round.
+    pub last_repaired: u64,
+}
+
+/// Sync status of a document with a peer.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PeerSyncStatus {
+    /// Time of the last successful sync request.
+    pub last_sync: Option<Timestamp>,
+    /// Whether the causal contexts were equal when they were last compared. They are
+    /// compared when the peer starts a reconciliation of the document. Local changes and
+    /// changes received from the peer mark the document as out of sync.
+    pub in_sync: bool,
+    /// Number of bytes sent to the peer.
+    pub bytes_sent: u64,
+    /// Number of bytes received from the peer.
+    pub bytes_received: u64,
+    /// Number of sync requests waiting for a response.
+    pub outstanding: u64,
+    /// Number of failed sync requests.
+    pub errors: u64,
+    /// Error of the last failed sync request.
+    pub last_error: Option<String>,
+}
+
+/// Sync status of a document.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SyncStatus {
+    /// Number of missing changes that are still to be received.
+    pub pending: u64,
+    /// Sync status of each peer the document was synced with.
+    pub peers: BTreeMap<PeerId, PeerSyncStatus>,
+}
+
+struct Schedule {
+    interval: Duration,
+    next: Instant,
+    repaired: u64,
+    stats: AntiEntropyStats,
+}
+
+struct AntiEntropy {
+    config: AntiEntropyConfig,
+    docs: FnvHashMap<DocId, Schedule>,
+    /// Reconcile requests sent by a round, including the follow up requests of their
+    /// responses.
+    requests: FnvHashSet<RequestId>,
+    /// Sync requests sent by a round to peers that don't support reconciliation.
+    full_requests: FnvHashSet<RequestId>,
+    timer: Delay,
+}
+
+impl AntiEntropy {
+    fn new(config: AntiEntropyConfig) -> Self {
+        Self {
+            timer: Delay::new(config.interval),
+            config,
+            docs: Default::default(),
+            requests: Default::default(),
+            full_requests: Default::default(),
+        }
+    }
+
+    fn configure(&mut self, config: AntiEntropyConfig) {
+        let now = Instant::now();
+        for schedule in self.docs.values_mut() {
+            schedule.interval = config.interval;
+            schedule.next = now + jitter(&config, config.interval);
+        }
+        self.config = config;
+        self.reset_timer(now);
+    }
+
+    fn add(&mut self, doc: DocId) {
+        let config = &self.config;
+        self.docs.entry(doc).or_insert_with(|| Schedule {
+            interval: config.interval,
+            next: Instant::now() + jitter(config, config.interval),
+            repaired: 0,
+            stats: Default::default(),
+        });
+    }
+
+    fn remove(&mut self, doc: &DocId) {
+        self.docs.remove(doc);
+    }
+
+    /// Marks a reconcile request as part of a round.
+    fn track(&mut self, id: RequestId) {
+        self.requests.insert(id);
+    }
+
+    /// Returns if a reconcile request that completed was part of a round.
+    fn untrack(&mut self, id: &RequestId) -> bool {
+        self.requests.remove(id)
+    }
+
+    /// Marks a sync request as part of a round.
+    fn track_full(&mut self, id: RequestId) {
+        self.full_requests.insert(id);
+    }
+
+    /// Returns if a sync request that completed was part of a round.
+    fn untrack_full(&mut self, id: &RequestId) -> bool {
+        self.full_requests.remove(id)
+    }
+
+    /// Records the paths received in response to a request of a round.
+    fn repaired(&mut self, doc: &DocId, n: u64) {
+        if let Some(schedule) = self.docs.get_mut(doc) {
+            schedule.repaired += n;
+            schedule.stats.repaired += n;
+        }
+    }
+
+    fn stats(&self, doc: &DocId) -> Option<AntiEntropyStats> {
+        self.docs.get(doc).map(|schedule| schedule.stats)
+    }
+
+    /// Starts a round for every document that is due. The interval of a document is reset
+    /// when the previous round repaired paths and doubled otherwise.
+    fn due(&mut self, now: Instant) -> Vec<DocId> {
+        let config = &self.config;
+        let mut due = vec![];
+        for (doc, schedule) in &mut self.docs {
+            if schedule.next > now {
+                continue;
+            }
+            if schedule.stats.rounds > 0 {
+                tracing::debug!(
+                    "anti-entropy round of {} repaired {} paths",
+                    doc,
+                    schedule.repaired
+                );
+                schedule.interval = if schedule.repaired > 0 {
+                    config.interval
+                } else {
+                    (schedule.interval * 2).min(config.max_interval)
+                };
+                schedule.stats.last_repaired = schedule.repaired;
+            }
+            schedule.repaired = 0;
+            schedule.stats.rounds += 1;
+            schedule.next = now + jitter(config, schedule.interval);
+            due.push(*doc);
+        }
+        due
+    }
+
+    fn reset_timer(&mut self, now: Instant) {
+        let next = self
+            .docs
+            .values()
+            .map(|schedule| schedule.next)
+            .min()
+            .unwrap_or_else(|| now + self.config.interval);
+        self.timer.reset(next.saturating_duration_since(now));
+    }
+}
+
+/// Adds a random fraction of the interval configured by [`AntiEntropyConfig::jitter`].
+fn jitter(config: &AntiEntropyConfig, interval: Duration) -> Duration {
+    let mut bytes = [0; 4];
+    getrandom::getrandom(&mut bytes).ok();
+    let r = u32::from_le_bytes(bytes) as f64 / u32::MAX as f64;
+    interval + interval.mul_f64(config.jitter.clamp(0.0, 1.0) * r)
+}
+
+/// Number of times the lenses of a schema are requested before the changes waiting for them
+/// are dropped.
+const MAX_LENSES_ATTEMPTS: u32 = 8;
+/// Backoff after the first failed lenses request. Doubles after every failure.
+const LENSES_BACKOFF: Duration = Duration::from_secs(1);
+/// Maximum backoff between two lenses requests.
+const MAX_LENSES_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Reason a remote change was dropped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DropReason {
+    /// The buffer of changes waiting for lenses was full.
+    BufferFull,
+    /// The lenses of the schema could not be obtained from any peer.
+    MissingLenses,
+}
+
+/// Remote change that was dropped because its schema could not be obtained.
+#[derive(Clone, Debug)]
+pub struct DroppedCausal {
+    /// Document of the change.
+    pub doc: DocId,
+    /// Peer that sent the change.
+    pub peer: PeerId,
+    /// Hash of the schema of the change.
+    pub schema: Hash,
+    /// Reason the change was dropped.
+    pub reason: DropReason,
+}
+
+/// Counters of the remote changes waiting for lenses.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BufferStats {
+    /// Number of changes that were buffered.
+    pub buffered: u64,
+    /// Number of lenses requests sent.
+    pub requests: u64,
+    /// Number of lenses requests that failed.
+    pub failures: u64,
+    /// Number of changes that were dropped.
+    pub dropped: u64,
+}
+
+/// Lenses of a schema that are requested from the peers that sent changes with the
+/// schema.
+struct MissingLenses {
+    peers: Vec<PeerId>,
+    attempts: u32,
+    /// Time of the next request, `None` while a request is in flight.
+    next: Option<Instant>,
+}
+
+/// Interval between two registrations and discoveries at a rendezvous server.
+const RENDEZVOUS_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Rendezvous server the peers of our documents are registered at.
+struct RendezvousNode {
+    addr: Multiaddr,
+    connected: bool,
+}
+
+/// Returns the rendezvous namespace of a document.
+fn namespace(doc: &DocId) -> Namespace {
+    Namespace::new(format!("/tlfs/{}", doc)).unwrap()
+}
+
+/// Returns the peer id an address ends with.
+fn p2p_peer_id(addr: &Multiaddr) -> Result<libp2p::PeerId> {
+    match addr.iter().last() {
+        Some(Protocol::P2p(hash)) => {
+            libp2p::PeerId::from_multihash(hash).map_err(|_| anyhow!("invalid peer id in {}", addr))
+        }
+        _ => Err(anyhow!("{} doesn't end with /p2p/<peer id>", addr)),
+    }
+}
+
+/// Returns the number of paths in a [`Causal`].
+fn paths(causal: &Causal) -> u64 {
+    (causal.store().iter().count() + causal.expired().iter().count()) as u64
+}
+
+/// Picks up to `n` random elements.
+fn sample<T>(mut items: Vec<T>, n: usize) -> Vec<T> {
+    let mut sample = vec![];
+    while sample.len() < n && !items.is_empty() {
+        let mut bytes = [0; 4];
+        getrandom::getrandom(&mut bytes).ok();
+        let i = u32::from_le_bytes(bytes) as usize % items.len();
+        sample.push(items.swap_remove(i));
+    }
+    sample
+}
+
 pub(crate) fn notify(subs: &mut Vec<mpsc::Sender<()>>) {
     subs.retain(|tx| match tx.clone().try_send(()) {
         Ok(()) => true,
@@ -168,19 +650,58 @@ pub(crate) fn notify(subs: &mut Vec<mpsc::Sender<()>>) {
 
 type RequestResponseEvent =
     request_response::RequestResponseEvent<Ref<SyncRequest>, Ref<SyncResponse>>;
+type ReconcileEvent =
+    request_response::RequestResponseEvent<Ref<ReconcileRequest>, Ref<ReconcileResponse>>;
 
 #[derive(NetworkBehaviour)]
 #[behaviour(event_process = true, poll_method = "poll_dial")]
 pub struct Behaviour {
     req: RequestResponse<SyncCodec>,
+    reconcile: RequestResponse<ReconcileCodec>,
     broadcast: Broadcast,
     ping: ping::Behaviour,
     #[cfg(not(target_family = "wasm"))]
-    mdns: mdns::Mdns,
+    mdns: Toggle<mdns::Mdns>,
+    relay: Toggle<Relay>,
+    rendezvous: Toggle<rendezvous::client::Behaviour>,
+    #[behaviour(ignore)]
+    rendezvous_nodes: FnvHashMap<libp2p::PeerId, RendezvousNode>,
+    #[behaviour(ignore)]
+    rendezvous_cookies: FnvHashMap<(libp2p::PeerId, Namespace), Cookie>,
+    #[behaviour(ignore)]
+    rendezvous_timer: Delay,
+    #[behaviour(ignore)]
+    local_peer: libp2p::PeerId,
     #[behaviour(ignore)]
     unjoin_req: FnvHashMap<RequestId, DocId>,
     #[behaviour(ignore)]
-    buffer: Vec<(Hash, DocId, PeerId, Causal)>,
+    reconcile_req: FnvHashMap<RequestId, DocId>,
+    #[behaviour(ignore)]
+    chunk_req: FnvHashMap<RequestId, (DocId, Pending)>,
+    #[behaviour(ignore)]
+    sync_status: FnvHashMap<DocId, BTreeMap<PeerId, PeerSyncStatus>>,
+    #[behaviour(ignore)]
+    sub_sync_status: FnvHashMap<DocId, Vec<mpsc::Sender<()>>>,
+    #[behaviour(ignore)]
+    pending: FnvHashMap<DocId, Pending>,
+    #[behaviour(ignore)]
+    redeem_req: FnvHashMap<RequestId, DocId>,
+    #[behaviour(ignore)]
+    pair_req: FnvHashMap<RequestId, PeerId>,
+    #[behaviour(ignore)]
+    pairings: FnvHashMap<PeerId, PeerId>,
+    #[behaviour(ignore)]
+    lenses_req: FnvHashMap<RequestId, Hash>,
+    #[behaviour(ignore)]
+    missing_lenses: FnvHashMap<Hash, MissingLenses>,
+    #[behaviour(ignore)]
+    lenses_timer: Delay,
+    #[behaviour(ignore)]
+    buffer_stats: BufferStats,
+    #[behaviour(ignore)]
+    dropped: Vec<DroppedCausal>,
+    #[behaviour(ignore)]
+    sub_dropped: Vec<mpsc::Sender<()>>,
     #[behaviour(ignore)]
     backend: Backend,
     #[behaviour(ignore)]
@@ -191,10 +712,37 @@ pub struct Behaviour {
     invites: Vec<Invite>,
     #[behaviour(ignore)]
     dial: VecDeque<PeerId>,
+    #[behaviour(ignore)]
+    dial_addr: VecDeque<(libp2p::PeerId, Multiaddr)>,
+    #[behaviour(ignore)]
+    anti_entropy: AntiEntropy,
 }
 
 impl Behaviour {
-    pub async fn new(backend: Backend) -> Result<Self> {
+    pub async fn new(
+        backend: Backend,
+        peer: &PeerId,
+        config: &NetworkConfig,
+        relay: Option<Relay>,
+    ) -> Result<Self> {
+        let mut rendezvous_nodes = FnvHashMap::default();
+        for addr in &config.rendezvous_servers {
+            let node = RendezvousNode {
+                addr: addr.clone(),
+                connected: false,
+            };
+            rendezvous_nodes.insert(p2p_peer_id(addr)?, node);
+        }
+        let rendezvous = if rendezvous_nodes.is_empty() {
+            None
+        } else {
+            let keypair = backend.frontend().keypair(peer)?.to_libp2p();
+            Some(rendezvous::client::Behaviour::new(keypair))
+        };
+        let dial_addr = rendezvous_nodes
+            .iter()
+            .map(|(node, rendezvous)| (*node, rendezvous.addr.clone()))
+            .collect();
         let mut me = Self {
             backend,
             req: RequestResponse::new(
@@ -202,33 +750,118 @@ impl Behaviour {
                 vec![(SyncProtocol, ProtocolSupport::Full)],
                 RequestResponseConfig::default(),
             ),
+            reconcile: RequestResponse::new(
+                ReconcileCodec::default(),
+                vec![(ReconcileProtocol, ProtocolSupport::Full)],
+                RequestResponseConfig::default(),
+            ),
             #[cfg(not(target_family = "wasm"))]
-            mdns: mdns::Mdns::new(mdns::MdnsConfig {
-                query_interval: Duration::from_secs(10),
-                ..Default::default()
-            })
-            .await?,
+            mdns: if config.mdns {
+                Some(
+                    mdns::Mdns::new(mdns::MdnsConfig {
+                        query_interval: Duration::from_secs(10),
+                        ..Default::default()
+                    })
+                    .await?,
+                )
+            } else {
+                None
+            }
+            .into(),
+            relay: relay.into(),
+            rendezvous: rendezvous.into(),
+            rendezvous_nodes,
+            rendezvous_cookies: Default::default(),
+            rendezvous_timer: Delay::new(RENDEZVOUS_INTERVAL),
+            local_peer: peer.to_libp2p().to_peer_id(),
             ping: ping::Behaviour::new(
                 ping::Config::new()
                     .with_keep_alive(true)
-                    .with_interval(Duration::from_secs(3))
-                    .with_timeout(Duration::from_secs(1)),
+                    .with_interval(config.ping_interval)
+                    .with_timeout(config.ping_timeout),
             ),
             unjoin_req: Default::default(),
-            buffer: Default::default(),
+            reconcile_req: Default::default(),
+            chunk_req: Default::default(),
+            sync_status: Default::default(),
+            sub_sync_status: Default::default(),
+            pending: Default::default(),
+            redeem_req: Default::default(),
+            pair_req: Default::default(),
+            pairings: Default::default(),
+            lenses_req: Default::default(),
+            missing_lenses: Default::default(),
+            lenses_timer: Delay::new(Duration::from_secs(0)),
+            buffer_stats: Default::default(),
+            dropped: Default::default(),
+            sub_dropped: Default::default(),
             broadcast: Broadcast::new(BroadcastConfig::default()),
             sub_local_peers: Default::default(),
             sub_invites: Default::default(),
             invites: Default::default(),
             dial: Default::default(),
+            dial_addr,
+            anti_entropy: AntiEntropy::new(config.anti_entropy.clone()),
         };
         for res in me.backend.frontend().docs() {
             let doc = res?;
             me.subscribe(&doc);
         }
+        // changes buffered before a restart are retried once the timer fires
+        let now = Instant::now();
+        for buffered in me.backend.buffered()? {
+            let missing = me
+                .missing_lenses
+                .entry(buffered.schema())
+                .or_insert_with(|| MissingLenses {
+                    peers: vec![],
+                    attempts: 0,
+                    next: Some(now),
+                });
+            if !missing.peers.contains(&buffered.peer) {
+                missing.peers.push(buffered.peer);
+            }
+        }
         Ok(me)
     }
 
+    pub fn install_package(&mut self, package: &[u8]) -> Result<()> {
+        self.backend.install_package(package)?;
+        self.join_buffered()
+    }
+
+    pub fn answer_sync(&mut self, peer: &PeerId, request: &[u8]) -> Result<Vec<u8>> {
+        self.backend.answer_sync(peer, request)
+    }
+
+    pub fn apply_sync(
+        &mut self,
+        peer: &PeerId,
+        doc: &DocId,
+        response: &[u8],
+    ) -> Result<Option<Hash>> {
+        let missing = self.backend.apply_sync(peer, doc, response)?;
+        self.join_buffered()?;
+        Ok(missing)
+    }
+
+    pub fn apply_delta(
+        &mut self,
+        peer: &PeerId,
+        doc: &DocId,
+        delta: &[u8],
+    ) -> Result<Option<Hash>> {
+        self.backend.apply_delta(peer, doc, delta)
+    }
+
+    /// Joins the buffered changes whose lenses became available.
+    fn join_buffered(&mut self) -> Result<()> {
+        let registry = self.backend.registry();
+        self.missing_lenses
+            .retain(|schema, _| !registry.contains(schema));
+        self.backend.join_buffered()
+    }
+
     pub fn poll_backend(&mut self, cx: &mut Context) -> Poll<Result<()>> {
         Pin::new(&mut self.backend).poll(cx)
     }
@@ -246,7 +879,9 @@ impl Behaviour {
         #[cfg(not(target_family = "wasm"))]
         return self
             .mdns
-            .discovered_nodes()
+            .as_ref()
+            .into_iter()
+            .flat_map(|mdns| mdns.discovered_nodes())
             .filter_map(|peer| libp2p_peer_id(peer).ok())
             .collect();
         #[cfg(target_family = "wasm")]
@@ -261,6 +896,18 @@ impl Behaviour {
         self.sub_invites.push(ch);
     }
 
+    pub fn subscribe_dropped(&mut self, ch: mpsc::Sender<()>) {
+        self.sub_dropped.push(ch);
+    }
+
+    pub fn clear_dropped(&mut self) -> Vec<DroppedCausal> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.buffer_stats
+    }
+
     pub fn request_lenses(&mut self, peer_id: &PeerId, hash: Hash) -> RequestId {
         tracing::debug!("request_lenses {} {}", peer_id, hash);
         let peer_id = peer_id.to_libp2p().to_peer_id();
@@ -268,19 +915,73 @@ impl Behaviour {
         self.req.send_request(&peer_id, Ref::archive(&req))
     }
 
+    /// Requests the changes of a document from a peer using set reconciliation.
     pub fn request_unjoin(&mut self, peer_id: &PeerId, doc: DocId) -> Result<RequestId> {
-        tracing::debug!("request_unjoin {} {}", peer_id, doc);
-        let peer_id = peer_id.to_libp2p().to_peer_id();
+        let id = self.request_reconcile(peer_id, doc, &Range::roots())?;
+        // resumes a transfer that was interrupted
+        self.request_chunk(peer_id, doc)?;
+        Ok(id)
+    }
+
+    /// Requests the changes of a document from a peer sending the full causal context.
+    pub fn request_full_unjoin(&mut self, peer_id: &PeerId, doc: DocId) -> Result<RequestId> {
+        tracing::debug!("request_full_unjoin {} {}", peer_id, doc);
         let ctx = self.backend.frontend().ctx(&doc)?;
-        let req = SyncRequest::Unjoin(doc, ctx);
-        let id = self.req.send_request(&peer_id, Ref::archive(&req));
+        let req = Ref::archive(&SyncRequest::Unjoin(doc, ctx));
+        self.request_sent(&doc, peer_id, req.as_bytes().len());
+        let peer_id = peer_id.to_libp2p().to_peer_id();
+        let id = self.req.send_request(&peer_id, req);
         self.unjoin_req.insert(id, doc);
         Ok(id)
     }
 
-    pub fn subscribe(&mut self, doc: &DocId) {
+    fn request_reconcile(
+        &mut self,
+        peer_id: &PeerId,
+        doc: DocId,
+        ranges: &[Range],
+    ) -> Result<RequestId> {
+        tracing::debug!("request_reconcile {} {} {}", peer_id, doc, ranges.len());
+        let sketches = self.backend.frontend().sketch(&doc, ranges)?;
+        let req = Ref::archive(&ReconcileRequest::Sketches(doc, sketches));
+        self.request_sent(&doc, peer_id, req.as_bytes().len());
+        let peer_id = peer_id.to_libp2p().to_peer_id();
+        let id = self.reconcile.send_request(&peer_id, req);
+        self.reconcile_req.insert(id, doc);
+        Ok(id)
+    }
+
+    /// Requests the next chunk of the missing changes of a document from a peer. Only
+    /// one chunk per document is in flight.
+    fn request_chunk(&mut self, peer_id: &PeerId, doc: DocId) -> Result<Option<RequestId>> {
+        if self.chunk_req.values().any(|(doc2, _)| *doc2 == doc) {
+            return Ok(None);
+        }
+        let pending = if let Some(pending) = self.pending.get_mut(&doc) {
+            pending
+        } else {
+            return Ok(None);
+        };
+        pending.retain_missing(&self.backend.frontend().ctx(&doc)?);
+        let chunk = pending.next_chunk();
+        if pending.is_empty() {
+            self.pending.remove(&doc);
+        }
+        if chunk.is_empty() {
+            return Ok(None);
+        }
+        tracing::debug!("request_chunk {} {} {}", peer_id, doc, chunk.len());
+        let req = Ref::archive(&ReconcileRequest::Chunk(doc, chunk.clone()));
+        self.request_sent(&doc, peer_id, req.as_bytes().len());
+        let peer_id = peer_id.to_libp2p().to_peer_id();
+        let id = self.reconcile.send_request(&peer_id, req);
+        self.chunk_req.insert(id, (doc, chunk));
+        Ok(Some(id))
+    }
+
+    /// Returns the connected peers subscribed to a document.
+    fn doc_peers(&self, doc: &DocId) -> Vec<PeerId> {
         let topic = Topic::new(doc.as_ref());
-        self.broadcast.subscribe(topic);
         let mut peers = vec![];
         if let Some(iter) = self.broadcast.peers(&topic) {
             for peer in iter {
@@ -289,9 +990,192 @@ impl Behaviour {
                 }
             }
         }
-        for peer in peers {
+        peers
+    }
+
+    pub fn subscribe(&mut self, doc: &DocId) {
+        self.broadcast.subscribe(Topic::new(doc.as_ref()));
+        self.anti_entropy.add(*doc);
+        for peer in self.doc_peers(doc) {
             unwrap!(self.request_unjoin(&peer, *doc));
         }
+        let nodes: Vec<_> = self
+            .rendezvous_nodes
+            .iter()
+            .filter(|(_, node)| node.connected)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for node in nodes {
+            self.rendezvous_register(&node, doc);
+        }
+    }
+
+    /// Registers at a rendezvous server once connected to it.
+    pub fn connection_established(&mut self, peer: &libp2p::PeerId) {
+        if let Some(node) = self.rendezvous_nodes.get_mut(peer) {
+            node.connected = true;
+            unwrap!(self.rendezvous_refresh(peer));
+        }
+    }
+
+    pub fn connection_closed(&mut self, peer: &libp2p::PeerId) {
+        if let Some(node) = self.rendezvous_nodes.get_mut(peer) {
+            node.connected = false;
+        }
+    }
+
+    /// Registers the namespace of a document at a rendezvous server and discovers the
+    /// peers registered under it.
+    fn rendezvous_register(&mut self, node: &libp2p::PeerId, doc: &DocId) {
+        if let Some(rendezvous) = self.rendezvous.as_mut() {
+            let namespace = namespace(doc);
+            let cookie = self.rendezvous_cookies.get(&(*node, namespace.clone()));
+            rendezvous.register(namespace.clone(), *node, None);
+            rendezvous.discover(Some(namespace), cookie.cloned(), None, *node);
+        }
+    }
+
+    /// Registers all documents at a rendezvous server.
+    fn rendezvous_refresh(&mut self, node: &libp2p::PeerId) -> Result<()> {
+        for res in self.backend.frontend().docs() {
+            let doc = res?;
+            self.rendezvous_register(node, &doc);
+        }
+        Ok(())
+    }
+
+    fn poll_rendezvous(&mut self, cx: &mut Context) {
+        while Pin::new(&mut self.rendezvous_timer).poll(cx).is_ready() {
+            let nodes: Vec<_> = self
+                .rendezvous_nodes
+                .iter()
+                .map(|(peer, node)| (*peer, node.addr.clone(), node.connected))
+                .collect();
+            for (peer, addr, connected) in nodes {
+                if connected {
+                    if let Err(err) = self.rendezvous_refresh(&peer) {
+                        tracing::error!("{}", err);
+                    }
+                } else {
+                    self.dial_addr.push_back((peer, addr));
+                }
+            }
+            cx.waker().wake_by_ref();
+            self.rendezvous_timer.reset(RENDEZVOUS_INTERVAL);
+        }
+    }
+
+    pub fn configure_anti_entropy(&mut self, config: AntiEntropyConfig) {
+        self.anti_entropy.configure(config);
+    }
+
+    pub fn anti_entropy_stats(&self, doc: &DocId) -> Option<AntiEntropyStats> {
+        self.anti_entropy.stats(doc)
+    }
+
+    /// Returns the sync status of a document.
+    pub fn sync_status(&self, doc: &DocId) -> SyncStatus {
+        SyncStatus {
+            pending: self
+                .pending
+                .get(doc)
+                .map(|pending| pending.len() as u64)
+                .unwrap_or_default()
+                + self
+                    .chunk_req
+                    .values()
+                    .filter(|(doc2, _)| doc2 == doc)
+                    .map(|(_, chunk)| chunk.len() as u64)
+                    .sum::<u64>(),
+            peers: self.sync_status.get(doc).cloned().unwrap_or_default(),
+        }
+    }
+
+    pub fn subscribe_sync_status(&mut self, doc: &DocId, ch: mpsc::Sender<()>) {
+        self.sub_sync_status.entry(*doc).or_default().push(ch);
+    }
+
+    /// Updates the sync status of a document with a peer and notifies the subscribers.
+    fn update_status(&mut self, doc: &DocId, peer: &PeerId, f: impl FnOnce(&mut PeerSyncStatus)) {
+        let status = self
+            .sync_status
+            .entry(*doc)
+            .or_default()
+            .entry(*peer)
+            .or_default();
+        f(status);
+        if let Some(subs) = self.sub_sync_status.get_mut(doc) {
+            notify(subs);
+        }
+    }
+
+    fn request_sent(&mut self, doc: &DocId, peer: &PeerId, bytes: usize) {
+        self.update_status(doc, peer, |status| {
+            status.outstanding += 1;
+            status.bytes_sent += bytes as u64;
+        });
+    }
+
+    fn response_received(&mut self, doc: &DocId, peer: &PeerId, bytes: usize, repaired: u64) {
+        self.update_status(doc, peer, |status| {
+            status.outstanding = status.outstanding.saturating_sub(1);
+            status.bytes_received += bytes as u64;
+            status.last_sync = Some(Timestamp::now());
+            if repaired > 0 {
+                status.in_sync = false;
+            }
+        });
+    }
+
+    fn request_failed(&mut self, doc: &DocId, peer: &PeerId, error: String) {
+        self.update_status(doc, peer, |status| {
+            status.outstanding = status.outstanding.saturating_sub(1);
+            status.errors += 1;
+            status.last_error = Some(error);
+        });
+    }
+
+    /// Syncs the documents that are due with some of their connected peers.
+    fn poll_anti_entropy(&mut self, cx: &mut Context) {
+        while Pin::new(&mut self.anti_entropy.timer).poll(cx).is_ready() {
+            let now = Instant::now();
+            for doc in self.anti_entropy.due(now) {
+                if !self.backend.contains(&doc).unwrap_or_default() {
+                    self.anti_entropy.remove(&doc);
+                    self.sync_status.remove(&doc);
+                    self.pending.remove(&doc);
+                    continue;
+                }
+                // the tombstones acknowledged by reconciliations are compacted once a round
+                match self.backend.compact(&doc) {
+                    Ok(n) if n > 0 => tracing::debug!("compacted {} tombstones of {}", n, doc),
+                    Ok(_) => {}
+                    Err(err) => tracing::error!("{}", err),
+                }
+                let peers = sample(self.doc_peers(&doc), self.anti_entropy.config.peers);
+                for peer in peers {
+                    let id = match self.request_reconcile(&peer, doc, &Range::roots()) {
+                        Ok(id) => id,
+                        Err(err) => {
+                            tracing::error!("{}", err);
+                            continue;
+                        }
+                    };
+                    self.anti_entropy.track(id);
+                    match self.request_chunk(&peer, doc) {
+                        Ok(Some(id)) => self.anti_entropy.track(id),
+                        Ok(None) => {}
+                        Err(err) => tracing::error!("{}", err),
+                    }
+                    if let Some(schedule) = self.anti_entropy.docs.get_mut(&doc) {
+                        schedule.stats.requests += 1;
+                    }
+                }
+                // the requests are queued in the request response behaviour
+                cx.waker().wake_by_ref();
+            }
+            self.anti_entropy.reset_timer(now);
+        }
     }
 
     pub fn invite(&mut self, peer_id: &PeerId, doc: DocId, schema: String) -> RequestId {
@@ -301,6 +1185,36 @@ impl Behaviour {
         self.req.send_request(&peer_id, Ref::archive(&req))
     }
 
+    /// Sends a redemption to the peer that minted the capability. The document is
+    /// synced once the redemption is accepted.
+    pub fn redeem(&mut self, peer_id: &PeerId, doc: DocId, causal: Causal) -> Result<RequestId> {
+        tracing::debug!("redeem {} {}", peer_id, doc);
+        let peer_id = peer_id.to_libp2p().to_peer_id();
+        let hash = self.backend.frontend().schema(&doc)?.as_ref().hash();
+        let req = SyncRequest::Redeem(doc, hash.into(), causal);
+        let id = self.req.send_request(&peer_id, Ref::archive(&req));
+        self.redeem_req.insert(id, doc);
+        Ok(id)
+    }
+
+    /// Accepts a single pairing request proving possession of the secret of `token`. The
+    /// requesting peer is linked as a device of `root`.
+    pub fn add_pairing(&mut self, token: PeerId, root: PeerId) {
+        self.pairings.insert(token, root);
+    }
+
+    /// Sends a pairing request to the peer that created the pairing link. The documents of
+    /// the peer are added and synced once the pairing is accepted.
+    pub fn pair(&mut self, peer_id: &PeerId, device: PeerId, secret: Keypair) -> RequestId {
+        tracing::debug!("pair {} {}", peer_id, device);
+        let peer_id = peer_id.to_libp2p().to_peer_id();
+        let proof = secret.sign(device.as_ref()).to_bytes();
+        let req = SyncRequest::Pair(secret.peer_id().into(), proof);
+        let id = self.req.send_request(&peer_id, Ref::archive(&req));
+        self.pair_req.insert(id, device);
+        id
+    }
+
     pub fn clear_invites(&mut self) -> Vec<Invite> {
         std::mem::take(&mut self.invites)
     }
@@ -315,6 +1229,15 @@ impl Behaviour {
         let delta = Ref::archive(&delta);
         tracing::debug!("sending broadcast");
         self.broadcast.broadcast(&topic, delta.as_bytes().into());
+        // peers are out of sync until they reconciled the local change
+        let peers: Vec<PeerId> = self
+            .sync_status
+            .get(doc)
+            .map(|peers| peers.keys().copied().collect())
+            .unwrap_or_default();
+        for peer in peers {
+            self.update_status(doc, &peer, |status| status.in_sync = false);
+        }
         Ok(())
     }
 
@@ -328,15 +1251,125 @@ impl Behaviour {
         if self.backend.registry().contains(&schema) {
             self.backend.join(&peer, &doc, &schema, causal)?;
         } else {
-            self.buffer.push((schema, doc, peer, causal));
-            self.request_lenses(&peer, schema);
+            let dropped = self.backend.buffer(&peer, &doc, &schema, causal)?;
+            self.buffer_stats.buffered += 1;
+            self.drop_causals(dropped, DropReason::BufferFull);
+            let missing = self
+                .missing_lenses
+                .entry(schema)
+                .or_insert_with(|| MissingLenses {
+                    peers: vec![],
+                    attempts: 0,
+                    next: Some(Instant::now()),
+                });
+            if !missing.peers.contains(&peer) {
+                missing.peers.push(peer);
+            }
+            if missing.next.is_some() && missing.attempts == 0 {
+                self.retry_lenses(schema);
+            }
         }
         Ok(())
     }
 
+    /// Requests the lenses of a schema from the next peer that sent changes with the
+    /// schema.
+    fn retry_lenses(&mut self, schema: Hash) {
+        let missing = if let Some(missing) = self.missing_lenses.get_mut(&schema) {
+            missing
+        } else {
+            return;
+        };
+        if missing.peers.is_empty() {
+            return;
+        }
+        let peer = missing.peers[missing.attempts as usize % missing.peers.len()];
+        missing.attempts += 1;
+        missing.next = None;
+        self.buffer_stats.requests += 1;
+        let id = self.request_lenses(&peer, schema);
+        self.lenses_req.insert(id, schema);
+    }
+
+    /// Backs off after a failed lenses request. Drops the changes waiting for the lenses
+    /// after too many attempts.
+    fn lenses_failed(&mut self, schema: Hash) -> Result<()> {
+        self.buffer_stats.failures += 1;
+        let attempts = match self.missing_lenses.get_mut(&schema) {
+            Some(missing) if missing.attempts < MAX_LENSES_ATTEMPTS => {
+                let backoff = LENSES_BACKOFF * 2u32.pow(missing.attempts - 1);
+                let backoff = backoff.min(MAX_LENSES_BACKOFF);
+                missing.next = Some(Instant::now() + backoff);
+                missing.attempts
+            }
+            Some(_) => {
+                self.missing_lenses.remove(&schema);
+                let dropped = self.backend.drop_buffered(&schema)?;
+                self.drop_causals(dropped, DropReason::MissingLenses);
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+        tracing::debug!("lenses request {} for {} failed", attempts, schema);
+        self.reset_lenses_timer();
+        Ok(())
+    }
+
+    fn drop_causals(&mut self, dropped: Vec<Buffered>, reason: DropReason) {
+        if dropped.is_empty() {
+            return;
+        }
+        for buffered in dropped {
+            tracing::warn!(
+                "dropped change of {} from {} with schema {}: {:?}",
+                buffered.doc,
+                buffered.peer,
+                buffered.schema(),
+                reason
+            );
+            self.buffer_stats.dropped += 1;
+            self.dropped.push(DroppedCausal {
+                doc: buffered.doc,
+                peer: buffered.peer,
+                schema: buffered.schema(),
+                reason,
+            });
+        }
+        notify(&mut self.sub_dropped);
+    }
+
+    fn reset_lenses_timer(&mut self) {
+        let now = Instant::now();
+        let next = self
+            .missing_lenses
+            .values()
+            .filter_map(|missing| missing.next)
+            .min()
+            .unwrap_or_else(|| now + MAX_LENSES_BACKOFF);
+        self.lenses_timer.reset(next.saturating_duration_since(now));
+    }
+
+    /// Retries the lenses requests that are due.
+    fn poll_lenses(&mut self, cx: &mut Context) {
+        while Pin::new(&mut self.lenses_timer).poll(cx).is_ready() {
+            let now = Instant::now();
+            let due: Vec<Hash> = self
+                .missing_lenses
+                .iter()
+                .filter(|(_, missing)| missing.next.map(|next| next <= now).unwrap_or_default())
+                .map(|(schema, _)| *schema)
+                .collect();
+            for schema in due {
+                self.retry_lenses(schema);
+                cx.waker().wake_by_ref();
+            }
+            self.reset_lenses_timer();
+        }
+    }
+
     fn poll_dial(
         &mut self,
-        _cx: &mut Context,
+        cx: &mut Context,
         _params: &mut impl PollParameters,
     ) -> Poll<
         NetworkBehaviourAction<
@@ -344,6 +1377,9 @@ impl Behaviour {
             <Self as NetworkBehaviour>::ProtocolsHandler,
         >,
     > {
+        self.poll_anti_entropy(cx);
+        self.poll_lenses(cx);
+        self.poll_rendezvous(cx);
         if let Some(peer) = self.dial.pop_front() {
             Poll::Ready(NetworkBehaviourAction::Dial {
                 opts: DialOpts::peer_id(peer.to_libp2p().to_peer_id())
@@ -351,6 +1387,14 @@ impl Behaviour {
                     .build(),
                 handler: self.new_handler(),
             })
+        } else if let Some((peer, addr)) = self.dial_addr.pop_front() {
+            Poll::Ready(NetworkBehaviourAction::Dial {
+                opts: DialOpts::peer_id(peer)
+                    .addresses(vec![addr])
+                    .condition(PeerCondition::Disconnected)
+                    .build(),
+                handler: self.new_handler(),
+            })
         } else {
             Poll::Pending
         }
@@ -418,10 +1462,65 @@ impl NetworkBehaviourEventProcess<RequestResponseEvent> for Behaviour {
                         }
                         SyncRequest::Unjoin(doc, ctx) => {
                             let peer = unwrap!(libp2p_peer_id(&peer));
-                            let schema =
-                                unwrap!(self.backend.frontend().schema(doc)).as_ref().hash();
-                            let causal = unwrap!(self.backend.unjoin(&peer, doc, ctx));
-                            let resp = SyncResponse::Unjoin(schema.into(), causal);
+                            let resp = unwrap!(offline::unjoin(&mut self.backend, &peer, doc, ctx));
+                            let resp = Ref::archive(&resp);
+                            let received = request.as_bytes().len() as u64;
+                            let sent = resp.as_bytes().len() as u64;
+                            self.update_status(doc, &peer, |status| {
+                                status.bytes_received += received;
+                                status.bytes_sent += sent;
+                            });
+                            self.req.send_response(channel, resp).ok();
+                        }
+                        SyncRequest::Redeem(doc, schema, causal) => {
+                            let peer = unwrap!(libp2p_peer_id(&peer));
+                            let schema = Hash::from(*schema);
+                            let causal: Causal = unwrap!(causal.deserialize(&mut rkyv::Infallible));
+                            // only the redemption statement is joined
+                            let redemption = match causal.redemption() {
+                                Some(redemption) => redemption,
+                                None => {
+                                    tracing::error!("invalid redeem request from {}", peer);
+                                    return;
+                                }
+                            };
+                            unwrap!(self.inject_causal(peer, *doc, schema, causal));
+                            let accepted =
+                                self.backend.frontend().accept_redemption(doc, redemption);
+                            match accepted {
+                                Ok(causal) => unwrap!(self.broadcast(doc, causal)),
+                                Err(err) => tracing::info!("redemption not accepted: {}", err),
+                            }
+                            let resp = SyncResponse::Redeem;
+                            let resp = Ref::archive(&resp);
+                            self.req.send_response(channel, resp).ok();
+                        }
+                        SyncRequest::Pair(token, proof) => {
+                            let device = unwrap!(libp2p_peer_id(&peer));
+                            let token = PeerId::new(*token);
+                            // the pairing secret signs the device key
+                            let root = match self.pairings.get(&token) {
+                                Some(root)
+                                    if token.to_libp2p().verify(device.as_ref(), &proof[..]) =>
+                                {
+                                    *root
+                                }
+                                _ => {
+                                    tracing::error!("invalid pairing request from {}", device);
+                                    return;
+                                }
+                            };
+                            self.pairings.remove(&token);
+                            let frontend = self.backend.frontend();
+                            let statements = unwrap!(frontend.link_device(&root, &device));
+                            let mut docs = vec![];
+                            for (doc, causal) in statements {
+                                let schema =
+                                    unwrap!(frontend.schema(&doc)).as_ref().name().to_string();
+                                unwrap!(self.broadcast(&doc, causal));
+                                docs.push(PairedDoc { doc, schema });
+                            }
+                            let resp = SyncResponse::Pair(docs);
                             let resp = Ref::archive(&resp);
                             self.req.send_response(channel, resp).ok();
                         }
@@ -436,19 +1535,17 @@ impl NetworkBehaviourEventProcess<RequestResponseEvent> for Behaviour {
                     match response.as_ref() {
                         Invite => {}
                         Lenses(lenses) => {
-                            let schema2 = unwrap!(self.backend.registry().register(lenses));
-                            self.buffer.retain(|(schema, doc, peer, causal)| {
-                                if *schema == schema2 {
-                                    if let Err(err) =
-                                        self.backend.join(peer, doc, schema, causal.clone())
-                                    {
-                                        tracing::error!("{}", err);
-                                    }
-                                    false
-                                } else {
-                                    true
+                            let schema = self.lenses_req.remove(&request_id);
+                            if let Err(err) = self.backend.registry().register(lenses) {
+                                tracing::error!("{}", err);
+                            }
+                            match schema {
+                                // the peer sent lenses of a different schema
+                                Some(schema) if !self.backend.registry().contains(&schema) => {
+                                    unwrap!(self.lenses_failed(schema));
                                 }
-                            });
+                                _ => unwrap!(self.join_buffered()),
+                            }
                         }
                         Unjoin(schema, causal) => {
                             let schema = Hash::from(*schema);
@@ -458,18 +1555,195 @@ impl NetworkBehaviourEventProcess<RequestResponseEvent> for Behaviour {
                                 anyhow::anyhow!("received response without request")
                             });
                             let doc = unwrap!(res);
+                            let repaired = paths(&causal);
+                            if self.anti_entropy.untrack_full(&request_id) {
+                                self.anti_entropy.repaired(&doc, repaired);
+                            }
+                            let received = response.as_bytes().len();
+                            self.response_received(&doc, &peer, received, repaired);
                             unwrap!(self.inject_causal(peer, doc, schema, causal));
                         }
+                        Redeem => {
+                            let peer = unwrap!(libp2p_peer_id(&peer));
+                            let res = self.redeem_req.remove(&request_id).ok_or_else(|| {
+                                anyhow::anyhow!("received response without request")
+                            });
+                            let doc = unwrap!(res);
+                            unwrap!(self.request_unjoin(&peer, doc));
+                        }
+                        Pair(docs) => {
+                            let peer = unwrap!(libp2p_peer_id(&peer));
+                            let res = self.pair_req.remove(&request_id).ok_or_else(|| {
+                                anyhow::anyhow!("received response without request")
+                            });
+                            let device = unwrap!(res);
+                            for paired in docs.iter() {
+                                if !unwrap!(self.backend.contains(&paired.doc)) {
+                                    unwrap!(self.backend.frontend().add_doc(
+                                        paired.doc,
+                                        &device,
+                                        &paired.schema
+                                    ));
+                                }
+                                self.subscribe(&paired.doc);
+                                unwrap!(self.request_unjoin(&peer, paired.doc));
+                            }
+                        }
                     }
                 }
             },
             OutboundFailure {
-                peer: _,
+                peer,
                 request_id,
                 error,
             } => {
-                self.unjoin_req.remove(&request_id);
+                self.anti_entropy.untrack_full(&request_id);
+                if let Some(doc) = self.unjoin_req.remove(&request_id) {
+                    let peer = unwrap!(libp2p_peer_id(&peer));
+                    self.request_failed(&doc, &peer, error.to_string());
+                }
+                self.redeem_req.remove(&request_id);
+                self.pair_req.remove(&request_id);
                 tracing::error!("{}", error);
+                if let Some(schema) = self.lenses_req.remove(&request_id) {
+                    unwrap!(self.lenses_failed(schema));
+                }
+            }
+            InboundFailure {
+                peer: _,
+                request_id: _,
+                error,
+            } => {
+                tracing::error!("{}", error);
+            }
+            ResponseSent {
+                peer: _,
+                request_id: _,
+            } => {}
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<ReconcileEvent> for Behaviour {
+    fn inject_event(&mut self, ev: ReconcileEvent) {
+        use request_response::{RequestResponseEvent::*, RequestResponseMessage::*};
+        match ev {
+            Message { peer, message } => match message {
+                Request {
+                    request_id: _,
+                    request,
+                    channel,
+                } => {
+                    let peer = unwrap!(libp2p_peer_id(&peer));
+                    let (doc, resp) = match request.as_ref() {
+                        ArchivedReconcileRequest::Sketches(doc, sketches) => {
+                            let schema =
+                                unwrap!(self.backend.frontend().schema(doc)).as_ref().hash();
+                            if let Some(in_sync) =
+                                unwrap!(self.backend.frontend().is_synced(&peer, doc, sketches))
+                            {
+                                self.update_status(doc, &peer, |status| status.in_sync = in_sync);
+                            }
+                            let (causal, pending, split) =
+                                unwrap!(self.backend.reconcile(&peer, doc, sketches));
+                            let resp =
+                                ReconcileResponse::Sketches(schema.into(), causal, pending, split);
+                            (doc, resp)
+                        }
+                        ArchivedReconcileRequest::Chunk(doc, chunk) => {
+                            let schema =
+                                unwrap!(self.backend.frontend().schema(doc)).as_ref().hash();
+                            let causal = unwrap!(self.backend.unjoin_chunk(&peer, doc, chunk));
+                            (doc, ReconcileResponse::Chunk(schema.into(), causal))
+                        }
+                    };
+                    let resp = Ref::archive(&resp);
+                    let received = request.as_bytes().len() as u64;
+                    let sent = resp.as_bytes().len() as u64;
+                    self.update_status(doc, &peer, |status| {
+                        status.bytes_received += received;
+                        status.bytes_sent += sent;
+                    });
+                    self.reconcile.send_response(channel, resp).ok();
+                }
+                Response {
+                    request_id,
+                    response,
+                } => {
+                    let peer = unwrap!(libp2p_peer_id(&peer));
+                    let res = self
+                        .reconcile_req
+                        .remove(&request_id)
+                        .or_else(|| self.chunk_req.remove(&request_id).map(|(doc, _)| doc))
+                        .ok_or_else(|| anyhow::anyhow!("received response without request"));
+                    let doc = unwrap!(res);
+                    let round = self.anti_entropy.untrack(&request_id);
+                    let received = response.as_bytes().len();
+                    match unwrap!(response.to_owned()) {
+                        ReconcileResponse::Sketches(schema, causal, pending, split) => {
+                            let repaired = paths(&causal);
+                            if round {
+                                self.anti_entropy.repaired(&doc, repaired);
+                            }
+                            self.response_received(&doc, &peer, received, repaired);
+                            unwrap!(self.inject_causal(peer, doc, schema.into(), causal));
+                            if !pending.is_empty() {
+                                self.pending.entry(doc).or_default().extend(pending);
+                            }
+                            if !split.is_empty() {
+                                let id = unwrap!(self.request_reconcile(&peer, doc, &split));
+                                if round {
+                                    self.anti_entropy.track(id);
+                                }
+                            }
+                        }
+                        ReconcileResponse::Chunk(schema, causal) => {
+                            let repaired = paths(&causal);
+                            if round {
+                                self.anti_entropy.repaired(&doc, repaired);
+                            }
+                            self.response_received(&doc, &peer, received, repaired);
+                            unwrap!(self.inject_causal(peer, doc, schema.into(), causal));
+                        }
+                    }
+                    if let Some(id) = unwrap!(self.request_chunk(&peer, doc)) {
+                        if round {
+                            self.anti_entropy.track(id);
+                        }
+                    }
+                }
+            },
+            OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                let peer = unwrap!(libp2p_peer_id(&peer));
+                let round = self.anti_entropy.untrack(&request_id);
+                if let Some((doc, chunk)) = self.chunk_req.remove(&request_id) {
+                    // the chunk is requested again when the document is synced next
+                    tracing::debug!("chunk of {} failed: {}", doc, error);
+                    self.pending.entry(doc).or_default().extend(chunk);
+                    self.request_failed(&doc, &peer, error.to_string());
+                    return;
+                }
+                let doc = self.reconcile_req.remove(&request_id);
+                match (doc, error) {
+                    (Some(doc), request_response::OutboundFailure::UnsupportedProtocols) => {
+                        self.update_status(&doc, &peer, |status| {
+                            status.outstanding = status.outstanding.saturating_sub(1)
+                        });
+                        let id = unwrap!(self.request_full_unjoin(&peer, doc));
+                        if round {
+                            self.anti_entropy.track_full(id);
+                        }
+                    }
+                    (Some(doc), error) => {
+                        tracing::error!("{}", error);
+                        self.request_failed(&doc, &peer, error.to_string());
+                    }
+                    (None, error) => tracing::error!("{}", error),
+                }
             }
             InboundFailure {
                 peer: _,
@@ -490,6 +1764,59 @@ impl NetworkBehaviourEventProcess<ping::Event> for Behaviour {
     fn inject_event(&mut self, _event: ping::Event) {}
 }
 
+impl NetworkBehaviourEventProcess<()> for Behaviour {
+    fn inject_event(&mut self, _event: ()) {}
+}
+
+impl NetworkBehaviourEventProcess<rendezvous::client::Event> for Behaviour {
+    fn inject_event(&mut self, event: rendezvous::client::Event) {
+        use rendezvous::client::Event::*;
+        match event {
+            Discovered {
+                rendezvous_node,
+                registrations,
+                cookie,
+            } => {
+                if let Some(namespace) = cookie.namespace() {
+                    let key = (rendezvous_node, namespace.clone());
+                    self.rendezvous_cookies.insert(key, cookie);
+                }
+                for registration in registrations {
+                    let peer = registration.record.peer_id();
+                    if peer == self.local_peer {
+                        continue;
+                    }
+                    for addr in registration.record.addresses() {
+                        self.req.add_address(&peer, addr.clone());
+                    }
+                    if let Ok(peer) = libp2p_peer_id(&peer) {
+                        tracing::info!("dialing discovered peer {}", peer);
+                        self.dial.push_back(peer);
+                    }
+                }
+            }
+            DiscoverFailed {
+                rendezvous_node,
+                error,
+                ..
+            } => {
+                tracing::error!("discovery at {} failed: {:?}", rendezvous_node, error);
+            }
+            Registered {
+                rendezvous_node,
+                namespace,
+                ..
+            } => {
+                tracing::debug!("registered {} at {}", namespace, rendezvous_node);
+            }
+            RegisterFailed(err) => {
+                tracing::error!("{}", err);
+            }
+            Expired { .. } => {}
+        }
+    }
+}
+
 #[cfg(not(target_family = "wasm"))]
 impl NetworkBehaviourEventProcess<mdns::MdnsEvent> for Behaviour {
     fn inject_event(&mut self, event: mdns::MdnsEvent) {
@@ -559,3 +1886,85 @@ pub fn libp2p_peer_id(peer_id: &libp2p::PeerId) -> Result<PeerId> {
         Err(err) => bail!(err),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(jitter: f64) -> AntiEntropyConfig {
+        AntiEntropyConfig {
+            interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(40),
+            jitter,
+            peers: 2,
+        }
+    }
+
+    /// Runs the round of a document when it is due and returns the next interval.
+    fn round(anti_entropy: &mut AntiEntropy, doc: &DocId) -> Duration {
+        let now = anti_entropy.docs[doc].next;
+        assert!(anti_entropy.due(now - Duration::from_millis(1)).is_empty());
+        assert_eq!(anti_entropy.due(now), vec![*doc]);
+        anti_entropy.docs[doc].next - now
+    }
+
+    #[test]
+    fn test_jitter() {
+        let interval = Duration::from_secs(10);
+        assert_eq!(jitter(&config(0.0), interval), interval);
+        for _ in 0..100 {
+            let d = jitter(&config(0.5), interval);
+            assert!(d >= interval && d <= Duration::from_secs(15));
+            let d = jitter(&config(2.0), interval);
+            assert!(d >= interval && d <= Duration::from_secs(20));
+        }
+    }
+
+    #[test]
+    fn test_anti_entropy_backoff() {
+        let mut anti_entropy = AntiEntropy::new(config(0.0));
+        let doc = DocId::new([0; 32]);
+        anti_entropy.add(doc);
+        assert_eq!(round(&mut anti_entropy, &doc), Duration::from_secs(10));
+        assert_eq!(round(&mut anti_entropy, &doc), Duration::from_secs(20));
+        assert_eq!(round(&mut anti_entropy, &doc), Duration::from_secs(40));
+        assert_eq!(round(&mut anti_entropy, &doc), Duration::from_secs(40));
+        anti_entropy.repaired(&doc, 1);
+        assert_eq!(round(&mut anti_entropy, &doc), Duration::from_secs(10));
+        assert_eq!(round(&mut anti_entropy, &doc), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn test_anti_entropy_stats() {
+        let mut anti_entropy = AntiEntropy::new(config(0.2));
+        let doc = DocId::new([0; 32]);
+        let other = DocId::new([1; 32]);
+        anti_entropy.add(doc);
+        assert_eq!(anti_entropy.stats(&doc), Some(AntiEntropyStats::default()));
+        assert_eq!(anti_entropy.stats(&other), None);
+
+        round(&mut anti_entropy, &doc);
+        anti_entropy.repaired(&doc, 2);
+        anti_entropy.repaired(&doc, 3);
+        anti_entropy.repaired(&other, 4);
+        let stats = anti_entropy.stats(&doc).unwrap();
+        assert_eq!(stats.rounds, 1);
+        assert_eq!(stats.repaired, 5);
+        assert_eq!(stats.last_repaired, 0);
+
+        round(&mut anti_entropy, &doc);
+        let stats = anti_entropy.stats(&doc).unwrap();
+        assert_eq!(stats.rounds, 2);
+        assert_eq!(stats.repaired, 5);
+        assert_eq!(stats.last_repaired, 5);
+
+        round(&mut anti_entropy, &doc);
+        let stats = anti_entropy.stats(&doc).unwrap();
+        assert_eq!(stats.rounds, 3);
+        assert_eq!(stats.repaired, 5);
+        assert_eq!(stats.last_repaired, 0);
+
+        anti_entropy.remove(&doc);
+        assert_eq!(anti_entropy.stats(&doc), None);
+    }
+}
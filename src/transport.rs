@@ -1,66 +1,108 @@
+use crate::NetworkConfig;
 use anyhow::Result;
 use libp2p::{
     core::{muxing::StreamMuxerBox, transport::Boxed},
-    identity, PeerId,
+    identity,
+    relay::v1::Relay,
+    PeerId,
 };
 
-pub fn transport(keypair: identity::Keypair) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
+/// Returns the transport and the relay behaviour. The relay behaviour is only returned
+/// when circuit relay servers are configured.
+pub fn transport(
+    keypair: identity::Keypair,
+    config: &NetworkConfig,
+) -> Result<(Boxed<(PeerId, StreamMuxerBox)>, Option<Relay>)> {
     #[cfg(target_arch = "wasm32")]
-    return wasm_transport(keypair);
+    return wasm_transport(keypair, config);
     #[cfg(not(target_arch = "wasm32"))]
-    return native_transport(keypair);
+    return native_transport(keypair, config);
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn native_transport(keypair: identity::Keypair) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
+fn native_transport(
+    keypair: identity::Keypair,
+    config: &NetworkConfig,
+) -> Result<(Boxed<(PeerId, StreamMuxerBox)>, Option<Relay>)> {
     use std::time::Duration;
 
     use libp2p::{
         core::{self, upgrade::Version},
         dns::{ResolverConfig, TokioDnsConfig},
         noise::{self, NoiseConfig, X25519Spec},
+        relay::v1::{new_transport_and_behaviour, RelayConfig},
         tcp::TcpConfig,
         yamux::YamuxConfig,
         Transport,
     };
     use libp2p_webrtc::WebRtcTransport;
 
+    use crate::DnsResolver;
+
     let peer_id = PeerId::from(keypair.public());
-    let webrtc = WebRtcTransport::new(peer_id, vec!["stun:stun.l.google.com:19302"]);
+    let ice_servers = config
+        .ice_servers
+        .iter()
+        .map(|url| url.as_str())
+        .collect::<Vec<_>>();
+    let webrtc = WebRtcTransport::new(peer_id, ice_servers);
     let tcp = TcpConfig::new().nodelay(true);
-    let dns = TokioDnsConfig::custom(tcp, ResolverConfig::cloudflare(), Default::default())?;
+    let resolver = match config.dns {
+        DnsResolver::System => None,
+        DnsResolver::Cloudflare => Some(ResolverConfig::cloudflare()),
+        DnsResolver::Google => Some(ResolverConfig::google()),
+        DnsResolver::Quad9 => Some(ResolverConfig::quad9()),
+    };
+    let dns = if let Some(resolver) = resolver {
+        TokioDnsConfig::custom(tcp, resolver, Default::default())?
+    } else {
+        TokioDnsConfig::system(tcp)?
+    };
+    let (dns, relay) = new_transport_and_behaviour(RelayConfig::default(), dns);
     let transport = core::transport::OrTransport::new(webrtc, dns);
     let key = noise::Keypair::<X25519Spec>::new().into_authentic(&keypair)?;
-    Ok(transport
+    let transport = transport
         .upgrade(Version::V1)
         .authenticate(NoiseConfig::xx(key).into_authenticated())
         .multiplex(YamuxConfig::default())
         .timeout(Duration::from_secs(20))
-        .boxed())
+        .boxed();
+    Ok((transport, (!config.relays.is_empty()).then(|| relay)))
 }
 
 #[cfg(target_arch = "wasm32")]
-fn wasm_transport(identity: identity::Keypair) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
+fn wasm_transport(
+    identity: identity::Keypair,
+    config: &NetworkConfig,
+) -> Result<(Boxed<(PeerId, StreamMuxerBox)>, Option<Relay>)> {
     use std::time::Duration;
 
     use libp2p::{
         core::{self, transport::upgrade},
         noise,
+        relay::v1::{new_transport_and_behaviour, RelayConfig},
         wasm_ext::{ffi, ExtTransport},
         yamux, Transport,
     };
     use libp2p_webrtc::WebRtcTransport;
 
     let peer_id = PeerId::from(identity.public());
-    let webrtc = WebRtcTransport::new(peer_id, vec!["stun:stun.l.google.com:19302"]);
+    let ice_servers = config
+        .ice_servers
+        .iter()
+        .map(|url| url.as_str())
+        .collect::<Vec<_>>();
+    let webrtc = WebRtcTransport::new(peer_id, ice_servers);
     let ws = ExtTransport::new(ffi::websocket_transport());
+    let (ws, relay) = new_transport_and_behaviour(RelayConfig::default(), ws);
     let base = core::transport::OrTransport::new(webrtc, ws);
     let noise_keys = noise::Keypair::<noise::X25519Spec>::new().into_authentic(&identity)?;
 
-    Ok(base
+    let transport = base
         .upgrade(upgrade::Version::V1Lazy)
         .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
         .multiplex(yamux::YamuxConfig::default())
         .timeout(Duration::from_secs(20))
-        .boxed())
+        .boxed();
+    Ok((transport, (!config.relays.is_empty()).then(|| relay)))
 }
@@ -4,16 +4,19 @@ use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 use std::path::Path;
-use tlfs_crdt::{Kind, Lens, Lenses, Package, PrimitiveKind, Ref, Schema};
+use tlfs_crdt::{Keypair, Kind, Lens, Lenses, Package, PrimitiveKind, Ref, Schema};
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 struct GrammarParser;
 
-pub fn compile<P: AsRef<Path>>(input: P, output: P) -> Result<()> {
+pub fn compile<P: AsRef<Path>>(input: P, output: P, keypair: Option<Keypair>) -> Result<()> {
     let input = std::fs::read(input)?;
     let input = std::str::from_utf8(&input)?;
-    let lenses = compile_lenses(input)?;
+    let mut lenses = compile_lenses(input)?;
+    if let Some(keypair) = keypair {
+        sign_packages(&mut lenses, keypair);
+    }
     let lenses = Ref::archive(&lenses);
     std::fs::write(output, lenses.as_bytes())?;
     Ok(())
@@ -32,6 +35,12 @@ pub fn compile_lenses(input: &str) -> Result<Vec<Package>> {
     Ok(interpreter.into_packages())
 }
 
+pub fn sign_packages(packages: &mut [Package], keypair: Keypair) {
+    for package in packages {
+        package.sign(keypair);
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Interpreter {
     name: Option<String>,
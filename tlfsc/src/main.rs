@@ -1,6 +1,7 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
 use std::path::PathBuf;
+use tlfs_crdt::Keypair;
 
 #[derive(Parser)]
 struct Cli {
@@ -8,10 +9,23 @@ struct Cli {
     input: PathBuf,
     #[clap(short, long)]
     output: PathBuf,
+    /// File containing the 32 byte ed25519 secret of the publisher.
+    #[clap(short, long)]
+    key: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    tlfsc::compile(&cli.input, &cli.output)?;
+    let keypair = if let Some(key) = cli.key {
+        let secret: [u8; 32] = std::fs::read(key)?
+            .try_into()
+            .map_err(|_| anyhow!("expected a 32 byte secret"))?;
+        let keypair = Keypair::new(secret);
+        println!("publisher {}", keypair.peer_id());
+        Some(keypair)
+    } else {
+        None
+    };
+    tlfsc::compile(&cli.input, &cli.output, keypair)?;
     Ok(())
 }